@@ -1,4 +1,48 @@
+use crate::bigint::BigInt;
+use crate::chan::Chan;
+use crate::closure::Closure;
+use crate::heap::Pointer;
+use crate::lexer::Location;
+use crate::map::Map;
+use crate::slice::Slice;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// Represents a range of source text, used to point errors at the code causing them.
+///
+/// Spans don't participate in equality: two expressions that differ only by
+/// where they were written compare as equal.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Span {
+    /// The byte offset where this span starts
+    pub start: usize,
+    /// The byte offset just after the end of this span
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: Location, end: Location) -> Self {
+        Span {
+            start: start.0,
+            end: end.0,
+        }
+    }
+
+    /// Find the line and column (both starting at 1) where this span starts
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        (line, col)
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Span) -> bool {
+        true
+    }
+}
 
 /// Represents a litteral value in the language
 ///
@@ -9,13 +53,60 @@ pub enum Litteral {
     Str(String),
     /// Represents an integer litteral, like `333`.
     ///
-    /// Right now all string litterals are 64 bit signed integers,
-    /// but we might want litterals to be big nums at some point.
+    /// Integer litterals are 64 bit signed integers, unless they're too big
+    /// to fit, in which case they become a `BigInt` instead.
     I64(i64),
+    /// An arbitrary precision integer, like `123456789012345678901234567890`
+    BigInt(BigInt),
     /// A boolean litteral
     Bool(bool),
+    /// A single unicode character, like `'a'`
+    Rune(char),
+    /// A single byte, like the ones making up a string
+    U8(u8),
     /// A tuple, like (1, 2)
     Tuple(Vec<Litteral>),
+    /// A slice of values with the same type, like [1, 2]
+    Slice(Slice),
+    /// A map from keys to values, like {"a": 1}
+    Map(Map),
+    /// An optional value, which is either Some(value) or None
+    Option(Option<Box<Litteral>>),
+    /// The result of an operation that can fail, which is either Ok(value) or Err(error)
+    Result(Result<Box<Litteral>, Box<Litteral>>),
+    /// A function, like `func(x I64) I64 { x + 1 }`, or the name of a top level function
+    Function(Rc<Closure>),
+    /// A value wrapped in a newtype, like `UserId(5)`
+    Newtype(String, Box<Litteral>),
+    /// A channel, created with `chan(capacity)`
+    Chan(Chan),
+    /// A pointer to a value on the heap, created with `&v`
+    Pointer(Pointer),
+}
+
+impl Eq for Litteral {}
+
+// Only strings, integers, booleans, runes, bytes, and tuples or newtypes of those can be
+// used as the keys of a map. Other litterals hash by their kind alone, which
+// is still consistent with equality.
+impl Hash for Litteral {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Litteral::Str(s) => s.hash(state),
+            Litteral::I64(i) => i.hash(state),
+            Litteral::BigInt(i) => i.hash(state),
+            Litteral::Bool(b) => b.hash(state),
+            Litteral::Rune(c) => c.hash(state),
+            Litteral::U8(b) => b.hash(state),
+            Litteral::Tuple(litterals) => litterals.hash(state),
+            Litteral::Newtype(name, l) => {
+                name.hash(state);
+                l.hash(state)
+            }
+            _ => {}
+        }
+    }
 }
 
 impl fmt::Display for Litteral {
@@ -23,7 +114,20 @@ impl fmt::Display for Litteral {
         match self {
             Litteral::Str(s) => write!(f, "{}", s),
             Litteral::I64(i) => write!(f, "{}", i),
+            Litteral::BigInt(i) => write!(f, "{}", i),
             Litteral::Bool(b) => write!(f, "{}", b),
+            Litteral::Rune(c) => write!(f, "{}", c),
+            Litteral::U8(b) => write!(f, "{}", b),
+            Litteral::Slice(s) => write!(f, "{}", s),
+            Litteral::Map(m) => write!(f, "{}", m),
+            Litteral::Option(Some(l)) => write!(f, "Some({})", l),
+            Litteral::Option(None) => write!(f, "None"),
+            Litteral::Result(Ok(l)) => write!(f, "Ok({})", l),
+            Litteral::Result(Err(l)) => write!(f, "Err({})", l),
+            Litteral::Function(c) => write!(f, "{}", c),
+            Litteral::Newtype(name, l) => write!(f, "{}({})", name, l),
+            Litteral::Chan(c) => write!(f, "{}", c),
+            Litteral::Pointer(p) => write!(f, "{}", p),
             // This code is complicated because we want to print single tuples like (1,)
             Litteral::Tuple(litterals) => {
                 write!(f, "(")?;
@@ -73,6 +177,18 @@ pub enum Op {
     Div,
     /// Modulo
     Mod,
+    /// Bitwise and, with &
+    BitAnd,
+    /// Bitwise or, with |
+    BitOr,
+    /// Bitwise exclusive or, with ^
+    BitXor,
+    /// Clearing the bits set on the right, with &^
+    AndNot,
+    /// Shifting bits to the left, with <<
+    Shl,
+    /// Shifting bits to the right, keeping the sign, with >>
+    Shr,
 }
 
 /// Represents a short circuiting operation between booleans.
@@ -96,27 +212,182 @@ pub enum BoolOp {
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
     /// Call a function by name, with arguments expression
-    Call(String, Vec<Expr>),
+    Call(String, Vec<Expr>, Span),
+    /// Call the function an expression evaluates to, like `make_adder(1)(2)`
+    CallValue(Box<Expr>, Vec<Expr>, Span),
+    /// An anonymous function, like `func(x I64) I64 { x + 1 }`
+    Lambda(Vec<(String, TypeName)>, Option<TypeName>, Vec<Expr>, Locals),
     /// Represents the use of a litteral as an expression
     Litt(Litteral),
     /// A variable declaration, like `x := 2`
-    Declare(String, Box<Expr>),
+    Declare(Var, Box<Expr>),
+    /// Declaring a variable that can't be assigned to later, like `x :: 2`
+    DeclareImmutable(Var, Box<Expr>),
+    /// A declaration destructuring a tuple, like `(a, _) := pair`
+    DeclarePattern(Pattern, Box<Expr>, Span),
     /// A variable assignment, like `x = 3`
-    Assign(String, Box<Expr>),
+    Assign(Var, Box<Expr>),
+    /// Updating a variable with a binary operation, like `x += 3`
+    AssignOp(Op, Var, Box<Expr>, Span),
     /// A block of expressions
     Block(Vec<Expr>),
     /// A binary operation between two arguments
-    BinOp(Op, Box<Expr>, Box<Expr>),
+    BinOp(Op, Box<Expr>, Box<Expr>, Span),
     /// A conditional short circuiting operation between two arguments
     ConditionalOp(BoolOp, Box<Expr>, Box<Expr>),
     /// An if else expresion, like `if y { 3 } else { 4 }`
     IfElse(Box<Expr>, Vec<Expr>, Vec<Expr>),
     /// The unary negation operator !
     Not(Box<Expr>),
+    /// The unary minus operator, like `-x`
+    Neg(Box<Expr>, Span),
     /// The tuple constructor
     MakeTuple(Vec<Expr>),
+    /// The slice constructor, like `[1, 2, 3]`
+    MakeSlice(Vec<Expr>),
+    /// The map constructor, like `{"a": 1, "b": 2}`, or `{:}` for an empty map
+    MakeMap(Vec<(Expr, Expr)>),
+    /// Indexing into a slice or a map, like `xs[i]`
+    Index(Box<Expr>, Box<Expr>, Span),
+    /// Taking part of a slice, like `xs[a:b]`, where both ends are optional
+    Subslice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>, Span),
+    /// Assigning to an index of a slice or a map, like `xs[i] = 3`
+    AssignIndex(Box<Expr>, Box<Expr>, Box<Expr>, Span),
+    /// Reading one of the elements of a tuple, like `pair.0`
+    Field(Box<Expr>, usize, Span),
+    /// Unwrap an Option or a Result, returning early from the current function
+    /// with None or Err instead, like `x?`
+    Try(Box<Expr>, Span),
+    /// Call a function when the enclosing function exits, like `defer close(f)`.
+    ///
+    /// The function and its arguments are evaluated right away, like in Go.
+    Defer(Box<Expr>, Span),
+    /// Call a function in a new task, like `go worker(jobs)`.
+    ///
+    /// Like with `defer`, the function and its arguments are evaluated right away.
+    Go(Box<Expr>, Span),
+    /// Send a value on a channel, like `ch <- 3`
+    Send(Box<Expr>, Box<Expr>, Span),
+    /// Receive a value from a channel, like `<-ch`, which is None once it's closed
+    Recv(Box<Expr>, Span),
+    /// Wait for the first of several channel operations that can happen,
+    /// with an optional block to run if none of them can happen right away
+    Select(Vec<SelectCase>, Option<Vec<Expr>>, Span),
+    /// Put a value on the heap, giving a pointer to it, like `&[]int{1}`.
+    ///
+    /// Unlike in Go, this can't take the address of a variable, since the
+    /// pointer would point to a copy of it rather than share it.
+    Ref(Box<Expr>, Span),
+    /// Read the value a pointer points to, like `*p`
+    Deref(Box<Expr>, Span),
+    /// Change the value a pointer points to, like `*p = 3`
+    AssignDeref(Box<Expr>, Box<Expr>, Span),
     /// A reference to a variable name
-    Name(String),
+    Name(Var),
+}
+
+/// Represents a variable, either where it's declared or where it's used
+#[derive(Clone, Debug, PartialEq)]
+pub struct Var {
+    pub name: String,
+    /// What the name refers to, once the resolver has run
+    pub binding: Binding,
+    pub span: Span,
+}
+
+impl Var {
+    pub fn new(name: String, span: Span) -> Self {
+        Var {
+            name,
+            binding: Binding::Unresolved,
+            span,
+        }
+    }
+}
+
+impl From<String> for Var {
+    fn from(name: String) -> Self {
+        Var::new(name, Span::default())
+    }
+}
+
+impl From<&str> for Var {
+    fn from(name: &str) -> Self {
+        Var::from(String::from(name))
+    }
+}
+
+/// Represents what a name refers to.
+///
+/// Local variables live in numbered slots of the function using them, so
+/// nothing needs to look them up by name while running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    /// The parser leaves every name like this, until the resolver runs
+    Unresolved,
+    /// A slot of the function using this variable, starting with its arguments
+    Local(usize),
+    /// One of the variables an anonymous function captured, by position
+    Captured(usize),
+    /// A constant, a builtin constant, or a top level function
+    Global,
+}
+
+/// Represents what the resolver found out about the variables of a function
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Locals {
+    /// How many slots the local variables need, including the arguments
+    pub slots: usize,
+    /// The variables of the enclosing function an anonymous function captures,
+    /// resolved where the anonymous function is created
+    pub captures: Vec<Var>,
+}
+
+/// Represents one of the cases of a select, like `case x := <-ch { ... }`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectCase {
+    pub op: SelectOp,
+    /// The expressions to run if this case is chosen
+    pub body: Vec<Expr>,
+    pub span: Span,
+}
+
+/// Represents the channel operation a select case waits for
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectOp {
+    /// Receive from a channel, declaring a variable holding what was received
+    Recv(Option<Var>, Expr),
+    /// Send a value on a channel
+    Send(Expr, Expr),
+}
+
+/// Represents the names being declared when destructuring a tuple
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Bind a value to a name
+    Name(Var),
+    /// Ignore a value, with `_`
+    Ignore,
+    /// Destructure a tuple, matching each element with a pattern
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Interpret an expression as a pattern, if it's made only of names and tuples.
+    ///
+    /// Patterns look just like tuple expressions, so the parser reads them
+    /// that way first.
+    pub fn from_expr(expr: &Expr) -> Option<Pattern> {
+        match expr {
+            Expr::Name(var) if var.name == "_" => Some(Pattern::Ignore),
+            Expr::Name(var) => Some(Pattern::Name(var.clone())),
+            Expr::MakeTuple(exprs) => {
+                let patterns: Option<Vec<Pattern>> = exprs.iter().map(Pattern::from_expr).collect();
+                Some(Pattern::Tuple(patterns?))
+            }
+            _ => None,
+        }
+    }
 }
 
 /// Instead of being a type itself, this is just a syntactic reference to a type
@@ -126,13 +397,23 @@ pub enum TypeName {
     Name(String),
     /// A tuple of type names
     Tuple(Vec<TypeName>),
+    /// A slice of some type, like `[I64]`
+    Slice(Box<TypeName>),
+    /// A type taking other types as parameters, like `Map[String, I64]`
+    Apply(String, Vec<TypeName>),
+    /// The type of a function, like `func(I64) Bool`
+    Func(Vec<TypeName>, Option<Box<TypeName>>),
+    /// A pointer to a value of some type, like `*I64`
+    Pointer(Box<TypeName>),
 }
 
 /// Represents a function definition.
 ///
 /// Functions have a name, as well as some code to run when they're called.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Function {
+    /// Whether or not this function can be used by other modules, with `pub`
+    pub public: bool,
     /// The name we can use to call the function
     pub name: String,
     /// The named arguments this function accepts
@@ -141,17 +422,94 @@ pub struct Function {
     pub ret: Option<TypeName>,
     /// The series of expressions making up the body of this function
     pub body: Vec<Expr>,
+    pub locals: Locals,
 }
 
-/// Represents the full abstract syntax tree of an Iku program.
+/// Represents a constant, like `const LIMIT I64 = 10 * 1024`
+///
+/// The value of a constant is evaluated while type checking, so it can only
+/// be made of litterals, operators, and other constants.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Const {
+    /// Whether or not this constant can be used by other modules, with `pub`
+    pub public: bool,
+    pub name: String,
+    /// An optionally declared type
+    pub typ: Option<TypeName>,
+    pub value: Expr,
+    pub span: Span,
+}
+
+/// Represents declaring a new name for a type.
 ///
-/// At the moment, a program is synonymous with a single file. This will
-/// probably change at some point.
+/// This is either an alias, like `type Meters = I64`, which can be used
+/// interchangeably with the type it names, or a newtype, like `newtype UserId I64`,
+/// which is a distinct type wrapping another one.
 #[derive(Clone, Debug, PartialEq)]
+pub struct TypeDecl {
+    /// Whether or not this type can be used by other modules, with `pub`
+    pub public: bool,
+    pub name: String,
+    /// Whether this declares a newtype, rather than an alias
+    pub newtype: bool,
+    /// The type being named, or wrapped by a newtype
+    pub typ: TypeName,
+    pub span: Span,
+}
+
+/// Represents something declared at the top level of a file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    Function(Function),
+    Const(Const),
+    Type(TypeDecl),
+}
+
+/// Represents importing another module, like `import "mathx"`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Import {
+    /// The path of the module, relative to the root of the project
+    pub path: String,
+    pub span: Span,
+}
+
+impl Import {
+    /// The name used to refer to the functions of this module, like `mathx` in `mathx.gcd`
+    pub fn alias(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Represents the full abstract syntax tree of an Iku program.
+///
+/// Each file is parsed into its own AST. Once the modules they import have
+/// been loaded, they're linked together into a single AST, without imports.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct AST {
+    pub imports: Vec<Import>,
+    pub types: Vec<TypeDecl>,
+    pub consts: Vec<Const>,
     pub functions: Vec<Function>,
 }
 
+impl AST {
+    /// Create an AST out of the items in a file, in the order they were declared
+    pub fn from_items(imports: Vec<Import>, items: Vec<Item>) -> Self {
+        let mut ast = AST {
+            imports,
+            ..AST::default()
+        };
+        for item in items {
+            match item {
+                Item::Function(f) => ast.functions.push(f),
+                Item::Const(c) => ast.consts.push(c),
+                Item::Type(t) => ast.types.push(t),
+            }
+        }
+        ast
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
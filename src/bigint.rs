@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// Represents an arbitrary precision signed integer.
+///
+/// This is the value behind the `BigInt` type, for when 64 bits aren't enough.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    // Whether or not this number is strictly negative. Zero is never negative.
+    negative: bool,
+    // The magnitude of this number, in base 2^32, starting with the least
+    // significant digit. There are never any trailing zero digits.
+    digits: Vec<u32>,
+}
+
+// Remove the most significant zero digits in a magnitude
+fn trim(mut digits: Vec<u32>) -> Vec<u32> {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    digits
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut res = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, d) in long.iter().enumerate() {
+        let sum = u64::from(*d) + u64::from(*short.get(i).unwrap_or(&0)) + carry;
+        res.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        res.push(carry as u32);
+    }
+    res
+}
+
+// This assumes that a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, d) in a.iter().enumerate() {
+        let mut diff = i64::from(*d) - i64::from(*b.get(i).unwrap_or(&0)) - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        res.push(diff as u32);
+    }
+    trim(res)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let cur = u64::from(res[i + j]) + u64::from(*x) * u64::from(*y) + carry;
+            res[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        res[i + b.len()] = carry as u32;
+    }
+    trim(res)
+}
+
+// Divide a magnitude by a single digit, returning the quotient and remainder
+fn divrem_small(a: &[u32], b: u32) -> (Vec<u32>, u32) {
+    let mut quot = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for (i, d) in a.iter().enumerate().rev() {
+        let cur = (rem << 32) | u64::from(*d);
+        quot[i] = (cur / u64::from(b)) as u32;
+        rem = cur % u64::from(b);
+    }
+    (trim(quot), rem as u32)
+}
+
+// Divide two magnitudes, using simple binary long division.
+// This assumes that b isn't zero.
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let (quot, rem) = divrem_small(a, b[0]);
+        return (quot, trim(vec![rem]));
+    }
+    let mut quot = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        // rem = rem * 2 + the current bit of a
+        let bit = (a[i / 32] >> (i % 32)) & 1;
+        let mut carry = bit;
+        for d in rem.iter_mut() {
+            let next = *d >> 31;
+            *d = (*d << 1) | carry;
+            carry = next;
+        }
+        if carry > 0 {
+            rem.push(carry);
+        }
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quot[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(quot), rem)
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, digits: Vec<u32>) -> Self {
+        let digits = trim(digits);
+        let negative = negative && !digits.is_empty();
+        BigInt { negative, digits }
+    }
+
+    pub fn zero() -> Self {
+        BigInt::from_parts(false, Vec::new())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// How many base 2^32 digits make up the magnitude of this number
+    pub fn limbs(&self) -> usize {
+        self.digits.len()
+    }
+
+    /// Convert this number to an i64, if it fits
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let mut mag = 0u64;
+        for d in self.digits.iter().rev() {
+            mag = (mag << 32) | u64::from(*d);
+        }
+        if self.negative {
+            if mag <= 1 << 63 {
+                Some((mag as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else if mag < 1 << 63 {
+            Some(mag as i64)
+        } else {
+            None
+        }
+    }
+
+    /// Divide two numbers, rounding towards zero, like the division of i64.
+    ///
+    /// This returns None when dividing by zero.
+    pub fn checked_div(&self, other: &BigInt) -> Option<BigInt> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quot, _) = divrem_mag(&self.digits, &other.digits);
+        Some(BigInt::from_parts(self.negative != other.negative, quot))
+    }
+
+    /// Take the remainder of a division, which has the same sign as self, like
+    /// the remainder of i64.
+    ///
+    /// This returns None when dividing by zero.
+    pub fn checked_rem(&self, other: &BigInt) -> Option<BigInt> {
+        if other.is_zero() {
+            return None;
+        }
+        let (_, rem) = divrem_mag(&self.digits, &other.digits);
+        Some(BigInt::from_parts(self.negative, rem))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(i: i64) -> Self {
+        let mag = i.unsigned_abs();
+        BigInt::from_parts(i < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+}
+
+/// Represents a failure to parse a string as a BigInt
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseBigIntError;
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        if s.is_empty() {
+            return Err(ParseBigIntError);
+        }
+        let mut digits = Vec::new();
+        for c in s.chars() {
+            let d = c.to_digit(10).ok_or(ParseBigIntError)?;
+            digits = add_mag(&mul_mag(&digits, &[10]), &[d]);
+        }
+        Ok(BigInt::from_parts(negative, digits))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // We peel off 9 decimal digits at a time
+        let mut chunks = Vec::new();
+        let mut rest = self.digits.clone();
+        while !rest.is_empty() {
+            let (quot, rem) = divrem_small(&rest, 1_000_000_000);
+            chunks.push(rem);
+            rest = quot;
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut iter = chunks.iter().rev();
+        if let Some(first) = iter.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in iter {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.digits, &other.digits),
+            (true, true) => cmp_mag(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.digits.clone())
+    }
+}
+
+impl<'a> Add for &'a BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &'a BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_mag(&self.digits, &other.digits));
+        }
+        // The signs differ, so the result takes the sign of the bigger magnitude
+        match cmp_mag(&self.digits, &other.digits) {
+            Ordering::Less => {
+                BigInt::from_parts(other.negative, sub_mag(&other.digits, &self.digits))
+            }
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl<'a> Sub for &'a BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &'a BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl<'a> Mul for &'a BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &'a BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != other.negative,
+            mul_mag(&self.digits, &other.digits),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parsing_and_display_roundtrip() {
+        for s in &["0", "-1", "4294967296", "-123456789012345678901234567890"] {
+            assert_eq!(&format!("{}", big(s)), s);
+        }
+        assert_eq!(format!("{}", big("-0")), "0");
+        assert!("12a".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn arithmetic_matches_i64() {
+        let values = [0, 1, -1, 7, -7, 1 << 40, -(1 << 33) + 5, 123456789];
+        for &a in &values {
+            for &b in &values {
+                let (x, y) = (BigInt::from(a), BigInt::from(b));
+                assert_eq!((&x + &y).to_i64(), Some(a + b));
+                assert_eq!((&x - &y).to_i64(), Some(a - b));
+                assert_eq!(x.cmp(&y), a.cmp(&b));
+                if b != 0 {
+                    assert_eq!(x.checked_div(&y).unwrap().to_i64(), Some(a / b));
+                    assert_eq!(x.checked_rem(&y).unwrap().to_i64(), Some(a % b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn multiplication_and_division_of_large_numbers() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        let prod = &a * &b;
+        assert_eq!(
+            format!("{}", prod),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!(prod.checked_div(&b), Some(a.clone()));
+        assert_eq!(prod.checked_rem(&a), Some(BigInt::zero()));
+        assert_eq!(a.checked_div(&BigInt::zero()), None);
+    }
+
+    #[test]
+    fn conversion_to_i64_checks_bounds() {
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from(i64::MAX).to_i64(), Some(i64::MAX));
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+    }
+}
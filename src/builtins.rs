@@ -0,0 +1,370 @@
+use crate::ast::Litteral;
+use crate::bigint::BigInt;
+use crate::interpreter::InterpreterResult;
+use crate::slice::Slice;
+use crate::typer::{self, Type};
+use std::convert::TryFrom;
+
+type IntOp = fn(i64, i64) -> i64;
+
+// Builtins taking two integers and producing an integer that can't fail.
+//
+// These give explicit control over what happens on overflow, since the
+// standard operators fail instead.
+const INT_BINARY: [(&str, IntOp); 6] = [
+    ("wrapping_add", i64::wrapping_add),
+    ("wrapping_sub", i64::wrapping_sub),
+    ("wrapping_mul", i64::wrapping_mul),
+    ("saturating_add", i64::saturating_add),
+    ("saturating_sub", i64::saturating_sub),
+    ("saturating_mul", i64::saturating_mul),
+];
+
+fn find_int_binary(name: &str) -> Option<IntOp> {
+    INT_BINARY
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, f)| *f)
+}
+
+fn wrong_types<T>(name: &str, args: &[Type]) -> Option<typer::Result<T>> {
+    let message = format!("{} can't be called with types {:?}", name, args);
+    Some(Err(message.into()))
+}
+
+fn wrong_values<T>(name: &str, args: &[Litteral]) -> Option<InterpreterResult<T>> {
+    let message = format!("{} can't be called with {:?}", name, args);
+    Some(Err(message.into()))
+}
+
+/// Find the type of a builtin constant, like `None`.
+pub fn type_constant(name: &str) -> Option<Type> {
+    match name {
+        "None" => Some(Type::Option(Box::new(Type::Unknown))),
+        _ => None,
+    }
+}
+
+/// Find the value of a builtin constant, like `None`.
+pub fn constant(name: &str) -> Option<Litteral> {
+    match name {
+        "None" => Some(Litteral::Option(None)),
+        _ => None,
+    }
+}
+
+/// Check whether there's a builtin function with some name, whatever its arguments
+pub fn is_builtin(name: &str) -> bool {
+    type_call(name, &[]).is_some()
+}
+
+/// Find the type returned by a builtin function, given the types of its arguments.
+///
+/// This returns `None` if there's no builtin with that name.
+pub fn type_call(name: &str, args: &[Type]) -> Option<typer::Result<Type>> {
+    // Conversions between integer types are called like a function named
+    // after the type we're converting to. Runes and bytes convert to and from
+    // I64, and bytes can also become runes.
+    let conversion = match name {
+        "BigInt" => Some((Type::BigInt, &[Type::I64, Type::BigInt][..])),
+        "I64" => Some((Type::I64, &[Type::I64, Type::BigInt, Type::Rune, Type::U8][..])),
+        "Rune" => Some((Type::Rune, &[Type::I64, Type::Rune, Type::U8][..])),
+        "U8" => Some((Type::U8, &[Type::I64, Type::U8][..])),
+        _ => None,
+    };
+    if let Some((to, from)) = conversion {
+        return Some(match args {
+            [arg] if from.contains(arg) => Ok(to),
+            _ => Err(format!("Cannot convert {:?} to {:?}", args, to).into()),
+        });
+    }
+    if let Some(res) = type_string_call(name, args) {
+        return Some(res);
+    }
+    match (name, args) {
+        // The interpreter implements these itself, since they depend on its state
+        ("chan", [Type::I64]) => return Some(Ok(Type::Chan(Box::new(Type::Unknown)))),
+        ("close", [Type::Chan(_)]) => return Some(Ok(Type::Tuple(vec![]))),
+        ("chan", _) | ("close", _) => return wrong_types(name, args),
+        // Collecting garbage gives how many values it freed
+        ("gc", []) => return Some(Ok(Type::I64)),
+        ("gc", _) => return wrong_types(name, args),
+        // Panicking never returns, so it fits wherever a value is expected
+        ("panic", [Type::Str]) => return Some(Ok(Type::Unknown)),
+        ("recover", []) => return Some(Ok(Type::Option(Box::new(Type::Str)))),
+        ("panic", _) | ("recover", _) => return wrong_types(name, args),
+        ("len", [Type::Slice(_)]) | ("len", [Type::Map(_, _)]) => return Some(Ok(Type::I64)),
+        ("len", _) => return wrong_types(name, args),
+        ("append", [Type::Slice(elem), value]) => {
+            return match elem.unify(value) {
+                Some(elem) => Some(Ok(Type::Slice(Box::new(elem)))),
+                None => wrong_types(name, args),
+            };
+        }
+        ("append", _) => return wrong_types(name, args),
+        ("insert", [Type::Map(k, v), key, value]) => {
+            return match (k.unify(key), v.unify(value)) {
+                (Some(_), Some(v)) => Some(Ok(Type::Option(Box::new(v)))),
+                _ => wrong_types(name, args),
+            };
+        }
+        ("delete", [Type::Map(k, v), key]) if k.unify(key).is_some() => {
+            return Some(Ok(Type::Option(v.clone())));
+        }
+        ("contains", [Type::Map(k, _), key]) if k.unify(key).is_some() => {
+            return Some(Ok(Type::Bool));
+        }
+        ("keys", [Type::Map(k, _)]) => return Some(Ok(Type::Slice(k.clone()))),
+        ("values", [Type::Map(_, v)]) => return Some(Ok(Type::Slice(v.clone()))),
+        ("entries", [Type::Map(k, v)]) => {
+            let entry = Type::Tuple(vec![(**k).clone(), (**v).clone()]);
+            return Some(Ok(Type::Slice(Box::new(entry))));
+        }
+        ("insert", _) | ("delete", _) | ("contains", _) | ("keys", _) | ("values", _)
+        | ("entries", _) => return wrong_types(name, args),
+        ("Some", [t]) => return Some(Ok(Type::Option(Box::new(t.clone())))),
+        ("Ok", [t]) => {
+            return Some(Ok(Type::Result(Box::new(t.clone()), Box::new(Type::Unknown))));
+        }
+        ("Err", [e]) => {
+            return Some(Ok(Type::Result(Box::new(Type::Unknown), Box::new(e.clone()))));
+        }
+        ("Some", _) | ("Ok", _) | ("Err", _) => return wrong_types(name, args),
+        ("is_some", [Type::Option(_)]) | ("is_ok", [Type::Result(_, _)]) => {
+            return Some(Ok(Type::Bool));
+        }
+        ("unwrap", [Type::Option(t)]) | ("unwrap", [Type::Result(t, _)]) => {
+            return Some(Ok((**t).clone()));
+        }
+        ("unwrap_err", [Type::Result(_, e)]) => return Some(Ok((**e).clone())),
+        ("unwrap_or", [Type::Option(t), default]) | ("unwrap_or", [Type::Result(t, _), default]) => {
+            return match t.unify(default) {
+                Some(t) => Some(Ok(t)),
+                None => wrong_types(name, args),
+            };
+        }
+        ("is_some", _) | ("is_ok", _) | ("unwrap", _) | ("unwrap_err", _) | ("unwrap_or", _) => {
+            return wrong_types(name, args);
+        }
+        _ => {}
+    }
+    if find_int_binary(name).is_some() {
+        let expected = [Type::I64, Type::I64];
+        if args != expected {
+            return Some(Err(format!(
+                "Trying to call {} with types {:?}, expected {:?} instead",
+                name, args, expected
+            )
+            .into()));
+        }
+        return Some(Ok(Type::I64));
+    }
+    None
+}
+
+// Type the builtins working on strings
+fn type_string_call(name: &str, args: &[Type]) -> Option<typer::Result<Type>> {
+    let strings = || Type::Slice(Box::new(Type::Str));
+    let typ = match (name, args) {
+        ("len", [Type::Str]) | ("rune_count", [Type::Str]) => Type::I64,
+        ("rune_at", [Type::Str, Type::I64]) => Type::Rune,
+        ("runes", [Type::Str]) => Type::Slice(Box::new(Type::Rune)),
+        ("bytes", [Type::Str]) => Type::Slice(Box::new(Type::U8)),
+        ("split", [Type::Str, Type::Str]) => strings(),
+        ("join", [parts, Type::Str]) if parts.unify(&strings()).is_some() => Type::Str,
+        ("trim", [Type::Str]) | ("to_upper", [Type::Str]) | ("to_lower", [Type::Str]) => Type::Str,
+        ("contains", [Type::Str, Type::Str]) => Type::Bool,
+        ("replace", [Type::Str, Type::Str, Type::Str]) => Type::Str,
+        ("index_of", [Type::Str, Type::Str]) => Type::Option(Box::new(Type::I64)),
+        ("repeat", [Type::Str, Type::I64]) => Type::Str,
+        ("rune_count", _) | ("rune_at", _) | ("runes", _) | ("bytes", _) | ("split", _)
+        | ("join", _) | ("trim", _) | ("to_upper", _) | ("to_lower", _) | ("replace", _)
+        | ("index_of", _) | ("repeat", _) => return wrong_types(name, args),
+        _ => return None,
+    };
+    Some(Ok(typ))
+}
+
+// Call the builtins working on strings.
+//
+// Strings are UTF-8, and positions in them are byte offsets, like in Go.
+fn call_string(name: &str, args: &[Litteral]) -> Option<InterpreterResult<Litteral>> {
+    let strings = |parts: Vec<&str>| {
+        let parts = parts.into_iter().map(|s| Litteral::Str(s.into())).collect();
+        Litteral::Slice(Slice::new(parts))
+    };
+    let res = match (name, args) {
+        ("len", [Litteral::Str(s)]) => Litteral::I64(s.len() as i64),
+        ("rune_count", [Litteral::Str(s)]) => Litteral::I64(s.chars().count() as i64),
+        ("rune_at", [Litteral::Str(s), Litteral::I64(i)]) => {
+            let rune = usize::try_from(*i).ok().and_then(|i| s.chars().nth(i));
+            return Some(match rune {
+                Some(c) => Ok(Litteral::Rune(c)),
+                None => Err(format!("Rune index {} out of bounds for {:?}", i, s).into()),
+            });
+        }
+        // Iterating over a string is done through a slice of its runes or bytes
+        ("runes", [Litteral::Str(s)]) => {
+            Litteral::Slice(Slice::new(s.chars().map(Litteral::Rune).collect()))
+        }
+        ("bytes", [Litteral::Str(s)]) => {
+            Litteral::Slice(Slice::new(s.bytes().map(Litteral::U8).collect()))
+        }
+        // Like in Go, splitting on an empty separator splits every rune apart
+        ("split", [Litteral::Str(s), Litteral::Str(sep)]) if sep.is_empty() => {
+            let runes = s.chars().map(|c| Litteral::Str(c.to_string())).collect();
+            Litteral::Slice(Slice::new(runes))
+        }
+        ("split", [Litteral::Str(s), Litteral::Str(sep)]) => strings(s.split(sep.as_str()).collect()),
+        ("join", [Litteral::Slice(parts), Litteral::Str(sep)]) => {
+            let mut joined = String::new();
+            for (i, part) in parts.values().iter().enumerate() {
+                if i > 0 {
+                    joined.push_str(sep);
+                }
+                match part {
+                    Litteral::Str(part) => joined.push_str(part),
+                    _ => return wrong_values(name, args),
+                }
+            }
+            Litteral::Str(joined)
+        }
+        ("trim", [Litteral::Str(s)]) => Litteral::Str(s.trim().into()),
+        ("to_upper", [Litteral::Str(s)]) => Litteral::Str(s.to_uppercase()),
+        ("to_lower", [Litteral::Str(s)]) => Litteral::Str(s.to_lowercase()),
+        ("contains", [Litteral::Str(s), Litteral::Str(sub)]) => {
+            Litteral::Bool(s.contains(sub.as_str()))
+        }
+        ("replace", [Litteral::Str(s), Litteral::Str(from), Litteral::Str(to)]) => {
+            Litteral::Str(s.replace(from.as_str(), to))
+        }
+        ("index_of", [Litteral::Str(s), Litteral::Str(sub)]) => {
+            let found = s.find(sub.as_str());
+            Litteral::Option(found.map(|i| Box::new(Litteral::I64(i as i64))))
+        }
+        ("repeat", [Litteral::Str(s), Litteral::I64(n)]) => {
+            // Strings can't hold more than isize::MAX bytes
+            let fits = |count| s.len().checked_mul(count).and_then(|l| isize::try_from(l).ok());
+            return Some(match usize::try_from(*n) {
+                Ok(count) if fits(count).is_some() => Ok(Litteral::Str(s.repeat(count))),
+                Ok(_) => Err(format!("Repeating {:?} {} times is too long", s, n).into()),
+                Err(_) => Err(format!("Negative repeat count {}", n).into()),
+            });
+        }
+        ("rune_count", _) | ("rune_at", _) | ("runes", _) | ("bytes", _) | ("split", _)
+        | ("join", _) | ("trim", _) | ("to_upper", _) | ("to_lower", _) | ("replace", _)
+        | ("index_of", _) | ("repeat", _) => return wrong_values(name, args),
+        _ => return None,
+    };
+    Some(Ok(res))
+}
+
+/// Call a builtin function with already evaluated arguments.
+///
+/// This returns `None` if there's no builtin with that name.
+pub fn call(name: &str, args: &[Litteral]) -> Option<InterpreterResult<Litteral>> {
+    if let Some(res) = call_string(name, args) {
+        return Some(res);
+    }
+    match (name, args) {
+        ("BigInt", [Litteral::I64(i)]) => return Some(Ok(Litteral::BigInt(BigInt::from(*i)))),
+        ("BigInt", [Litteral::BigInt(i)]) => return Some(Ok(Litteral::BigInt(i.clone()))),
+        ("I64", [Litteral::I64(i)]) => return Some(Ok(Litteral::I64(*i))),
+        ("I64", [Litteral::BigInt(i)]) => {
+            let res = i
+                .to_i64()
+                .map(Litteral::I64)
+                .ok_or_else(|| format!("{} doesn't fit in an I64", i).into());
+            return Some(res);
+        }
+        ("I64", [Litteral::Rune(c)]) => return Some(Ok(Litteral::I64(i64::from(u32::from(*c))))),
+        ("I64", [Litteral::U8(b)]) => return Some(Ok(Litteral::I64(i64::from(*b)))),
+        ("Rune", [Litteral::I64(i)]) => {
+            let res = u32::try_from(*i)
+                .ok()
+                .and_then(std::char::from_u32)
+                .map(Litteral::Rune)
+                .ok_or_else(|| format!("{} isn't a valid Rune", i).into());
+            return Some(res);
+        }
+        ("Rune", [Litteral::Rune(c)]) => return Some(Ok(Litteral::Rune(*c))),
+        ("Rune", [Litteral::U8(b)]) => return Some(Ok(Litteral::Rune(char::from(*b)))),
+        ("U8", [Litteral::I64(i)]) => {
+            let res = u8::try_from(*i)
+                .map(Litteral::U8)
+                .map_err(|_| format!("{} doesn't fit in a U8", i).into());
+            return Some(res);
+        }
+        ("U8", [Litteral::U8(b)]) => return Some(Ok(Litteral::U8(*b))),
+        ("len", [Litteral::Slice(s)]) => return Some(Ok(Litteral::I64(s.len() as i64))),
+        ("len", [Litteral::Map(m)]) => return Some(Ok(Litteral::I64(m.len() as i64))),
+        ("len", _) => return wrong_values(name, args),
+        ("append", [Litteral::Slice(s), value]) => {
+            return Some(Ok(Litteral::Slice(s.append(value.clone()))));
+        }
+        ("append", _) => return wrong_values(name, args),
+        ("insert", [Litteral::Map(m), key, value]) => {
+            let previous = m.insert(key.clone(), value.clone());
+            return Some(Ok(Litteral::Option(previous.map(Box::new))));
+        }
+        ("delete", [Litteral::Map(m), key]) => {
+            return Some(Ok(Litteral::Option(m.remove(key).map(Box::new))));
+        }
+        ("contains", [Litteral::Map(m), key]) => return Some(Ok(Litteral::Bool(m.contains(key)))),
+        ("keys", [Litteral::Map(m)]) => {
+            let keys = m.entries().into_iter().map(|(k, _)| k).collect();
+            return Some(Ok(Litteral::Slice(Slice::new(keys))));
+        }
+        ("values", [Litteral::Map(m)]) => {
+            let values = m.entries().into_iter().map(|(_, v)| v).collect();
+            return Some(Ok(Litteral::Slice(Slice::new(values))));
+        }
+        ("entries", [Litteral::Map(m)]) => {
+            let entries = m
+                .entries()
+                .into_iter()
+                .map(|(k, v)| Litteral::Tuple(vec![k, v]))
+                .collect();
+            return Some(Ok(Litteral::Slice(Slice::new(entries))));
+        }
+        ("insert", _) | ("delete", _) | ("contains", _) | ("keys", _) | ("values", _)
+        | ("entries", _) => return wrong_values(name, args),
+        ("Some", [l]) => return Some(Ok(Litteral::Option(Some(Box::new(l.clone()))))),
+        ("Ok", [l]) => return Some(Ok(Litteral::Result(Ok(Box::new(l.clone()))))),
+        ("Err", [l]) => return Some(Ok(Litteral::Result(Err(Box::new(l.clone()))))),
+        ("Some", _) | ("Ok", _) | ("Err", _) => return wrong_values(name, args),
+        ("is_some", [Litteral::Option(o)]) => return Some(Ok(Litteral::Bool(o.is_some()))),
+        ("is_ok", [Litteral::Result(r)]) => return Some(Ok(Litteral::Bool(r.is_ok()))),
+        ("unwrap", [Litteral::Option(Some(l))]) | ("unwrap", [Litteral::Result(Ok(l))]) => {
+            return Some(Ok((**l).clone()));
+        }
+        ("unwrap", [Litteral::Option(None)]) => return Some(Err("Unwrapped a None value".into())),
+        ("unwrap", [Litteral::Result(Err(e))]) => {
+            return Some(Err(format!("Unwrapped an error: {}", e).into()));
+        }
+        ("unwrap_err", [Litteral::Result(Err(e))]) => return Some(Ok((**e).clone())),
+        ("unwrap_err", [Litteral::Result(Ok(l))]) => {
+            return Some(Err(format!("Expected an error, but got Ok({})", l).into()));
+        }
+        ("unwrap_or", [Litteral::Option(o), default]) => {
+            let res = o.as_ref().map_or(default.clone(), |l| (**l).clone());
+            return Some(Ok(res));
+        }
+        ("unwrap_or", [Litteral::Result(r), default]) => {
+            let res = r.as_ref().map_or(default.clone(), |l| (**l).clone());
+            return Some(Ok(res));
+        }
+        ("is_some", _) | ("is_ok", _) | ("unwrap", _) | ("unwrap_err", _) | ("unwrap_or", _) => {
+            return wrong_values(name, args);
+        }
+        _ => {}
+    }
+    if let Some(f) = find_int_binary(name) {
+        let res = match args {
+            [Litteral::I64(l), Litteral::I64(r)] => Ok(Litteral::I64(f(*l, *r))),
+            _ => Err(format!("{} expects two I64 arguments, but got {:?}", name, args).into()),
+        };
+        return Some(res);
+    }
+    None
+}
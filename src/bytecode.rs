@@ -0,0 +1,170 @@
+use crate::ast::{BoolOp, Litteral, Op, Span, TypeDecl, TypeName};
+use crate::ir::Expr;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Represents a single instruction of the VM.
+///
+/// Instructions work on a stack of values. Every expression compiles to
+/// instructions leaving exactly one value on the stack, so statements are
+/// followed by a `Pop`. Jumps go to the index of an instruction in the same chunk.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    /// Push a value from the constants of the chunk
+    Const(usize),
+    /// Push the unit value, `()`
+    Unit,
+    /// Push the value of a local variable
+    Load(usize),
+    /// Pop a value into a local variable
+    Store(usize),
+    /// Push the value of a variable captured by the closure being run
+    LoadCaptured(usize),
+    /// Pop a value into a variable captured by the closure being run
+    StoreCaptured(usize),
+    /// Push the value on top of the stack again
+    Dup,
+    /// Throw away the value on top of the stack
+    Pop,
+    /// Create a closure running a chunk, capturing the variables it needs
+    MakeClosure(usize),
+    /// Apply a binary operator to the two values on top of the stack
+    BinOp(Op),
+    /// Apply a binary operator to two I64s on top of the stack, which the
+    /// type checker made sure they are
+    IntOp(Op),
+    /// Negate a number, with `-`
+    Neg,
+    /// Negate a boolean, with `!`
+    Not,
+    /// Check the left side of `&&` or `||`, jumping with it as the result
+    /// if the right side doesn't need to run
+    ShortCircuit(BoolOp, usize),
+    /// Pop a condition, jumping if it's false
+    JumpIfFalse(usize),
+    Jump(usize),
+    /// Build a tuple out of a number of values
+    MakeTuple(usize),
+    /// Build a slice out of a number of values
+    MakeSlice(usize),
+    /// Build a map out of a number of keys, each followed by its value
+    MakeMap(usize),
+    /// Index into a slice, string, or map
+    Index,
+    /// Take part of a slice or a string, with whether each bound was given
+    Subslice(bool, bool),
+    /// Assign a value to an index of a slice or a map
+    AssignIndex,
+    /// Read one of the elements of a tuple, or the value wrapped by a newtype
+    Field(usize),
+    /// Split a tuple of some length into its elements, pushing the first one last
+    Unpack(usize),
+    /// Unwrap an Option or a Result, returning None or Err from the function instead
+    Try,
+    /// Call a top level function with a number of arguments
+    Call(usize, usize),
+    /// Call the function value sitting below a number of arguments
+    CallValue(usize),
+    /// Call a builtin or a newtype, named by one of the names of the chunk
+    CallNamed(usize, usize),
+    /// Call a top level function in tail position, replacing the function running
+    TailCall(usize, usize),
+    /// Call a function value in tail position, replacing the function running
+    TailCallValue(usize),
+    /// Defer a call with a number of arguments until the function returns
+    Defer(CallTarget, usize),
+    /// Start a call with a number of arguments in a new task
+    Go(CallTarget, usize),
+    /// Send a value on a channel
+    Send,
+    /// Receive a value from a channel
+    Recv,
+    /// Put a copy of a value on the heap, pushing a pointer to it
+    Ref,
+    /// Read the value a pointer points to
+    Deref,
+    /// Change the value a pointer points to, keeping the value on the stack
+    AssignDeref,
+    /// Wait for one of the cases of a select, described by the chunk
+    Select(usize),
+    /// Return the value on top of the stack from the function
+    Return,
+    /// Fail with one of the names of the chunk as the message
+    Fail(usize),
+}
+
+/// The function a deferred call or a new task starts with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallTarget {
+    /// A function value, sitting below the arguments
+    Value,
+    /// A function named by one of the names of the chunk, like a builtin
+    Named(usize),
+}
+
+/// Where a closure gets one of its captured variables from, when it's created
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capture {
+    Local(usize),
+    /// A variable the enclosing function captured itself
+    Captured(usize),
+}
+
+/// Describes a select, whose channels and values are on the stack in the order of its cases
+#[derive(Clone, Debug, PartialEq)]
+pub struct Select {
+    pub cases: Vec<SelectCase>,
+    /// Where the default block starts, if there's one
+    pub default: Option<usize>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectCase {
+    /// Whether this case receives, rather than sends a value
+    pub recv: bool,
+    /// Where the body of this case starts
+    pub target: usize,
+    pub span: Span,
+}
+
+/// Represents the compiled code of a function.
+///
+/// Local variables live in numbered slots, starting with the arguments, so
+/// the VM never looks them up by name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chunk {
+    /// How the function shows up in stack traces, like `func main`
+    pub name: String,
+    pub args: Vec<String>,
+    pub ret: Option<TypeName>,
+    /// The body this was compiled from, which closures refer to
+    pub body: Rc<Vec<Expr>>,
+    /// How many slots the local variables need, including the arguments
+    pub slots: usize,
+    /// Where the variables this function captures come from, when it's created
+    pub captures: Vec<Capture>,
+    pub code: Vec<Instr>,
+    /// Where the error of each instruction happens, if it fails
+    pub spans: Vec<Option<Span>>,
+    /// How many steps running each instruction takes, which is how many
+    /// expressions start with it, so programs use up as much fuel as in the interpreter
+    pub steps: Vec<u32>,
+    pub consts: Vec<Litteral>,
+    /// Names of the functions called by name, and messages of failures
+    pub names: Vec<String>,
+    pub selects: Vec<Select>,
+}
+
+/// Represents a whole program compiled for the VM
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    /// The chunks of every function, starting with the top level functions
+    pub chunks: Vec<Chunk>,
+    /// The chunk of each top level function, by name
+    pub functions: HashMap<String, usize>,
+    /// The names of newtypes, which can be called to wrap a value
+    pub newtypes: HashSet<String>,
+    /// Type declarations by name, used to find zero values
+    pub types: HashMap<String, TypeDecl>,
+}
@@ -0,0 +1,122 @@
+use crate::ast::Litteral;
+use crate::heap::Trace;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+/// Represents a channel, which tasks use to pass values to each other.
+///
+/// This follows the semantics of channels in Go: a channel holds up to its
+/// capacity in values, and sending on a full channel waits for a receiver.
+/// A channel without any capacity only accepts a value once a receiver is
+/// waiting for it.
+#[derive(Clone, Debug)]
+pub struct Chan {
+    // The scheduler uses this to know which tasks are waiting on this channel
+    id: usize,
+    state: Rc<RefCell<ChanState>>,
+}
+
+#[derive(Debug)]
+struct ChanState {
+    cap: usize,
+    // The values that have been sent, but not received yet
+    queue: VecDeque<Litteral>,
+    // How many tasks are waiting to receive from this channel
+    receivers: usize,
+    closed: bool,
+}
+
+impl Chan {
+    /// Create an empty channel that can hold a certain number of values
+    pub fn new(id: usize, cap: usize) -> Self {
+        let state = ChanState {
+            cap,
+            queue: VecDeque::new(),
+            receivers: 0,
+            closed: false,
+        };
+        Chan {
+            id,
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.borrow().closed
+    }
+
+    /// Check whether a value can be sent right now, without waiting.
+    ///
+    /// Every waiting receiver makes room for one more value, which is what
+    /// lets a channel without capacity accept values at all.
+    pub fn can_send(&self) -> bool {
+        let state = self.state.borrow();
+        state.queue.len() < state.cap + state.receivers
+    }
+
+    /// Check whether receiving right now would finish without waiting
+    pub fn can_recv(&self) -> bool {
+        let state = self.state.borrow();
+        !state.queue.is_empty() || state.closed
+    }
+
+    /// Send a value, even if there's no room for it.
+    ///
+    /// `can_send` should be checked first, unless the channel is closed.
+    pub fn send(&self, value: Litteral) {
+        self.state.borrow_mut().queue.push_back(value);
+    }
+
+    /// Take the oldest value sent on this channel, if there's one
+    pub fn recv(&self) -> Option<Litteral> {
+        self.state.borrow_mut().queue.pop_front()
+    }
+
+    /// Keep track of a receiver starting or stopping to wait on this channel
+    pub fn set_waiting(&self, waiting: bool) {
+        let mut state = self.state.borrow_mut();
+        if waiting {
+            state.receivers += 1;
+        } else {
+            state.receivers -= 1;
+        }
+    }
+
+    /// Close this channel, returning false if it was already closed
+    pub fn close(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        let was_open = !state.closed;
+        state.closed = true;
+        was_open
+    }
+}
+
+// The values sent on a channel stay alive until they're received
+impl Trace for Chan {
+    fn shared(&self) -> (usize, usize) {
+        (Rc::as_ptr(&self.state) as usize, Rc::strong_count(&self.state))
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(&Litteral)) {
+        self.state.borrow().queue.iter().for_each(visit)
+    }
+}
+
+// Like other references, channels are only equal if they're the same channel
+impl PartialEq for Chan {
+    fn eq(&self, other: &Chan) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl fmt::Display for Chan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chan#{}", self.id)
+    }
+}
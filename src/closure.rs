@@ -0,0 +1,59 @@
+use crate::ast::{Litteral, TypeName};
+use crate::heap::Trace;
+use crate::ir::Expr;
+use std::fmt;
+use std::rc::Rc;
+
+/// Represents a function used as a value.
+///
+/// This is either a reference to a top level function, or an anonymous
+/// function along with the variables it captured when it was created.
+/// Variables are captured by value, so every call of a closure starts with the
+/// values those variables had when the closure was created.
+#[derive(Clone, Debug)]
+pub struct Closure {
+    /// The name of the function, if it was defined at the top level
+    pub name: Option<String>,
+    /// The names of the arguments this function accepts
+    pub args: Vec<String>,
+    /// The declared return type, if any, which a recovered call returns the zero value of
+    pub ret: Option<TypeName>,
+    /// The series of expressions making up the body of this function
+    pub body: Rc<Vec<Expr>>,
+    /// The values of the variables this function uses from where it was created,
+    /// in the order the resolver found them
+    pub captured: Rc<Vec<Litteral>>,
+    /// How many slots the local variables of this function need, including its arguments
+    pub slots: usize,
+    /// The compiled code of this function, when it was created by the VM
+    pub code: Option<usize>,
+}
+
+// Functions can't be compared in the language itself, but litterals need
+// equality, so two closures are only equal if they're the very same value.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        Rc::ptr_eq(&self.body, &other.body) && Rc::ptr_eq(&self.captured, &other.captured)
+    }
+}
+
+// Values refer to a function through the Rc holding it, which is what gets
+// counted. Only the captured variables can hold values, since the body is code.
+impl Trace for Rc<Closure> {
+    fn shared(&self) -> (usize, usize) {
+        (Rc::as_ptr(self) as usize, Rc::strong_count(self))
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(&Litteral)) {
+        self.captured.iter().for_each(visit)
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "func {}", name),
+            None => write!(f, "func({})", self.args.join(", ")),
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use crate::ast::*;
+use crate::builtins;
+use crate::bytecode::{self, CallTarget, Capture, Chunk, Instr, Program, Select};
+use crate::ir;
+use crate::closure::Closure;
+use crate::ir::{Expr, Kind, SelectCase, SelectOp};
+use crate::interpreter::{InterpreterError, InterpreterResult};
+use crate::typer::Type;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// The state of a function being compiled
+struct Builder {
+    chunk: Chunk,
+    // The span given to errors happening inside of an expression like `xs[i]`,
+    // unless they have a more precise one, like in the interpreter
+    around: Option<Span>,
+    // How many expressions start with the next instruction
+    steps: u32,
+}
+
+/// Compiles the functions of a type checked program into bytecode for the VM
+struct Compiler<'a> {
+    // The values of constants, which were evaluated ahead of time
+    consts: &'a HashMap<String, Litteral>,
+    functions: &'a HashMap<String, usize>,
+    newtypes: &'a HashSet<String>,
+    // Each top level function used as a value, shared by every use of its name
+    values: Vec<Litteral>,
+    // The chunks compiled so far, with a placeholder for those being compiled
+    chunks: Vec<Chunk>,
+    // The functions being compiled, with anonymous functions nested in the last one
+    nested: Vec<Builder>,
+}
+
+impl<'a> Compiler<'a> {
+    fn builder(&mut self) -> &mut Builder {
+        self.nested.last_mut().expect("Compiling outside of a function")
+    }
+
+    // Add an instruction, along with where it fails if it has a span of its own
+    fn emit(&mut self, instr: Instr, span: Option<Span>) -> usize {
+        let builder = self.builder();
+        let span = span.or(builder.around);
+        let steps = std::mem::take(&mut builder.steps);
+        builder.chunk.code.push(instr);
+        builder.chunk.spans.push(span);
+        builder.chunk.steps.push(steps);
+        builder.chunk.code.len() - 1
+    }
+
+    // Evaluating an expression takes a step, like in the interpreter, which
+    // the first instruction it compiles to takes
+    fn step(&mut self) {
+        self.builder().steps += 1;
+    }
+
+    // Where the next instruction will go, which is where jumps to it go
+    fn here(&mut self) -> usize {
+        self.builder().chunk.code.len()
+    }
+
+    // Point a jump emitted earlier at the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.builder().chunk.code[at] {
+            Instr::ShortCircuit(_, to) | Instr::JumpIfFalse(to) | Instr::Jump(to) => *to = target,
+            instr => unreachable!("Patching {:?}, which doesn't jump", instr),
+        }
+    }
+
+    fn constant(&mut self, value: Litteral) -> usize {
+        let consts = &mut self.builder().chunk.consts;
+        consts.push(value);
+        consts.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        let names = &mut self.builder().chunk.names;
+        match names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                names.push(name.into());
+                names.len() - 1
+            }
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        let i = self.name(&message);
+        self.emit(Instr::Fail(i), None);
+    }
+
+    fn load(&mut self, var: &Var) {
+        match var.binding {
+            Binding::Local(slot) => self.emit(Instr::Load(slot), None),
+            Binding::Captured(i) => self.emit(Instr::LoadCaptured(i), None),
+            binding => unreachable!("Loading {}, which is {:?}", var.name, binding),
+        };
+    }
+
+    fn store(&mut self, var: &Var) {
+        match var.binding {
+            Binding::Local(slot) => self.emit(Instr::Store(slot), None),
+            Binding::Captured(i) => self.emit(Instr::StoreCaptured(i), None),
+            binding => unreachable!("Assigning to {}, which is {:?}", var.name, binding),
+        };
+    }
+
+    // Compile the body of a function into a new chunk
+    fn function(&mut self, chunk: Chunk) -> Chunk {
+        self.nested.push(Builder {
+            chunk,
+            around: None,
+            steps: 0,
+        });
+        let body = Rc::clone(&self.builder().chunk.body);
+        self.block(&body, true);
+        self.emit(Instr::Return, None);
+        self.nested.pop().unwrap().chunk
+    }
+
+    fn lambda(
+        &mut self,
+        args: &[(String, TypeName)],
+        ret: &Option<TypeName>,
+        body: &[Expr],
+        locals: &Locals,
+    ) {
+        let args: Vec<String> = args.iter().map(|(name, _)| name.clone()).collect();
+        let captures = locals.captures.iter().map(|var| match var.binding {
+            Binding::Local(slot) => Capture::Local(slot),
+            Binding::Captured(i) => Capture::Captured(i),
+            binding => unreachable!("Capturing {}, which is {:?}", var.name, binding),
+        });
+        let chunk = Chunk {
+            name: format!("func({})", args.join(", ")),
+            args,
+            ret: ret.clone(),
+            body: Rc::new(body.to_vec()),
+            slots: locals.slots,
+            captures: captures.collect(),
+            ..Chunk::default()
+        };
+        // Nested functions are compiled first, so this saves a place for this one
+        let index = self.chunks.len();
+        self.chunks.push(Chunk::default());
+        self.chunks[index] = self.function(chunk);
+        self.emit(Instr::MakeClosure(index), None);
+    }
+
+    // The last expression of a block is in tail position if the block is
+    fn block(&mut self, exprs: &[Expr], tail: bool) {
+        match exprs.split_last() {
+            None => {
+                self.emit(Instr::Unit, None);
+            }
+            Some((last, init)) => {
+                for e in init {
+                    self.statement(e);
+                }
+                match tail {
+                    true => self.tail(last),
+                    false => self.expr(last),
+                }
+            }
+        }
+    }
+
+    // Compile the last expression of a function, whose value it returns.
+    //
+    // Calls found there are tail calls, which replace the function making them.
+    fn tail(&mut self, e: &Expr) {
+        self.step();
+        match &e.kind {
+            Kind::Call(name, args, span) => self.call(name, args, *span, true),
+            Kind::CallValue(target, args, span) => {
+                self.expr(target);
+                self.args(args);
+                self.emit(Instr::TailCallValue(args.len()), Some(*span));
+            }
+            Kind::Block(exprs) => self.block(exprs, true),
+            Kind::IfElse(cond, if_part, else_part) => self.if_else(cond, if_part, else_part, true),
+            Kind::Select(cases, default, span) => self.select(cases, default, *span, true),
+            _ => self.kind(e),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], span: Span, tail: bool) {
+        self.args(args);
+        // Builtins and newtypes come first, like in the interpreter
+        let builtin = name == "print" || builtins::is_builtin(name);
+        match self.functions.get(name) {
+            Some(&f) if !builtin && !self.newtypes.contains(name) => {
+                let call = match tail {
+                    true => Instr::TailCall(f, args.len()),
+                    false => Instr::Call(f, args.len()),
+                };
+                self.emit(call, Some(span));
+            }
+            _ => {
+                let i = self.name(name);
+                self.emit(Instr::CallNamed(i, args.len()), Some(span));
+            }
+        }
+    }
+
+    fn if_else(&mut self, cond: &Expr, if_part: &[Expr], else_part: &[Expr], tail: bool) {
+        self.expr(cond);
+        let to_else = self.emit(Instr::JumpIfFalse(0), None);
+        self.block(if_part, tail);
+        let to_end = self.emit(Instr::Jump(0), None);
+        self.patch(to_else);
+        self.block(else_part, tail);
+        self.patch(to_end);
+    }
+
+    // Compile an expression whose value isn't used
+    fn statement(&mut self, e: &Expr) {
+        match &e.kind {
+            Kind::Declare(var, e) | Kind::DeclareImmutable(var, e) | Kind::Assign(var, e) => {
+                self.step();
+                self.expr(e);
+                self.store(var);
+            }
+            _ => {
+                self.expr(e);
+                self.emit(Instr::Pop, None);
+            }
+        }
+    }
+
+    // Compile the rest of the expression with errors happening at a span
+    fn around<F: FnOnce(&mut Self)>(&mut self, span: Span, f: F) {
+        let outer = self.builder().around.replace(span);
+        f(self);
+        self.builder().around = outer;
+    }
+
+    fn args(&mut self, args: &[Expr]) {
+        for a in args {
+            self.expr(a);
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        self.step();
+        self.kind(e)
+    }
+
+    fn kind(&mut self, e: &Expr) {
+        match &e.kind {
+            Kind::Call(name, args, span) => self.call(name, args, *span, false),
+            Kind::CallValue(target, args, span) => {
+                self.expr(target);
+                self.args(args);
+                self.emit(Instr::CallValue(args.len()), Some(*span));
+            }
+            Kind::Lambda(args, ret, body, locals) => self.lambda(args, ret, body, locals),
+            Kind::Litt(l) => {
+                let i = self.constant(l.clone());
+                self.emit(Instr::Const(i), None);
+            }
+            Kind::Name(var) if var.binding != Binding::Global => self.load(var),
+            Kind::Name(Var { name, .. }) => {
+                let value = self
+                    .consts
+                    .get(name)
+                    .cloned()
+                    .or_else(|| builtins::constant(name))
+                    .or_else(|| Some(self.values[*self.functions.get(name)?].clone()));
+                match value {
+                    Some(value) => {
+                        let i = self.constant(value);
+                        self.emit(Instr::Const(i), None);
+                    }
+                    None => self.fail(format!("Trying to use undefined variable {}", name)),
+                }
+            }
+            Kind::Declare(var, e) | Kind::DeclareImmutable(var, e) | Kind::Assign(var, e) => {
+                self.expr(e);
+                self.emit(Instr::Dup, None);
+                self.store(var);
+            }
+            Kind::DeclarePattern(pattern, e, span) => {
+                self.expr(e);
+                self.emit(Instr::Dup, None);
+                self.pattern(pattern, *span);
+            }
+            Kind::AssignOp(op, var, value, span) => {
+                self.load(var);
+                self.expr(value);
+                self.bin_op(*op, &e.typ, *span);
+                self.emit(Instr::Dup, None);
+                self.store(var);
+            }
+            Kind::Block(exprs) => self.block(exprs, false),
+            Kind::BinOp(op, left, right, span) => {
+                self.expr(left);
+                self.expr(right);
+                self.bin_op(*op, &left.typ, *span);
+            }
+            Kind::ConditionalOp(op, left, right) => {
+                self.expr(left);
+                let jump = self.emit(Instr::ShortCircuit(*op, 0), None);
+                self.expr(right);
+                self.patch(jump);
+            }
+            Kind::IfElse(cond, if_part, else_part) => {
+                self.if_else(cond, if_part, else_part, false)
+            }
+            Kind::Not(e) => {
+                self.expr(e);
+                self.emit(Instr::Not, None);
+            }
+            Kind::Neg(e, span) => {
+                self.expr(e);
+                self.emit(Instr::Neg, Some(*span));
+            }
+            Kind::MakeTuple(exprs) => {
+                self.args(exprs);
+                self.emit(Instr::MakeTuple(exprs.len()), None);
+            }
+            Kind::MakeSlice(exprs) => {
+                self.args(exprs);
+                self.emit(Instr::MakeSlice(exprs.len()), None);
+            }
+            Kind::MakeMap(entries) => {
+                for (k, v) in entries {
+                    self.expr(k);
+                    self.expr(v);
+                }
+                self.emit(Instr::MakeMap(entries.len()), None);
+            }
+            Kind::Index(target, index, span) => self.around(*span, |c| {
+                c.expr(target);
+                c.expr(index);
+                c.emit(Instr::Index, Some(*span));
+            }),
+            Kind::Subslice(target, from, to, span) => self.around(*span, |c| {
+                c.expr(target);
+                for bound in from.iter().chain(to.iter()) {
+                    c.expr(bound);
+                }
+                c.emit(Instr::Subslice(from.is_some(), to.is_some()), Some(*span));
+            }),
+            Kind::AssignIndex(target, index, e, span) => self.around(*span, |c| {
+                c.expr(target);
+                c.expr(index);
+                c.expr(e);
+                c.emit(Instr::AssignIndex, Some(*span));
+            }),
+            Kind::Field(e, field, span) => {
+                self.expr(e);
+                self.emit(Instr::Field(*field), Some(*span));
+            }
+            Kind::Try(e, span) => {
+                self.expr(e);
+                self.emit(Instr::Try, Some(*span));
+            }
+            Kind::Defer(call, span) => self.around(*span, |c| {
+                if let Some((target, argc)) = c.pending_call(call) {
+                    c.emit(Instr::Defer(target, argc), Some(*span));
+                }
+            }),
+            Kind::Go(call, span) => self.around(*span, |c| {
+                if let Some((target, argc)) = c.pending_call(call) {
+                    c.emit(Instr::Go(target, argc), Some(*span));
+                }
+            }),
+            Kind::Send(chan, value, span) => {
+                self.expr(chan);
+                self.expr(value);
+                self.emit(Instr::Send, Some(*span));
+            }
+            Kind::Recv(chan, span) => {
+                self.expr(chan);
+                self.emit(Instr::Recv, Some(*span));
+            }
+            Kind::Ref(e) => {
+                self.expr(e);
+                self.emit(Instr::Ref, None);
+            }
+            Kind::Deref(pointer, span) => {
+                self.expr(pointer);
+                self.emit(Instr::Deref, Some(*span));
+            }
+            Kind::AssignDeref(pointer, e, span) => {
+                self.expr(pointer);
+                self.expr(e);
+                self.emit(Instr::AssignDeref, Some(*span));
+            }
+            Kind::Select(cases, default, span) => self.select(cases, default, *span, false),
+        }
+    }
+
+    // Operators on I64s, which most of them are, don't need to check what
+    // their operands are
+    fn bin_op(&mut self, op: Op, operands: &Type, span: Span) {
+        let instr = match operands {
+            Type::I64 => Instr::IntOp(op),
+            _ => Instr::BinOp(op),
+        };
+        self.emit(instr, Some(span));
+    }
+
+    // Declare the names in a pattern, destructuring the value on top of the stack
+    fn pattern(&mut self, pattern: &Pattern, span: Span) {
+        match pattern {
+            Pattern::Name(var) => self.store(var),
+            Pattern::Ignore => {
+                self.emit(Instr::Pop, None);
+            }
+            Pattern::Tuple(patterns) => {
+                self.emit(Instr::Unpack(patterns.len()), Some(span));
+                for p in patterns {
+                    self.pattern(p, span);
+                }
+            }
+        }
+    }
+
+    // Push the function and arguments of a call being deferred or started in a task
+    fn pending_call(&mut self, call: &Expr) -> Option<(CallTarget, usize)> {
+        match &call.kind {
+            Kind::Call(name, args, _) => {
+                let target = CallTarget::Named(self.name(name));
+                self.args(args);
+                Some((target, args.len()))
+            }
+            Kind::CallValue(target, args, _) => {
+                self.expr(target);
+                self.args(args);
+                Some((CallTarget::Value, args.len()))
+            }
+            _ => {
+                self.fail(String::from("Only function calls can be deferred"));
+                None
+            }
+        }
+    }
+
+    // The channels and values of the cases are pushed first, then the select
+    // jumps to the body of the case it picks, which starts by binding what it
+    // received
+    fn select(
+        &mut self,
+        cases: &[SelectCase],
+        default: &Option<Vec<Expr>>,
+        span: Span,
+        tail: bool,
+    ) {
+        for case in cases {
+            match &case.op {
+                SelectOp::Recv(_, chan) => self.expr(chan),
+                SelectOp::Send(chan, value) => {
+                    self.expr(chan);
+                    self.expr(value);
+                }
+            }
+        }
+        let select = Select {
+            cases: cases
+                .iter()
+                .map(|case| bytecode::SelectCase {
+                    recv: matches!(case.op, SelectOp::Recv(_, _)),
+                    target: 0,
+                    span: case.span,
+                })
+                .collect(),
+            default: None,
+            span,
+        };
+        let selects = &mut self.builder().chunk.selects;
+        selects.push(select);
+        let index = selects.len() - 1;
+        self.emit(Instr::Select(index), None);
+        let mut to_end = Vec::new();
+        for (i, case) in cases.iter().enumerate() {
+            let target = self.here();
+            self.builder().chunk.selects[index].cases[i].target = target;
+            match &case.op {
+                SelectOp::Recv(Some(var), _) => self.store(var),
+                SelectOp::Recv(None, _) => {
+                    self.emit(Instr::Pop, None);
+                }
+                SelectOp::Send(_, _) => {}
+            }
+            self.block(&case.body, tail);
+            to_end.push(self.emit(Instr::Jump(0), None));
+        }
+        if let Some(default) = default {
+            let target = self.here();
+            self.builder().chunk.selects[index].default = Some(target);
+            self.block(default, tail);
+        }
+        for jump in to_end {
+            self.patch(jump);
+        }
+    }
+}
+
+/// Compile a type checked program into bytecode
+pub fn compile(program: &ir::Program) -> InterpreterResult<Program> {
+    let mut functions = HashMap::new();
+    for (i, f) in program.functions.iter().enumerate() {
+        if functions.insert(f.name.clone(), i).is_some() {
+            return Err(InterpreterError::from(format!("Redefinition of function {}", f.name)));
+        }
+    }
+    let newtypes = program.types.iter().filter(|t| t.newtype).map(|t| t.name.clone()).collect();
+    let mut chunks = Vec::with_capacity(program.functions.len());
+    let mut values = Vec::with_capacity(program.functions.len());
+    for (i, f) in program.functions.iter().enumerate() {
+        let args: Vec<String> = f.args.iter().map(|(name, _)| name.clone()).collect();
+        let body = Rc::new(f.body.clone());
+        values.push(Litteral::Function(Rc::new(Closure {
+            name: Some(f.name.clone()),
+            args: args.clone(),
+            ret: f.ret.clone(),
+            body: Rc::clone(&body),
+            captured: Rc::new(Vec::new()),
+            slots: f.locals.slots,
+            code: Some(i),
+        })));
+        chunks.push(Chunk {
+            name: format!("func {}", f.name),
+            args,
+            ret: f.ret.clone(),
+            body,
+            slots: f.locals.slots,
+            ..Chunk::default()
+        });
+    }
+    let mut compiler = Compiler {
+        consts: &program.consts,
+        functions: &functions,
+        newtypes: &newtypes,
+        values,
+        chunks: Vec::new(),
+        nested: Vec::new(),
+    };
+    // Top level functions come first, so calls can refer to them by index
+    compiler.chunks = chunks.clone();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        compiler.chunks[i] = compiler.function(chunk);
+    }
+    let chunks = compiler.chunks;
+    Ok(Program {
+        chunks,
+        functions,
+        newtypes,
+        types: program.types.iter().map(|t| (t.name.clone(), t.clone())).collect(),
+    })
+}
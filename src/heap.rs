@@ -0,0 +1,347 @@
+use crate::ast::Litteral;
+use crate::chan::Chan;
+use crate::closure::Closure;
+use crate::interpreter::unit;
+use crate::map::Map;
+use crate::slice::Slice;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// Represents a pointer to a value on the heap, created with `&v`.
+///
+/// Like in Go, copying a pointer shares the value it points to, so changing
+/// that value through one pointer is visible through every copy of it.
+#[derive(Clone)]
+pub struct Pointer {
+    // The heap numbers its values in the order they were allocated
+    id: usize,
+    cell: Rc<RefCell<Litteral>>,
+}
+
+impl Pointer {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Read the value this points to
+    pub fn get(&self) -> Litteral {
+        self.cell.borrow().clone()
+    }
+
+    /// Replace the value this points to
+    pub fn set(&self, value: Litteral) {
+        self.cell.replace(value);
+    }
+}
+
+// Like other references, pointers are only equal if they point to the same value
+impl PartialEq for Pointer {
+    fn eq(&self, other: &Pointer) -> bool {
+        Rc::ptr_eq(&self.cell, &other.cell)
+    }
+}
+
+// Pointers can point to themselves, so this doesn't show what they point to
+impl fmt::Debug for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pointer({})", self.id)
+    }
+}
+
+impl fmt::Display for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ptr#{}", self.id)
+    }
+}
+
+/// Implemented by the values sharing what they hold through a reference count,
+/// which the collector traces through.
+pub trait Trace {
+    /// Where the shared part lives, which identifies it, along with how many
+    /// values refer to it
+    fn shared(&self) -> (usize, usize);
+    /// Visit every value held by the shared part
+    fn trace(&self, visit: &mut dyn FnMut(&Litteral));
+}
+
+impl Trace for Pointer {
+    fn shared(&self) -> (usize, usize) {
+        (Rc::as_ptr(&self.cell) as usize, Rc::strong_count(&self.cell))
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(&Litteral)) {
+        visit(&self.cell.borrow())
+    }
+}
+
+/// What the garbage collector did while a program ran
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// How many values were put on the heap
+    pub allocated: usize,
+    /// How many of those were freed, once nothing could reach them anymore
+    pub freed: usize,
+    /// How many values the collector freed itself, since they could only
+    /// reach each other, in cycles that reference counting never frees
+    pub collected: usize,
+    /// How many times the collector ran
+    pub collections: usize,
+}
+
+impl GcStats {
+    /// How many values are still on the heap
+    pub fn live(&self) -> usize {
+        self.allocated - self.freed
+    }
+}
+
+// Something shared that the heap refers to, held while collecting
+enum Node {
+    Pointer(Pointer),
+    Slice(Slice),
+    Map(Map),
+    Chan(Chan),
+    Closure(Rc<Closure>),
+}
+
+impl Node {
+    fn as_trace(&self) -> &dyn Trace {
+        match self {
+            Node::Pointer(pointer) => pointer,
+            Node::Slice(slice) => slice,
+            Node::Map(map) => map,
+            Node::Chan(chan) => chan,
+            Node::Closure(closure) => closure,
+        }
+    }
+}
+
+// Find the shared parts of a value, looking inside of those that aren't shared
+fn shared_in(value: &Litteral, found: &mut Vec<Node>) {
+    match value {
+        Litteral::Pointer(pointer) => found.push(Node::Pointer(pointer.clone())),
+        Litteral::Slice(slice) => found.push(Node::Slice(slice.clone())),
+        Litteral::Map(map) => found.push(Node::Map(map.clone())),
+        Litteral::Chan(chan) => found.push(Node::Chan(chan.clone())),
+        Litteral::Function(closure) => found.push(Node::Closure(closure.clone())),
+        Litteral::Tuple(litterals) => {
+            for l in litterals {
+                shared_in(l, found);
+            }
+        }
+        Litteral::Option(Some(l))
+        | Litteral::Result(Ok(l))
+        | Litteral::Result(Err(l))
+        | Litteral::Newtype(_, l) => shared_in(l, found),
+        _ => {}
+    }
+}
+
+// How the collector sees a shared part, while it traces the heap
+struct Traced {
+    node: Node,
+    // How many references to this come from other shared parts the heap refers to
+    internal: usize,
+    // The shared parts this refers to, by where they live
+    refers_to: Vec<usize>,
+}
+
+// How many values can be on the heap before the collector runs, at the least
+const MIN_THRESHOLD: usize = 1024;
+
+/// The values programs put on the heap, with a garbage collector freeing them.
+///
+/// Values on the heap are reference counted, which frees most of them as soon
+/// as nothing refers to them anymore. Values referring to each other in a
+/// cycle keep each other alive though, which is what the collector is for.
+///
+/// It works like the cycle collector of CPython: it traces everything the
+/// heap refers to, counting how many references to each part come from the
+/// heap itself. A part with more references than that is referred to from
+/// somewhere else, like a variable, so it's a root. Values that can't be
+/// reached from any root are garbage, even if they refer to each other.
+/// This doesn't need to know where the running program keeps its values.
+#[derive(Debug)]
+pub struct Heap {
+    // Every value put on the heap that may still be alive, with its id
+    cells: Vec<(usize, Weak<RefCell<Litteral>>)>,
+    stats: GcStats,
+    // How many values can be on the heap before the collector runs again
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            cells: Vec::new(),
+            stats: GcStats::default(),
+            threshold: MIN_THRESHOLD,
+        }
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap::default()
+    }
+
+    /// Put a value on the heap, giving a pointer to it.
+    ///
+    /// Once the heap holds twice as many values as after the last
+    /// collection, this collects garbage first.
+    pub fn alloc(&mut self, value: Litteral) -> Pointer {
+        if self.cells.len() >= self.threshold {
+            self.collect();
+            self.threshold = (2 * self.cells.len()).max(MIN_THRESHOLD);
+        }
+        let id = self.stats.allocated;
+        self.stats.allocated += 1;
+        let cell = Rc::new(RefCell::new(value));
+        self.cells.push((id, Rc::downgrade(&cell)));
+        Pointer { id, cell }
+    }
+
+    /// What the collector did so far
+    pub fn stats(&mut self) -> GcStats {
+        self.sweep();
+        self.stats
+    }
+
+    // Forget the values that reference counting already freed
+    fn sweep(&mut self) {
+        let before = self.cells.len();
+        self.cells.retain(|(_, cell)| cell.strong_count() > 0);
+        self.stats.freed += before - self.cells.len();
+    }
+
+    /// Free the values on the heap that can't be reached anymore, returning
+    /// how many of them there were.
+    ///
+    /// The collector runs by itself as the heap grows, but this lets the
+    /// host, or a program calling `gc()`, run it at any time.
+    pub fn collect(&mut self) -> usize {
+        self.sweep();
+        self.stats.collections += 1;
+        // Every shared part is held exactly once here, which the counts account for
+        let mut traced: HashMap<usize, Traced> = HashMap::new();
+        let mut todo = Vec::with_capacity(self.cells.len());
+        for (id, cell) in &self.cells {
+            if let Some(cell) = cell.upgrade() {
+                let node = Node::Pointer(Pointer { id: *id, cell });
+                let (at, _) = node.as_trace().shared();
+                let refers_to = Vec::new();
+                traced.insert(at, Traced { node, internal: 0, refers_to });
+                todo.push(at);
+            }
+        }
+        let cells = todo.clone();
+        while let Some(at) = todo.pop() {
+            let mut found = Vec::new();
+            traced[&at].node.as_trace().trace(&mut |value| shared_in(value, &mut found));
+            let mut refers_to = Vec::with_capacity(found.len());
+            for node in found {
+                let (to, _) = node.as_trace().shared();
+                refers_to.push(to);
+                match traced.get_mut(&to) {
+                    Some(part) => part.internal += 1,
+                    None => {
+                        traced.insert(to, Traced { node, internal: 1, refers_to: Vec::new() });
+                        todo.push(to);
+                    }
+                }
+            }
+            traced.get_mut(&at).unwrap().refers_to = refers_to;
+        }
+        // Whatever is reachable from the roots is still in use
+        let mut reachable = HashSet::new();
+        let mut todo: Vec<usize> = traced
+            .iter()
+            .filter(|(_, part)| part.node.as_trace().shared().1 - 1 > part.internal)
+            .map(|(at, _)| *at)
+            .collect();
+        while let Some(at) = todo.pop() {
+            if reachable.insert(at) {
+                todo.extend(&traced[&at].refers_to);
+            }
+        }
+        let garbage: Vec<Pointer> = cells
+            .iter()
+            .filter(|at| !reachable.contains(at))
+            .filter_map(|at| match &traced[at].node {
+                Node::Pointer(pointer) => Some(pointer.clone()),
+                _ => None,
+            })
+            .collect();
+        drop(traced);
+        // Emptying the garbage breaks the cycles keeping it alive, which frees it
+        let values: Vec<Litteral> = garbage.iter().map(|p| p.cell.replace(unit())).collect();
+        drop(values);
+        let collected = garbage.len();
+        drop(garbage);
+        self.stats.collected += collected;
+        self.sweep();
+        collected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cycles_are_collected() {
+        let mut heap = Heap::new();
+        let a = heap.alloc(unit());
+        let b = heap.alloc(Litteral::Pointer(a.clone()));
+        a.set(Litteral::Pointer(b.clone()));
+        let alone = heap.alloc(Litteral::I64(1));
+        drop((a, b, alone));
+        // Reference counting freed the value outside of the cycle already
+        assert_eq!(heap.stats().live(), 2);
+        assert_eq!(heap.collect(), 2);
+        let stats = heap.stats();
+        assert_eq!((stats.allocated, stats.freed, stats.collected), (3, 3, 2));
+    }
+
+    #[test]
+    fn values_reachable_from_outside_are_kept() {
+        let mut heap = Heap::new();
+        let a = heap.alloc(unit());
+        let b = heap.alloc(Litteral::Slice(Slice::new(vec![Litteral::Pointer(a.clone())])));
+        a.set(Litteral::Tuple(vec![Litteral::Pointer(b.clone())]));
+        // The cycle goes through a slice, which is also held from outside the heap
+        let xs = b.get();
+        drop((a, b));
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(heap.stats().live(), 2);
+        drop(xs);
+        assert_eq!(heap.collect(), 2);
+        assert_eq!(heap.stats().live(), 0);
+    }
+
+    #[test]
+    fn functions_reachable_from_outside_are_kept() {
+        let mut heap = Heap::new();
+        let p = heap.alloc(unit());
+        let g = Rc::new(Closure {
+            name: None,
+            args: vec!["n".into()],
+            ret: None,
+            body: Rc::new(Vec::new()),
+            captured: Rc::new(vec![Litteral::Pointer(p.clone())]),
+            slots: 1,
+            code: None,
+        });
+        // The function calls itself through the pointer, and a variable holds it
+        p.set(Litteral::Function(Rc::clone(&g)));
+        let f = Litteral::Function(g);
+        drop(p);
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(heap.stats().live(), 1);
+        drop(f);
+        assert_eq!(heap.collect(), 1);
+        assert_eq!(heap.stats().live(), 0);
+    }
+}
@@ -1,9 +1,24 @@
 use crate::ast::*;
-use std::collections::HashMap;
-use crate::scopes::Scopes;
+use crate::bigint::BigInt;
+use crate::builtins;
+use crate::chan::Chan;
+use crate::closure::Closure;
+use crate::heap::Heap;
+use crate::ir::{self, Expr, Function, Kind, Program, SelectCase, SelectOp};
+use crate::scheduler::{Blocked, Scheduler, Waiting};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::time::{Duration, Instant};
+use crate::map::Map;
+use crate::slice::Slice;
 
-// Unit is used like void in other languages.
-fn unit() -> Litteral {
+/// Unit is used like void in other languages.
+pub fn unit() -> Litteral {
     Litteral::Tuple(vec![])
 }
 
@@ -34,250 +49,1827 @@ impl Context for RealContext {
 #[derive(Clone, Debug, PartialEq)]
 pub struct InterpreterError {
     message: String,
+    // Where in the source this error happened, if we know
+    span: Option<Span>,
+    // Other places involved in this error, with a description of each
+    notes: Vec<(String, Span)>,
+    // The functions being called when this error happened, outermost first
+    trace: Vec<Frame>,
+    // What the program ran out of, if it went over its limits
+    exhausted: Option<Resource>,
+}
+
+impl InterpreterError {
+    /// The message describing what went wrong
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The location in the source code responsible for this error, if known
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Other places involved in this error, like where each task in a deadlock is blocked
+    pub fn notes(&self) -> &[(String, Span)] {
+        &self.notes
+    }
+
+    /// The functions being called when this error happened, outermost first
+    pub fn trace(&self) -> &[Frame] {
+        &self.trace
+    }
+
+    /// What the program ran out of, if this error comes from going over its limits.
+    ///
+    /// These errors can't be recovered from, so the program always stops.
+    pub fn exhausted(&self) -> Option<Resource> {
+        self.exhausted
+    }
+
+    /// Attach a span to this error, unless a more precise one is already there
+    pub fn at(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    /// Add another place involved in this error
+    pub fn with_note(mut self, note: String, span: Span) -> Self {
+        self.notes.push((note, span));
+        self
+    }
+
+    /// Record the functions being called when this error happened, unless
+    /// a function called deeper already did
+    pub fn with_trace(mut self, trace: Vec<Frame>) -> Self {
+        if self.trace.is_empty() {
+            self.trace = trace;
+        }
+        self
+    }
 }
 
 impl From<String> for InterpreterError {
     fn from(message: String) -> Self {
-        InterpreterError { message }
+        InterpreterError {
+            message,
+            span: None,
+            notes: Vec::new(),
+            trace: Vec::new(),
+            exhausted: None,
+        }
     }
 }
 
 impl<'a> From<&'a str> for InterpreterError {
     fn from(message: &'a str) -> Self {
-        InterpreterError {
-            message: String::from(message),
-        }
+        InterpreterError::from(String::from(message))
     }
 }
 
+/// Represents a function being called, as part of a stack trace
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// The function being called, like `func main`
+    pub function: String,
+    /// Where this function was called, which is unknown for main
+    pub call_site: Option<Span>,
+}
+
 /// A wrapper type for results that fail in an interpreter
 pub type InterpreterResult<T> = Result<T, InterpreterError>;
 
-fn fail<T, S: Into<String>>(message: S) -> InterpreterResult<T> {
-    Err(InterpreterError {
-        message: message.into(),
+/// Limits on what a running program can use, which make it fail once it goes over them
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// How many calls a task can be inside of at once, counting the one it
+    /// starts with. Calls in tail position don't count, since they replace the
+    /// function making them.
+    pub max_depth: usize,
+    /// How many steps the program can take, which is one for each expression
+    /// it evaluates, in both the interpreter and the VM.
+    pub fuel: Option<u64>,
+    /// How many bytes the program can allocate for values, in total
+    pub memory: Option<usize>,
+    /// How long the program can run for
+    pub time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 10_000,
+            fuel: None,
+            memory: None,
+            time: None,
+        }
+    }
+}
+
+/// Something a program can run out of, going over its limits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    Fuel,
+    Memory,
+    Time,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Fuel => write!(f, "fuel"),
+            Resource::Memory => write!(f, "memory"),
+            Resource::Time => write!(f, "time"),
+        }
+    }
+}
+
+// Checking the clock takes a while, so it's only done every so many steps
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Keeps track of what a running program used, failing once it goes over its limits.
+///
+/// The host can read what was used once the program is done, like how much fuel is left.
+#[derive(Clone, Copy, Debug)]
+pub struct Meter {
+    limits: Limits,
+    steps: u64,
+    allocated: usize,
+    started: Instant,
+    // What the program ran out of, after which every step fails
+    out_of: Option<Resource>,
+}
+
+impl Meter {
+    /// Start measuring a program, whose time starts running right away
+    pub fn new(limits: Limits) -> Self {
+        Meter {
+            limits,
+            steps: 0,
+            allocated: 0,
+            started: Instant::now(),
+            out_of: None,
+        }
+    }
+
+    /// How many steps the program took
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// How much fuel the program has left, unless it has as much as it needs
+    pub fn fuel(&self) -> Option<u64> {
+        Some(self.limits.fuel?.saturating_sub(self.steps))
+    }
+
+    /// Roughly how many bytes the program allocated for values
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn exhausted(&mut self, resource: Resource) -> InterpreterError {
+        self.out_of = Some(resource);
+        let mut error = InterpreterError::from(format!("The program ran out of {}", resource));
+        error.exhausted = Some(resource);
+        error
+    }
+
+    // Take a step, using up some fuel
+    pub(crate) fn step(&mut self) -> InterpreterResult<()> {
+        self.take_steps(1)
+    }
+
+    // Take several steps at once, like taking each of them in turn would
+    pub(crate) fn take_steps(&mut self, steps: u64) -> InterpreterResult<()> {
+        if let Some(resource) = self.out_of {
+            return Err(self.exhausted(resource));
+        }
+        if let Some(fuel) = self.fuel().filter(|fuel| *fuel < steps) {
+            self.steps += fuel;
+            return Err(self.exhausted(Resource::Fuel));
+        }
+        let before = self.steps;
+        self.steps += steps;
+        match self.limits.time {
+            Some(time)
+                if self.steps / STEPS_PER_CLOCK_CHECK != before / STEPS_PER_CLOCK_CHECK
+                    && self.elapsed() > time =>
+            {
+                Err(self.exhausted(Resource::Time))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Account for a value the program just created
+    pub(crate) fn allocate(&mut self, value: &Litteral) -> InterpreterResult<()> {
+        self.allocate_bytes(allocation(value))
+    }
+
+    fn allocate_bytes(&mut self, bytes: usize) -> InterpreterResult<()> {
+        self.allocated = self.allocated.saturating_add(bytes);
+        match self.limits.memory {
+            Some(memory) if self.allocated > memory => Err(self.exhausted(Resource::Memory)),
+            _ => Ok(()),
+        }
+    }
+
+    // Fail before creating a value taking some number of bytes, if that would go
+    // over the limit. The value is only accounted for once it's created, with allocate.
+    pub(crate) fn reserve(&mut self, bytes: usize) -> InterpreterResult<()> {
+        match self.limits.memory {
+            Some(memory) if self.allocated.saturating_add(bytes) > memory => {
+                Err(self.exhausted(Resource::Memory))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Calls going deeper than the limit fail, instead of using up the stack of the host
+    pub(crate) fn check_depth(&self, depth: usize) -> InterpreterResult<()> {
+        let max_depth = self.limits.max_depth;
+        if depth < max_depth {
+            return Ok(());
+        }
+        let message = format!("Stack overflow, with more than {} calls running", max_depth);
+        Err(InterpreterError::from(message))
+    }
+}
+
+// Roughly how many bytes creating a value allocated, not counting the values
+// it shares with others, like a subslice does with the slice it's part of
+fn allocation(value: &Litteral) -> usize {
+    let cell = std::mem::size_of::<Litteral>();
+    match value {
+        Litteral::Str(s) => s.len(),
+        Litteral::Tuple(litterals) => litterals.len() * cell,
+        Litteral::Slice(slice) => slice.len() * cell,
+        Litteral::Map(map) => map.len() * 2 * cell,
+        Litteral::Function(closure) => closure.captured.len() * cell,
+        // Big integers grow with every multiplication, so their digits count too
+        Litteral::BigInt(i) => cell + i.limbs() * std::mem::size_of::<u32>(),
+        Litteral::Option(Some(_))
+        | Litteral::Result(_)
+        | Litteral::Newtype(_, _)
+        | Litteral::Chan(_)
+        | Litteral::Pointer(_) => cell,
+        _ => 0,
+    }
+}
+
+/// Roughly how many bytes applying an operator allocates, for the ones whose
+/// result can be much bigger than their operands, like concatenating strings
+pub fn op_allocation(op: Op, left: &Litteral, right: &Litteral) -> usize {
+    match (op, left, right) {
+        (Op::Add, Litteral::Str(l), Litteral::Str(r)) => l.len().saturating_add(r.len()),
+        _ => 0,
+    }
+}
+
+// Roughly how many bytes calling a builtin allocates, for the ones whose result
+// can be much bigger than their arguments, so they can fail before allocating it
+fn builtin_allocation(name: &str, args: &[Litteral]) -> usize {
+    let cell = std::mem::size_of::<Litteral>();
+    match (name, args) {
+        ("repeat", [Litteral::Str(s), Litteral::I64(n)]) => {
+            s.len().saturating_mul((*n).max(0) as usize)
+        }
+        ("join", [Litteral::Slice(parts), Litteral::Str(sep)]) => {
+            let parts = parts.values();
+            let seps = sep.len().saturating_mul(parts.len().saturating_sub(1));
+            parts.iter().fold(seps, |size, part| match part {
+                Litteral::Str(s) => size.saturating_add(s.len()),
+                _ => size,
+            })
+        }
+        ("replace", [Litteral::Str(s), Litteral::Str(from), Litteral::Str(to)]) => {
+            // An empty pattern matches around every character
+            let matches = match from.as_str() {
+                "" => s.chars().count() + 1,
+                from => s.matches(from).count(),
+            };
+            s.len().saturating_add(matches.saturating_mul(to.len()))
+        }
+        // Appending only allocates when the values don't fit in the backing array
+        ("append", [Litteral::Slice(slice), _]) => {
+            slice.grown_cap().map_or(0, |cap| cap.saturating_mul(cell))
+        }
+        _ => 0,
+    }
+}
+
+fn fail<T, E: From<InterpreterError>, S: Into<String>>(message: S) -> Result<T, E> {
+    Err(InterpreterError::from(message.into()).into())
+}
+
+/// Evaluation can stop before producing a value, either because of an error,
+/// or because we're returning early from a function.
+#[derive(Debug)]
+pub enum Unwind {
+    Error(InterpreterError),
+    Return(Box<Litteral>),
+    /// The function is done, and the call it makes in tail position replaces it
+    TailCall(Box<TailCall>),
+}
+
+/// A call in tail position, whose arguments have already been evaluated
+#[derive(Debug)]
+pub struct TailCall {
+    closure: Rc<Closure>,
+    args: Vec<Litteral>,
+    span: Span,
+}
+
+// The local and captured variables of a function, kept aside while it calls another
+type Variables = (Vec<Litteral>, Vec<Litteral>);
+
+impl Unwind {
+    fn at(self, span: Span) -> Self {
+        match self {
+            Unwind::Error(e) => Unwind::Error(e.at(span)),
+            returning => returning,
+        }
+    }
+}
+
+impl From<InterpreterError> for Unwind {
+    fn from(e: InterpreterError) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+/// The result of evaluating part of a program
+pub type EvalResult<T> = Result<T, Unwind>;
+
+// Evaluation happens inside of futures, which lets tasks be suspended anywhere
+type LocalFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+fn boxed<'a, F>(f: F) -> LocalFuture<'a, EvalResult<Litteral>>
+where
+    F: Future<Output = EvalResult<Litteral>> + 'a,
+{
+    Box::pin(f)
+}
+
+// Expressions that don't evaluate others still need a future
+fn ready<'a>(res: EvalResult<Litteral>) -> LocalFuture<'a, EvalResult<Litteral>> {
+    Box::pin(std::future::ready(res))
+}
+
+// Apply integer arithmetic, failing instead of overflowing or dividing by zero
+fn checked_arith(op: Op, l: i64, r: i64) -> InterpreterResult<i64> {
+    let res = match op {
+        Op::Add => l.checked_add(r),
+        Op::Sub => l.checked_sub(r),
+        Op::Mul => l.checked_mul(r),
+        Op::Div | Op::Mod if r == 0 => return fail("Division by zero"),
+        Op::Div => l.checked_div(r),
+        Op::Mod => l.checked_rem(r),
+        _ => unreachable!(),
+    };
+    res.ok_or_else(|| format!("Integer overflow in {:?} of {} and {}", op, l, r).into())
+}
+
+// Apply arithmetic on big integers, which can only fail by dividing by zero
+fn big_arith(op: Op, l: &BigInt, r: &BigInt) -> InterpreterResult<BigInt> {
+    let res = match op {
+        Op::Add => Some(l + r),
+        Op::Sub => Some(l - r),
+        Op::Mul => Some(l * r),
+        Op::Div => l.checked_div(r),
+        Op::Mod => l.checked_rem(r),
+        _ => unreachable!(),
+    };
+    res.ok_or_else(|| "Division by zero".into())
+}
+
+// Apply one of the ordering operators, like <=
+fn compare<T: PartialOrd>(op: Op, l: &T, r: &T) -> bool {
+    match op {
+        Op::Leq => l <= r,
+        Op::Less => l < r,
+        Op::Geq => l >= r,
+        Op::Greater => l > r,
+        _ => unreachable!(),
+    }
+}
+
+// Apply one of the bitwise operators, which only work on I64
+fn bit_op(op: Op, l: i64, r: i64) -> InterpreterResult<i64> {
+    let res = match op {
+        Op::BitAnd => l & r,
+        Op::BitOr => l | r,
+        Op::BitXor => l ^ r,
+        Op::AndNot => l & !r,
+        Op::Shl | Op::Shr if r < 0 => return fail(format!("Negative shift count {}", r)),
+        // Like in Go, shifting by the width of an integer or more isn't an error
+        Op::Shl if r >= 64 => 0,
+        Op::Shr if r >= 64 => l >> 63,
+        Op::Shl => l << r,
+        Op::Shr => l >> r,
+        _ => unreachable!(),
+    };
+    Ok(res)
+}
+
+/// Apply a binary operation to two fully evaluated operands.
+///
+/// This is separate from the interpreter, since the type checker also uses
+/// it to evaluate constant expressions. Their operands only need to have the
+/// same type, so an operator that doesn't apply to it fails here, like in
+/// `const Yes = true + true`.
+pub fn apply_op(op: Op, left: Litteral, right: Litteral) -> InterpreterResult<Litteral> {
+    if let (Litteral::I64(l), Litteral::I64(r)) = (&left, &right) {
+        return int_op(op, *l, *r);
+    }
+    match op {
+        Op::Equal => Ok(Litteral::Bool(left == right)),
+        Op::NotEqual => Ok(Litteral::Bool(left != right)),
+        Op::Leq | Op::Less | Op::Geq | Op::Greater => match (left, right) {
+            (Litteral::BigInt(l), Litteral::BigInt(r)) => {
+                Ok(Litteral::Bool(compare(op, &l, &r)))
+            }
+            // Strings are ordered byte by byte, which is also by code point
+            (Litteral::Str(l), Litteral::Str(r)) => Ok(Litteral::Bool(compare(op, &l, &r))),
+            (Litteral::Rune(l), Litteral::Rune(r)) => Ok(Litteral::Bool(compare(op, &l, &r))),
+            (Litteral::U8(l), Litteral::U8(r)) => Ok(Litteral::Bool(compare(op, &l, &r))),
+            (l, r) => fail(format!(
+                "Op {:?} only works on numbers, strings, runes and bytes, but got {:?} and {:?}",
+                op, l, r
+            )),
+        },
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => match (left, right) {
+            (Litteral::BigInt(l), Litteral::BigInt(r)) => {
+                Ok(Litteral::BigInt(big_arith(op, &l, &r)?))
+            }
+            (Litteral::Str(l), Litteral::Str(r)) if op == Op::Add => Ok(Litteral::Str(l + &r)),
+            (l, r) => fail(format!(
+                "Op {:?} only works on numbers, but got {:?} and {:?}",
+                op, l, r
+            )),
+        },
+        Op::BitAnd | Op::BitOr | Op::BitXor | Op::AndNot | Op::Shl | Op::Shr => fail(format!(
+            "Op {:?} only works on I64, but got {:?} and {:?}",
+            op, left, right
+        )),
+    }
+}
+
+/// Apply a binary operation to two I64s, which every operator applies to
+pub fn int_op(op: Op, left: i64, right: i64) -> InterpreterResult<Litteral> {
+    match op {
+        Op::Equal => Ok(Litteral::Bool(left == right)),
+        Op::NotEqual => Ok(Litteral::Bool(left != right)),
+        Op::Leq | Op::Less | Op::Geq | Op::Greater => {
+            Ok(Litteral::Bool(compare(op, &left, &right)))
+        }
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => {
+            Ok(Litteral::I64(checked_arith(op, left, right)?))
+        }
+        Op::BitAnd | Op::BitOr | Op::BitXor | Op::AndNot | Op::Shl | Op::Shr => {
+            Ok(Litteral::I64(bit_op(op, left, right)?))
+        }
+    }
+}
+
+/// Negate a fully evaluated number.
+///
+/// Like `apply_op`, this is shared with the type checker.
+pub fn negate(operand: Litteral) -> InterpreterResult<Litteral> {
+    match operand {
+        Litteral::I64(i) => match i.checked_neg() {
+            Some(i) => Ok(Litteral::I64(i)),
+            None => fail(format!("Integer overflow in negation of {}", i)),
+        },
+        Litteral::BigInt(i) => Ok(Litteral::BigInt(-&i)),
+        wrong_type => fail(format!(
+            "The operator - only applies to numbers, but got {:?}",
+            wrong_type
+        )),
+    }
+}
+
+/// Apply the unary operator !, which the type checker only allows on booleans
+pub fn logical_not(operand: Litteral) -> Litteral {
+    match operand {
+        Litteral::Bool(b) => Litteral::Bool(!b),
+        wrong_type => unreachable!("The operator ! applied to {:?}", wrong_type),
+    }
+}
+
+/// Read the value a pointer points to, like `*p`
+pub fn deref(pointer: Litteral) -> Litteral {
+    match pointer {
+        Litteral::Pointer(p) => p.get(),
+        wrong_type => unreachable!("Dereferencing {:?}, which isn't a pointer", wrong_type),
+    }
+}
+
+/// Change the value a pointer points to, giving that value back
+pub fn assign_deref(pointer: Litteral, value: Litteral) -> Litteral {
+    match pointer {
+        Litteral::Pointer(p) => {
+            p.set(value.clone());
+            value
+        }
+        wrong_type => unreachable!("Assigning through {:?}, which isn't a pointer", wrong_type),
+    }
+}
+
+/// Check the condition of an if else expression, which the type checker made sure is a Bool
+pub fn condition(cond: Litteral) -> bool {
+    match cond {
+        Litteral::Bool(b) => b,
+        wrong_type => unreachable!("Expected boolean in condition, but got {:?}", wrong_type),
+    }
+}
+
+/// Check the left side of a short circuiting operator, giving its result if
+/// the right side doesn't need to be evaluated
+pub fn short_circuit(op: BoolOp, left: Litteral) -> Option<Litteral> {
+    let left = match left {
+        Litteral::Bool(b) => b,
+        wrong_type => unreachable!("Expected boolean with {:?}, but found {:?}", op, wrong_type),
+    };
+    let short = match op {
+        BoolOp::And => false,
+        BoolOp::Or => true,
+    };
+    if left == short {
+        Some(Litteral::Bool(short))
+    } else {
+        None
+    }
+}
+
+// Convert a value that should be a valid index
+fn to_index(index: Litteral) -> InterpreterResult<usize> {
+    match index {
+        Litteral::I64(i) if i >= 0 => Ok(i as usize),
+        Litteral::I64(i) => fail(format!("Negative index {}", i)),
+        wrong_type => fail(format!("Indices must be I64, but got {:?}", wrong_type)),
+    }
+}
+
+/// Read an element of a slice, a byte of a string, or an entry of a map, like `xs[i]`
+pub fn index(target: Litteral, index: Litteral) -> InterpreterResult<Litteral> {
+    match target {
+        Litteral::Slice(slice) => {
+            let index = to_index(index)?;
+            slice.get(index).ok_or_else(|| {
+                format!(
+                    "Index {} out of bounds for slice of length {}",
+                    index,
+                    slice.len()
+                )
+                .into()
+            })
+        }
+        // Strings are indexed by byte, not by rune
+        Litteral::Str(s) => {
+            let index = to_index(index)?;
+            match s.as_bytes().get(index) {
+                Some(b) => Ok(Litteral::U8(*b)),
+                None => fail(format!(
+                    "Index {} out of bounds for string of length {}",
+                    index,
+                    s.len()
+                )),
+            }
+        }
+        Litteral::Map(map) => Ok(Litteral::Option(map.get(&index).map(Box::new))),
+        wrong_type => fail(format!(
+            "Only slices, strings and maps can be indexed, but got {:?}",
+            wrong_type
+        )),
+    }
+}
+
+/// Assign to an element of a slice or an entry of a map, like `xs[i] = 3`
+pub fn assign_index(
+    target: Litteral,
+    index: Litteral,
+    value: Litteral,
+) -> InterpreterResult<Litteral> {
+    match target {
+        Litteral::Slice(slice) => {
+            let index = to_index(index)?;
+            if slice.set(index, value.clone()) {
+                Ok(value)
+            } else {
+                fail(format!(
+                    "Index {} out of bounds for slice of length {}",
+                    index,
+                    slice.len()
+                ))
+            }
+        }
+        Litteral::Map(map) => {
+            map.insert(index, value.clone());
+            Ok(value)
+        }
+        wrong_type => fail(format!(
+            "Only slices and maps can be indexed, but got {:?}",
+            wrong_type
+        )),
+    }
+}
+
+/// Take part of a slice or a string, like `xs[a:b]`, defaulting to the whole length.
+///
+/// Strings are sliced by byte offsets, which must fall on character boundaries.
+pub fn subslice(
+    target: Litteral,
+    from: Option<Litteral>,
+    to: Option<Litteral>,
+) -> InterpreterResult<Litteral> {
+    let len = match &target {
+        Litteral::Slice(slice) => slice.len(),
+        Litteral::Str(s) => s.len(),
+        wrong_type => {
+            return fail(format!(
+                "Only slices and strings can be sliced, but got {:?}",
+                wrong_type
+            ))
+        }
+    };
+    let from = from.map_or(Ok(0), to_index)?;
+    let to = to.map_or(Ok(len), to_index)?;
+    let sliced = match &target {
+        Litteral::Slice(slice) => slice.slice(from, to).map(Litteral::Slice),
+        Litteral::Str(s) => match s.get(from..to) {
+            Some(sub) => Some(Litteral::Str(sub.into())),
+            None if from <= to && to <= len => {
+                return fail(format!(
+                    "Slice bounds {}:{} aren't on character boundaries of {:?}",
+                    from, to, s
+                ))
+            }
+            None => None,
+        },
+        _ => unreachable!(),
+    };
+    sliced.ok_or_else(|| {
+        format!("Slice bounds {}:{} out of range for length {}", from, to, len).into()
     })
 }
 
-/// Represents an Interpreter holding context allowing it to function
+/// Read one of the elements of a tuple, or the value wrapped by a newtype
+pub fn field(value: Litteral, field: usize) -> InterpreterResult<Litteral> {
+    match value {
+        Litteral::Tuple(mut litterals) if field < litterals.len() => {
+            Ok(litterals.swap_remove(field))
+        }
+        Litteral::Newtype(_, inner) if field == 0 => Ok(*inner),
+        wrong_type => fail(format!("Can't read field {} of {:?}", field, wrong_type)),
+    }
+}
+
+/// Split a tuple into its elements, to destructure it into a pattern
+pub fn unpack(value: Litteral, len: usize) -> InterpreterResult<Vec<Litteral>> {
+    match value {
+        Litteral::Tuple(litterals) if litterals.len() == len => Ok(litterals),
+        value => fail(format!(
+            "Can't destructure {:?} into a tuple of {} elements",
+            value, len
+        )),
+    }
+}
+
+/// What the operator ? does with an Option or a Result
+pub enum Unwrapped {
+    /// The value inside of Some or Ok
+    Value(Litteral),
+    /// None or Err, which the function returns early with
+    Return(Litteral),
+}
+
+/// Unwrap the value of the operator ?, which the type checker made sure is
+/// an Option or a Result
+pub fn try_unwrap(value: Litteral) -> Unwrapped {
+    match value {
+        Litteral::Option(Some(l)) | Litteral::Result(Ok(l)) => Unwrapped::Value(*l),
+        none @ Litteral::Option(None) => Unwrapped::Return(none),
+        err @ Litteral::Result(Err(_)) => Unwrapped::Return(err),
+        wrong_type => unreachable!("The operator ? applied to {:?}", wrong_type),
+    }
+}
+
+// Instead of producing None or Err, ? returns them from the current function
+fn unwrap_or_return(value: Litteral) -> EvalResult<Litteral> {
+    match try_unwrap(value) {
+        Unwrapped::Value(value) => Ok(value),
+        Unwrapped::Return(early) => Err(Unwind::Return(Box::new(early))),
+    }
+}
+
+/// Call a builtin function, or a newtype to wrap a value in it.
+///
+/// This doesn't include builtins that depend on the state of a running program,
+/// like print, or the ones using channels. Builtins producing big values fail
+/// before producing them if that would go over the memory limit of the meter,
+/// which accounts for what the result allocated too.
+pub fn call_builtin(
+    name: &str,
+    args: &[Litteral],
+    newtypes: &HashSet<String>,
+    meter: &mut Meter,
+) -> Option<InterpreterResult<Litteral>> {
+    if let ("panic", [Litteral::Str(message)]) = (name, args) {
+        return Some(fail(message.clone()));
+    }
+    let bytes = builtin_allocation(name, args);
+    if let Err(e) = meter.reserve(bytes) {
+        return Some(Err(e));
+    }
+    let res = match builtins::call(name, args) {
+        Some(res) => res,
+        None if newtypes.contains(name) => match args {
+            [arg] => Ok(Litteral::Newtype(name.into(), Box::new(arg.clone()))),
+            _ => fail(format!("The newtype {} wraps exactly one value", name)),
+        },
+        None => return None,
+    };
+    // The slice append gives shares the values it had with the one it got,
+    // so only the larger array it may have copied them into counts
+    let allocated = match name {
+        "append" => meter.allocate_bytes(bytes),
+        _ => res.as_ref().map_or(Ok(()), |value| meter.allocate(value)),
+    };
+    Some(allocated.and(res))
+}
+
+/// Find the value of a type that's empty, like 0 or "", if there's one.
+///
+/// Newtypes can contain themselves, so this keeps track of the ones seen.
+pub fn zero_value(
+    types: &HashMap<String, TypeDecl>,
+    typ: &TypeName,
+    seen: &mut Vec<String>,
+) -> Option<Litteral> {
+    match typ {
+        TypeName::Name(t) => match t.as_str() {
+            "I64" => Some(Litteral::I64(0)),
+            "BigInt" => Some(Litteral::BigInt(BigInt::from(0))),
+            "String" => Some(Litteral::Str(String::new())),
+            "Bool" => Some(Litteral::Bool(false)),
+            "Rune" => Some(Litteral::Rune('\0')),
+            "U8" => Some(Litteral::U8(0)),
+            _ => {
+                let decl = types.get(t)?;
+                if !decl.newtype {
+                    return zero_value(types, &decl.typ, seen);
+                }
+                if seen.contains(t) {
+                    return None;
+                }
+                seen.push(t.clone());
+                let inner = zero_value(types, &decl.typ, seen)?;
+                Some(Litteral::Newtype(t.clone(), Box::new(inner)))
+            }
+        },
+        TypeName::Tuple(ts) => {
+            let zeros: Option<Vec<Litteral>> =
+                ts.iter().map(|t| zero_value(types, t, seen)).collect();
+            Some(Litteral::Tuple(zeros?))
+        }
+        TypeName::Slice(_) => Some(Litteral::Slice(Slice::new(Vec::new()))),
+        TypeName::Apply(t, _) if t == "Map" => Some(Litteral::Map(Map::new())),
+        TypeName::Apply(t, _) if t == "Option" => Some(Litteral::Option(None)),
+        // Results, channels, functions, and pointers have nothing that could stand for empty
+        TypeName::Apply(_, _) | TypeName::Func(_, _) | TypeName::Pointer(_) => None,
+    }
+}
+
+/// The result of a function whose error was recovered by a deferred call,
+/// which is the zero value of its return type
+pub fn recovered(
+    types: &HashMap<String, TypeDecl>,
+    ret: &Option<TypeName>,
+    function: &str,
+) -> InterpreterResult<Litteral> {
+    let zero = match ret {
+        None => Some(unit()),
+        Some(ret) => zero_value(types, ret, &mut Vec::new()),
+    };
+    zero.ok_or_else(|| {
+        format!("Recovered in {}, but its return type has no zero value", function).into()
+    })
+}
+
+// Refer to a top level function as a value
+fn named_closure(f: &Function) -> Closure {
+    Closure {
+        name: Some(f.name.clone()),
+        args: f.args.iter().map(|(name, _)| name.clone()).collect(),
+        ret: f.ret.clone(),
+        body: Rc::new(f.body.clone()),
+        captured: Rc::new(Vec::new()),
+        slots: f.locals.slots,
+        code: None,
+    }
+}
+
+// The state shared by every task of a running program
+struct Shared<C> {
+    ctx: RefCell<C>,
+    // Keeping track of functions by their name, as values sharing their body
+    functions: HashMap<String, Rc<Closure>>,
+    // The values of constants, which were evaluated ahead of time
+    consts: HashMap<String, Litteral>,
+    // The names of newtypes, which can be called to wrap a value
+    newtypes: HashSet<String>,
+    // Type declarations by name, used to find zero values
+    types: HashMap<String, TypeDecl>,
+    scheduler: RefCell<Scheduler<PendingCall>>,
+    meter: RefCell<Meter>,
+    heap: RefCell<Heap>,
+}
+
+/// Represents an Interpreter running one of the tasks of a program
 struct Interpreter<C> {
-    ctx: C,
-    // This allows us to implement lexical scoping
-    scopes: Scopes<Litteral>,
-    // Keeping track of functions by their name
-    functions: HashMap<String, Function>,
+    shared: Rc<Shared<C>>,
+    // The task being run, which is how the scheduler refers to it
+    task: usize,
+    // The local variables of the function being run, by slot
+    locals: Vec<Litteral>,
+    // The variables captured by the function being run, which it can change
+    captured: Vec<Litteral>,
+    // The calls deferred by each function currently running, innermost last
+    deferred: Vec<Vec<PendingCall>>,
+    // The functions currently running, innermost last
+    frames: Vec<Frame>,
+    // The error a function is failing with, while its deferred calls run
+    panicking: Option<Panic>,
+}
+
+/// An error that deferred calls can still recover from
+pub struct Panic {
+    /// How many frames there are while running a deferred call, since only
+    /// the deferred call itself can recover, not the functions it calls
+    pub depth: usize,
+    pub error: InterpreterError,
+}
+
+/// The function being called by a pending call
+pub enum Callee {
+    Closure(Rc<Closure>),
+    // A top level function, builtin, or newtype
+    Named(String),
+}
+
+impl fmt::Display for Callee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Callee::Closure(closure) => write!(f, "{}", closure),
+            Callee::Named(name) => write!(f, "func {}", name),
+        }
+    }
+}
+
+/// A call queued with `defer` or `go`, whose arguments have already been evaluated
+pub struct PendingCall {
+    pub callee: Callee,
+    pub args: Vec<Litteral>,
+    pub span: Span,
+}
+
+// Tasks are named after the function they start with
+impl fmt::Display for PendingCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.callee)
+    }
+}
+
+// This suspends the task awaiting it once, letting the scheduler run others
+#[derive(Default)]
+struct Suspend {
+    suspended: bool,
+}
+
+impl Future for Suspend {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<()> {
+        if self.suspended {
+            Poll::Ready(())
+        } else {
+            self.suspended = true;
+            Poll::Pending
+        }
+    }
 }
 
 impl<C: Context> Interpreter<C> {
-    fn new(ctx: C) -> Self {
+    fn new(shared: Rc<Shared<C>>, task: usize) -> Self {
         Interpreter {
-            ctx,
-            scopes: Scopes::new(),
-            functions: HashMap::new(),
+            shared,
+            task,
+            locals: Vec::new(),
+            captured: Vec::new(),
+            deferred: Vec::new(),
+            frames: Vec::new(),
+            panicking: None,
         }
+    }
 
+    // Take a step evaluating an expression, which fails there if the program runs out
+    fn step(&self, e: &Expr) -> InterpreterResult<()> {
+        let res = self.shared.meter.borrow_mut().step();
+        res.map_err(|error| match ir::span(&e.kind) {
+            Some(span) => error.at(span),
+            None => error,
+        })
+    }
+
+    fn allocate(&self, value: Litteral) -> InterpreterResult<Litteral> {
+        self.shared.meter.borrow_mut().allocate(&value)?;
+        Ok(value)
+    }
+
+    // Put a value on the heap, giving a pointer to it
+    fn make_ref(&self, value: Litteral) -> InterpreterResult<Litteral> {
+        let pointer = self.shared.heap.borrow_mut().alloc(value);
+        self.allocate(Litteral::Pointer(pointer))
     }
 
     fn print_litteral(&mut self, l: &Litteral) {
-        self.ctx.print(&format!("{}\n", l));
-    }
-
-    fn read_name(&mut self, name: &str) -> InterpreterResult<&Litteral> {
-        self.scopes
-            .get(name)
-            .ok_or_else(|| format!("Trying to use undefined variable {}", name).into())
-    }
-
-    fn eval_block(&mut self, exprs: &[Expr]) -> InterpreterResult<Litteral> {
-        let mut res = unit();
-        for e in exprs {
-            res = self.eval_expr(e)?;
-        }
-        Ok(res)
-    }
-
-    fn eval_bin_op(&mut self, op: Op, left: &Expr, right: &Expr) -> InterpreterResult<Litteral> {
-        let left = self.eval_expr(left)?;
-        let right = self.eval_expr(right)?;
-        match op {
-            Op::Equal => Ok(Litteral::Bool(left == right)),
-            Op::NotEqual => Ok(Litteral::Bool(left != right)),
-            // All of these only work on ints
-            Op::Leq
-            | Op::Less
-            | Op::Geq
-            | Op::Greater
-            | Op::Add
-            | Op::Sub
-            | Op::Mul
-            | Op::Div
-            | Op::Mod => {
-                let (l, r) = match (left, right) {
-                    (Litteral::I64(l), Litteral::I64(r)) => Ok((l, r)),
-                    (l, r) => fail(format!(
-                        "Op {:?} only works on I64, but got {:?} and {:?}",
-                        op, l, r
-                    )),
-                }?;
-                let res = match op {
-                    Op::Leq => Litteral::Bool(l <= r),
-                    Op::Less => Litteral::Bool(l < r),
-                    Op::Geq => Litteral::Bool(l >= r),
-                    Op::Greater => Litteral::Bool(l > r),
-                    Op::Add => Litteral::I64(l + r),
-                    Op::Sub => Litteral::I64(l - r),
-                    Op::Mul => Litteral::I64(l * r),
-                    Op::Div => Litteral::I64(l / r),
-                    Op::Mod => Litteral::I64(l % r),
-                    _ => unreachable!(),
-                };
-                Ok(res)
+        self.shared.ctx.borrow_mut().print(&format!("{}\n", l));
+    }
+
+    fn read_var(&self, var: &Var) -> InterpreterResult<Litteral> {
+        let name = &var.name;
+        let value = match var.binding {
+            Binding::Local(slot) => Some(self.locals[slot].clone()),
+            Binding::Captured(i) => Some(self.captured[i].clone()),
+            Binding::Global => self
+                .shared
+                .consts
+                .get(name)
+                .cloned()
+                .or_else(|| builtins::constant(name))
+                .or_else(|| self.shared.functions.get(name).cloned().map(Litteral::Function)),
+            Binding::Unresolved => return fail(format!("Running {} before resolving it", name)),
+        };
+        value.ok_or_else(|| format!("Trying to use undefined variable {}", name).into())
+    }
+
+    fn write_var(&mut self, var: &Var, value: Litteral) -> InterpreterResult<()> {
+        match var.binding {
+            Binding::Local(slot) => self.locals[slot] = value,
+            Binding::Captured(i) => self.captured[i] = value,
+            binding => return fail(format!("Assigning to {}, which is {:?}", var.name, binding)),
+        }
+        Ok(())
+    }
+
+    // Create an anonymous function, capturing the current values of the variables it uses
+    fn make_closure(
+        &self,
+        args: &[(String, TypeName)],
+        ret: &Option<TypeName>,
+        body: &[Expr],
+        locals: &Locals,
+    ) -> InterpreterResult<Litteral> {
+        let mut captured = Vec::with_capacity(locals.captures.len());
+        for var in &locals.captures {
+            captured.push(self.read_var(var)?);
+        }
+        Ok(Litteral::Function(Rc::new(Closure {
+            name: None,
+            args: args.iter().map(|(name, _)| name.clone()).collect(),
+            ret: ret.clone(),
+            body: Rc::new(body.to_vec()),
+            captured: Rc::new(captured),
+            slots: locals.slots,
+            code: None,
+        })))
+    }
+
+    // The last expression of a block is in tail position if the block is
+    async fn eval_block(&mut self, exprs: &[Expr], tail: bool) -> EvalResult<Litteral> {
+        let (last, init) = match exprs.split_last() {
+            Some(split) => split,
+            None => return Ok(unit()),
+        };
+        for e in init {
+            self.eval_expr(e).await?;
+        }
+        match tail {
+            true => self.eval_tail(last).await,
+            false => self.eval_expr(last).await,
+        }
+    }
+
+    async fn eval_args(&mut self, args: &[Expr]) -> EvalResult<Vec<Litteral>> {
+        let mut litterals = Vec::with_capacity(args.len());
+        for a in args {
+            litterals.push(self.eval_expr(a).await?);
+        }
+        Ok(litterals)
+    }
+
+    async fn eval_callee(&mut self, target: &Expr, span: Span) -> EvalResult<Rc<Closure>> {
+        match self.eval_expr(target).await? {
+            Litteral::Function(closure) => Ok(closure),
+            wrong_type => {
+                let message = format!("Only functions can be called, but got {:?}", wrong_type);
+                Err(Unwind::from(InterpreterError::from(message)).at(span))
             }
         }
     }
 
-    fn eval_conditional_op(
+    async fn eval_bin_op(
         &mut self,
-        op: BoolOp,
+        op: Op,
         left: &Expr,
         right: &Expr,
+        span: Span,
+    ) -> EvalResult<Litteral> {
+        let left = self.eval_expr(left).await?;
+        let right = self.eval_expr(right).await?;
+        Ok(self.bin_op(op, left, right, span)?)
+    }
+
+    fn bin_op(
+        &self,
+        op: Op,
+        left: Litteral,
+        right: Litteral,
+        span: Span,
     ) -> InterpreterResult<Litteral> {
-        let left = match self.eval_expr(left)? {
-            Litteral::Bool(b) => b,
-            wrong_type => {
-                return fail(format!(
-                    "Expected boolean with {:?}, but found {:?}",
-                    op, wrong_type
-                ));
-            }
-        };
-        let short = match op {
-            BoolOp::And => false,
-            BoolOp::Or => true,
-        };
-        if left == short {
-            return Ok(Litteral::Bool(short));
-        };
-        self.eval_expr(right)
+        let reserved = self.shared.meter.borrow_mut().reserve(op_allocation(op, &left, &right));
+        let res = reserved.and_then(|_| apply_op(op, left, right));
+        res.and_then(|value| self.allocate(value)).map_err(|e| e.at(span))
     }
 
-    fn eval_if_else(
+    fn assign_op(
+        &mut self,
+        op: Op,
+        var: &Var,
+        current: Litteral,
+        right: Litteral,
+        span: Span,
+    ) -> InterpreterResult<Litteral> {
+        let result = self.bin_op(op, current, right, span)?;
+        self.write_var(var, result.clone())?;
+        Ok(result)
+    }
+
+    async fn eval_conditional_op(
+        &mut self,
+        op: BoolOp,
+        left: &Expr,
+        right: &Expr,
+    ) -> EvalResult<Litteral> {
+        let left = self.eval_expr(left).await?;
+        match short_circuit(op, left) {
+            Some(res) => Ok(res),
+            None => self.eval_expr(right).await,
+        }
+    }
+
+    async fn eval_if_else(
         &mut self,
         cond: &Expr,
         if_part: &[Expr],
         else_part: &[Expr],
-    ) -> InterpreterResult<Litteral> {
-        let cond = match self.eval_expr(cond)? {
-            Litteral::Bool(b) => b,
-            wrong_type => {
-                return fail(format!(
-                    "Expected boolean in condition, but got {:?}",
-                    wrong_type
-                ))
-            }
-        };
+        tail: bool,
+    ) -> EvalResult<Litteral> {
+        let cond = self.eval_expr(cond).await?;
         // Because we haven't evaluated the left and right parts, this does the right thing
-        if cond {
-            self.eval_block(if_part)
-        } else {
-            self.eval_block(else_part)
-        }
+        let branch = if condition(cond) { if_part } else { else_part };
+        self.eval_block(branch, tail).await
     }
 
-    fn eval_expr(&mut self, e: &Expr) -> InterpreterResult<Litteral> {
-        match e {
-            Expr::Call(name, args) => {
-                let mut litterals: Vec<Litteral> = Vec::new();
-                for a in args {
-                    litterals.push(self.eval_expr(a)?);
-                }
-                self.call_function(name, &litterals)
+    // Whether calling a name runs a top level function, rather than a builtin
+    // or a newtype, which come first in call_function
+    fn calls_function(&self, name: &str) -> bool {
+        let shadowed = name == "print" || builtins::is_builtin(name);
+        let newtype = self.shared.newtypes.contains(name);
+        !shadowed && !newtype && self.shared.functions.contains_key(name)
+    }
+
+    // Evaluate the last expression of a function, whose value it returns.
+    //
+    // Calls found there are tail calls, which replace the function making them.
+    fn eval_tail<'a>(&'a mut self, e: &'a Expr) -> LocalFuture<'a, EvalResult<Litteral>> {
+        if let Err(error) = self.step(e) {
+            return ready(Err(error.into()));
+        }
+        match &e.kind {
+            Kind::Call(name, args, span) if self.calls_function(name) => boxed(async move {
+                let args = self.eval_args(args).await?;
+                let closure = self.shared.functions[name].clone();
+                self.tail_call(closure, args, *span).await
+            }),
+            Kind::CallValue(target, args, span) => boxed(async move {
+                let closure = self.eval_callee(target, *span).await?;
+                let args = self.eval_args(args).await?;
+                self.tail_call(closure, args, *span).await
+            }),
+            Kind::Block(exprs) => boxed(self.eval_block(exprs, true)),
+            Kind::IfElse(cond, if_part, else_part) => {
+                boxed(self.eval_if_else(cond, if_part, else_part, true))
             }
-            Expr::Litt(l) => Ok(l.clone()),
-            Expr::Name(n) => Ok(self.read_name(n)?.clone()),
-            Expr::Declare(name, e) => {
-                let result = self.eval_expr(e)?;
-                self.scopes.create(name, result.clone());
-                Ok(result)
+            Kind::Select(cases, default, span) => {
+                boxed(self.eval_select(cases, default, *span, true))
             }
-            Expr::Assign(name, e) => {
-                let result = self.eval_expr(e)?;
-                if self.scopes.set(name, result.clone()) {
-                    Ok(result)
-                } else {
-                    fail(format!("Trying to assign to undeclared variable {}", name))
+            _ => self.eval_kind(e),
+        }
+    }
+
+    // Replace the running function with a call it makes in tail position.
+    //
+    // Its deferred calls have to run once the call returns, so a function that
+    // deferred some calls the function normally instead.
+    async fn tail_call(
+        &mut self,
+        closure: Rc<Closure>,
+        args: Vec<Litteral>,
+        span: Span,
+    ) -> EvalResult<Litteral> {
+        if !self.can_replace(&closure, &args) {
+            let res = self.call_closure(&closure, &args, Some(span)).await;
+            return res.map_err(|e| e.at(span));
+        }
+        Err(Unwind::TailCall(Box::new(TailCall {
+            closure,
+            args,
+            span,
+        })))
+    }
+
+    // A function that deferred some calls has to run them after its tail call returns
+    fn can_replace(&self, closure: &Closure, args: &[Litteral]) -> bool {
+        let deferring = self.deferred.last().is_some_and(|calls| !calls.is_empty());
+        !deferring && args.len() == closure.args.len()
+    }
+
+    // Evaluating an expression takes a step, using up some fuel
+    fn eval_expr<'a>(&'a mut self, e: &'a Expr) -> LocalFuture<'a, EvalResult<Litteral>> {
+        match self.step(e) {
+            Ok(()) => self.eval_kind(e),
+            Err(error) => ready(Err(error.into())),
+        }
+    }
+
+    // This is boxed, since evaluating an expression recursively evaluates others.
+    //
+    // Each kind of expression gets its own future, rather than sharing one for
+    // the whole match, which keeps the stack used by deep recursion small.
+    fn eval_kind<'a>(&'a mut self, e: &'a Expr) -> LocalFuture<'a, EvalResult<Litteral>> {
+        match &e.kind {
+            Kind::Call(name, args, span) => boxed(async move {
+                let litterals = self.eval_args(args).await?;
+                let res = self.call_function(name, &litterals, Some(*span)).await;
+                // Errors without a span of their own happened in the call
+                res.map_err(|e| e.at(*span))
+            }),
+            Kind::CallValue(target, args, span) => boxed(async move {
+                let closure = self.eval_callee(target, *span).await?;
+                let litterals = self.eval_args(args).await?;
+                let res = self.call_closure(&closure, &litterals, Some(*span)).await;
+                res.map_err(|e| e.at(*span))
+            }),
+            Kind::Defer(call, span) => boxed(async move {
+                let deferred = self.eval_pending_call(call, *span).await.map_err(|e| e.at(*span))?;
+                match self.deferred.last_mut() {
+                    Some(frame) => frame.push(deferred),
+                    None => return fail("Deferring a call outside of a function"),
                 }
+                Ok(unit())
+            }),
+            Kind::Go(call, span) => boxed(async move {
+                let started = self.eval_pending_call(call, *span).await.map_err(|e| e.at(*span))?;
+                self.shared.scheduler.borrow_mut().start(started);
+                self.yield_now().await;
+                Ok(unit())
+            }),
+            Kind::Send(chan, value, span) => boxed(async move {
+                let chan = self.eval_chan(chan).await?;
+                let value = self.eval_expr(value).await?;
+                self.send(&chan, value, *span).await.map_err(|e| e.at(*span))?;
+                Ok(unit())
+            }),
+            Kind::Recv(chan, span) => boxed(async move {
+                let chan = self.eval_chan(chan).await?;
+                let received = self.recv(&chan, *span).await;
+                Ok(Litteral::Option(received.map(Box::new)))
+            }),
+            Kind::Select(cases, default, span) => {
+                boxed(self.eval_select(cases, default, *span, false))
             }
-            Expr::Block(exprs) => {
-                self.scopes.enter(true);
-                let res = self.eval_block(exprs);
-                self.scopes.exit();
-                res
+            Kind::Ref(e) => boxed(async move {
+                let value = self.eval_expr(e).await?;
+                Ok(self.make_ref(value)?)
+            }),
+            Kind::Deref(pointer, _) => boxed(async move {
+                let pointer = self.eval_expr(pointer).await?;
+                Ok(deref(pointer))
+            }),
+            Kind::AssignDeref(pointer, e, _) => boxed(async move {
+                let pointer = self.eval_expr(pointer).await?;
+                let value = self.eval_expr(e).await?;
+                Ok(assign_deref(pointer, value))
+            }),
+            Kind::Lambda(args, ret, body, locals) => ready(
+                self.make_closure(args, ret, body, locals)
+                    .and_then(|closure| self.allocate(closure))
+                    .map_err(Unwind::from),
+            ),
+            Kind::Litt(l) => ready(Ok(l.clone())),
+            Kind::Name(var) => ready(self.read_var(var).map_err(Unwind::from)),
+            // Whether a variable can be assigned to was checked ahead of time
+            Kind::Declare(var, e) | Kind::DeclareImmutable(var, e) => boxed(async move {
+                let result = self.eval_expr(e).await?;
+                self.write_var(var, result.clone())?;
+                Ok(result)
+            }),
+            Kind::DeclarePattern(pattern, e, span) => boxed(async move {
+                let result = self.eval_expr(e).await?;
+                self.bind_pattern(pattern, result.clone())
+                    .map_err(|e| e.at(*span))?;
+                Ok(result)
+            }),
+            Kind::Assign(var, e) => boxed(async move {
+                let result = self.eval_expr(e).await?;
+                self.write_var(var, result.clone())?;
+                Ok(result)
+            }),
+            Kind::AssignOp(op, var, e, span) => boxed(async move {
+                let current = self.read_var(var)?;
+                let right = self.eval_expr(e).await?;
+                Ok(self.assign_op(*op, var, current, right, *span)?)
+            }),
+            Kind::Block(exprs) => boxed(self.eval_block(exprs, false)),
+            Kind::BinOp(op, left, right, span) => boxed(self.eval_bin_op(*op, left, right, *span)),
+            Kind::ConditionalOp(op, left, right) => boxed(self.eval_conditional_op(*op, left, right)),
+            Kind::IfElse(cond, if_part, right_part) => {
+                boxed(self.eval_if_else(cond, if_part, right_part, false))
             }
-            Expr::BinOp(op, left, right) => self.eval_bin_op(*op, left, right),
-            Expr::ConditionalOp(op, left, right) => self.eval_conditional_op(*op, left, right),
-            Expr::IfElse(cond, if_part, right_part) => self.eval_if_else(cond, if_part, right_part),
-            Expr::Not(expr) => match self.eval_expr(expr)? {
-                Litteral::Bool(b) => Ok(Litteral::Bool(!b)),
-                wrong_type => fail(format!(
-                    "The operator ! only applies to Bool, but got {:?}",
-                    wrong_type
-                )),
-            },
-            Expr::MakeTuple(exprs) => {
+            Kind::Not(expr) => boxed(async move {
+                let operand = self.eval_expr(expr).await?;
+                Ok(logical_not(operand))
+            }),
+            Kind::Neg(expr, span) => boxed(async move {
+                let operand = self.eval_expr(expr).await?;
+                Ok(negate(operand).map_err(|e| e.at(*span))?)
+            }),
+            Kind::MakeTuple(exprs) => boxed(async move {
                 let mut litterals = Vec::new();
                 for e in exprs {
-                    litterals.push(self.eval_expr(e)?);
+                    litterals.push(self.eval_expr(e).await?);
+                }
+                Ok(self.allocate(Litteral::Tuple(litterals))?)
+            }),
+            Kind::MakeSlice(exprs) => boxed(async move {
+                let mut litterals = Vec::with_capacity(exprs.len());
+                for e in exprs {
+                    litterals.push(self.eval_expr(e).await?);
+                }
+                Ok(self.allocate(Litteral::Slice(Slice::new(litterals)))?)
+            }),
+            Kind::MakeMap(entries) => boxed(async move {
+                let map = Map::new();
+                for (k, v) in entries {
+                    let k = self.eval_expr(k).await?;
+                    let v = self.eval_expr(v).await?;
+                    map.insert(k, v);
+                }
+                Ok(self.allocate(Litteral::Map(map))?)
+            }),
+            Kind::Index(target, index, span) => boxed(async move {
+                self.eval_indexing(target, index).await.map_err(|e| e.at(*span))
+            }),
+            Kind::Subslice(target, from, to, span) => boxed(async move {
+                self.eval_subslice(target, from.as_deref(), to.as_deref())
+                    .await
+                    .map_err(|e| e.at(*span))
+            }),
+            Kind::AssignIndex(target, index, e, span) => boxed(async move {
+                self.eval_assign_index(target, index, e).await.map_err(|e| e.at(*span))
+            }),
+            Kind::Field(e, field, span) => boxed(async move {
+                let value = self.eval_expr(e).await?;
+                Ok(self::field(value, *field).map_err(|e| e.at(*span))?)
+            }),
+            // Instead of producing None or Err, we return these from the current function
+            Kind::Try(e, _) => boxed(async move {
+                let value = self.eval_expr(e).await?;
+                unwrap_or_return(value)
+            }),
+        }
+    }
+
+    // Declare the names in a pattern, destructuring a value
+    fn bind_pattern(&mut self, pattern: &Pattern, value: Litteral) -> InterpreterResult<()> {
+        match pattern {
+            Pattern::Name(var) => self.write_var(var, value)?,
+            Pattern::Ignore => {}
+            Pattern::Tuple(patterns) => {
+                for (p, l) in patterns.iter().zip(unpack(value, patterns.len())?) {
+                    self.bind_pattern(p, l)?;
                 }
-                Ok(Litteral::Tuple(litterals))
             }
         }
+        Ok(())
+    }
+
+    async fn eval_indexing(&mut self, target: &Expr, index: &Expr) -> EvalResult<Litteral> {
+        let target = self.eval_expr(target).await?;
+        let index = self.eval_expr(index).await?;
+        Ok(self::index(target, index)?)
     }
 
-    fn call_function(&mut self, name: &str, args: &[Litteral]) -> InterpreterResult<Litteral> {
-        self.scopes.enter(false);
+    async fn eval_assign_index(
+        &mut self,
+        target: &Expr,
+        index: &Expr,
+        e: &Expr,
+    ) -> EvalResult<Litteral> {
+        let target = self.eval_expr(target).await?;
+        let index = self.eval_expr(index).await?;
+        let value = self.eval_expr(e).await?;
+        Ok(assign_index(target, index, value)?)
+    }
+
+    async fn eval_subslice(
+        &mut self,
+        target: &Expr,
+        from: Option<&Expr>,
+        to: Option<&Expr>,
+    ) -> EvalResult<Litteral> {
+        let target = self.eval_expr(target).await?;
+        let from = match from {
+            Some(e) => Some(self.eval_expr(e).await?),
+            None => None,
+        };
+        let to = match to {
+            Some(e) => Some(self.eval_expr(e).await?),
+            None => None,
+        };
+        Ok(subslice(target, from, to)?)
+    }
+
+    // Call print or one of the builtins, if that's what a name refers to
+    fn try_builtin(
+        &mut self,
+        name: &str,
+        args: &[Litteral],
+    ) -> Option<InterpreterResult<Litteral>> {
         if name == "print" {
-            let arg = args.get(0).ok_or("Not enough arguments to print")?;
+            let arg = match args.first() {
+                Some(arg) => arg,
+                None => return Some(fail("Not enough arguments to print")),
+            };
             self.print_litteral(arg);
-            self.scopes.exit();
-            return Ok(unit());
+            return Some(Ok(unit()));
         };
-        let res = match self.functions.get(name) {
-            None => fail(format!("Trying to call undefined function {}", name)),
-            Some(f) => {
-                if args.len() != f.args.len() {
-                    return fail(format!(
-                        "Incorrect number of arguments to function {}\n.Expected {}, but got {}",
-                        f.name,
-                        f.args.len(),
-                        args.len()
-                    ));
+        let res = match (name, args) {
+            ("chan", [Litteral::I64(cap)]) => {
+                let chan = self.shared.scheduler.borrow_mut().make_chan(*cap);
+                chan.and_then(|chan| self.allocate(Litteral::Chan(chan)))
+            }
+            ("close", [Litteral::Chan(chan)]) => {
+                self.shared.scheduler.borrow_mut().close(chan).map(|_| unit())
+            }
+            ("gc", []) => {
+                let collected = self.shared.heap.borrow_mut().collect();
+                Ok(Litteral::I64(collected as i64))
+            }
+            ("recover", []) => {
+                let recovered = match self.panicking.take() {
+                    // Running out of resources stops the program, whatever it does
+                    Some(panic)
+                        if panic.depth == self.frames.len() && panic.error.exhausted.is_none() =>
+                    {
+                        Some(panic.error.message)
+                    }
+                    panicking => {
+                        self.panicking = panicking;
+                        None
+                    }
                 };
-                for (i, (arg, _)) in f.args.iter().enumerate() {
-                    self.scopes.create(arg.clone(), args[i].clone());
-                }
-                // We need to clone, because Rust doesn't know that evaluation
-                // won't change the contents of f
-                let body = f.body.clone();
-                self.eval_block(&body)
+                let recovered = recovered.map(|m| Box::new(Litteral::Str(m)));
+                self.allocate(Litteral::Option(recovered))
+            }
+            _ => {
+                let newtypes = &self.shared.newtypes;
+                call_builtin(name, args, newtypes, &mut self.shared.meter.borrow_mut())?
             }
         };
-        self.scopes.exit();
+        Some(res)
+    }
+
+    fn function(&self, name: &str) -> InterpreterResult<Rc<Closure>> {
+        match self.shared.functions.get(name) {
+            Some(closure) => Ok(closure.clone()),
+            None => fail(format!("Trying to call undefined function {}", name)),
+        }
+    }
+
+    async fn call_function(
+        &mut self,
+        name: &str,
+        args: &[Litteral],
+        call_site: Option<Span>,
+    ) -> EvalResult<Litteral> {
+        if let Some(res) = self.try_builtin(name, args) {
+            return Ok(res?);
+        }
+        self.call_closure(&*self.function(name)?, args, call_site).await
+    }
+
+    async fn call_closure(
+        &mut self,
+        closure: &Closure,
+        args: &[Litteral],
+        call_site: Option<Span>,
+    ) -> EvalResult<Litteral> {
+        let outer = self.enter(closure, args, call_site)?;
+        let mut res = self.eval_block(&closure.body, true).await;
+        // Tail calls run in the frame of the function they replace, so any
+        // number of them can follow each other without using up the stack
+        let mut replaced_by = None;
+        while let Err(Unwind::TailCall(call)) = res {
+            self.replace(&call);
+            res = self.eval_block(&call.closure.body, true).await;
+            replaced_by = Some(call);
+        }
+        let closure = replaced_by.as_ref().map_or(closure, |call| &call.closure);
+        // Most functions don't defer any calls, so running them is kept out
+        // of the future every call makes, which would be several times bigger
+        let res = match self.deferred.last().is_some_and(|calls| !calls.is_empty()) {
+            true => Box::pin(self.run_deferred(closure, res)).await,
+            false => {
+                self.deferred.pop();
+                res
+            }
+        };
+        self.leave(outer, res)
+    }
+
+    // Start running a function in a frame of its own, returning the variables
+    // of the function calling it
+    fn enter(
+        &mut self,
+        closure: &Closure,
+        args: &[Litteral],
+        call_site: Option<Span>,
+    ) -> InterpreterResult<Variables> {
+        if args.len() != closure.args.len() {
+            return fail(format!(
+                "Incorrect number of arguments to function {}\n.Expected {}, but got {}",
+                closure,
+                closure.args.len(),
+                args.len()
+            ));
+        };
+        self.shared.meter.borrow().check_depth(self.frames.len())?;
+        let mut locals = args.to_vec();
+        locals.resize(closure.slots, unit());
+        let outer_locals = std::mem::replace(&mut self.locals, locals);
+        let captured = closure.captured.as_ref().clone();
+        let outer_captured = std::mem::replace(&mut self.captured, captured);
+        self.frames.push(Frame {
+            function: closure.to_string(),
+            call_site,
+        });
+        self.deferred.push(Vec::new());
+        Ok((outer_locals, outer_captured))
+    }
+
+    // Replace the running function with the one it calls in tail position
+    fn replace(&mut self, call: &TailCall) {
+        let mut locals = call.args.clone();
+        locals.resize(call.closure.slots, unit());
+        self.locals = locals;
+        self.captured = call.closure.captured.as_ref().clone();
+        *self.frames.last_mut().unwrap() = Frame {
+            function: call.closure.to_string(),
+            call_site: Some(call.span),
+        };
+    }
+
+    // Go back to the function that called the one we're leaving
+    fn leave(&mut self, outer: Variables, res: EvalResult<Litteral>) -> EvalResult<Litteral> {
+        let (outer_locals, outer_captured) = outer;
+        self.locals = outer_locals;
+        self.captured = outer_captured;
+        // The innermost function an error goes through is where it happened
+        let res = match res {
+            Err(Unwind::Error(e)) => Err(Unwind::Error(e.with_trace(self.frames.clone()))),
+            Err(Unwind::Return(l)) => Ok(*l),
+            res => res,
+        };
+        self.frames.pop();
         res
     }
 
-    fn interpret(&mut self, ast: &AST) -> InterpreterResult<Litteral> {
-        for f in &ast.functions {
-            if self.functions.insert(f.name.clone(), f.clone()).is_some() {
-                return fail(format!("Redefinition of function {}", f.name));
+    // Let the other tasks run for a while
+    async fn yield_now(&mut self) {
+        Suspend::default().await
+    }
+
+    // Wait until one of the channels this task is blocked on changes
+    async fn block(&mut self, doing: &'static str, channels: Vec<(usize, Waiting)>, span: Span) {
+        let blocked = Blocked {
+            doing,
+            channels,
+            span,
+        };
+        self.shared.scheduler.borrow_mut().block(self.task, blocked);
+        Suspend::default().await
+    }
+
+    // Wake the tasks waiting to do some operation on a channel
+    fn wake(&self, chan: &Chan, waiting: Waiting) {
+        self.shared.scheduler.borrow_mut().wake(chan, waiting);
+    }
+
+    async fn eval_chan(&mut self, e: &Expr) -> EvalResult<Chan> {
+        match self.eval_expr(e).await? {
+            Litteral::Chan(chan) => Ok(chan),
+            wrong_type => fail(format!("Only channels can send and receive, but got {:?}", wrong_type)),
+        }
+    }
+
+    async fn send(&mut self, chan: &Chan, value: Litteral, span: Span) -> InterpreterResult<()> {
+        loop {
+            if chan.is_closed() {
+                return fail("Sending on a closed channel");
+            }
+            if chan.can_send() {
+                break;
+            }
+            self.block("sending on a channel", vec![(chan.id(), Waiting::Send)], span).await;
+        }
+        chan.send(value);
+        self.wake(chan, Waiting::Recv);
+        self.yield_now().await;
+        Ok(())
+    }
+
+    // Receive a value from a channel, waiting for one to be sent if needed
+    async fn recv(&mut self, chan: &Chan, span: Span) -> Option<Litteral> {
+        loop {
+            if let Some(value) = chan.recv() {
+                self.wake(chan, Waiting::Send);
+                self.yield_now().await;
+                return Some(value);
             }
+            if chan.is_closed() {
+                return None;
+            }
+            // Waiting receivers make room on the channel, so senders can try again
+            chan.set_waiting(true);
+            self.wake(chan, Waiting::Send);
+            self.block("receiving from a channel", vec![(chan.id(), Waiting::Recv)], span).await;
+            chan.set_waiting(false);
         }
-        self.call_function("main", &[])
+    }
+
+    // The channels and values of a select are evaluated once, in order, like in Go.
+    //
+    // Then one of the cases that can happen right away is picked at random, and
+    // if none can, this runs the default block, or waits for one of them.
+    async fn eval_select(
+        &mut self,
+        cases: &[SelectCase],
+        default: &Option<Vec<Expr>>,
+        span: Span,
+        tail: bool,
+    ) -> EvalResult<Litteral> {
+        let mut ops = Vec::with_capacity(cases.len());
+        for case in cases {
+            match &case.op {
+                SelectOp::Recv(_, chan) => ops.push((self.eval_chan(chan).await?, None)),
+                SelectOp::Send(chan, value) => {
+                    let chan = self.eval_chan(chan).await?;
+                    ops.push((chan, Some(self.eval_expr(value).await?)));
+                }
+            }
+        }
+        let chosen = loop {
+            let ready: Vec<usize> = (0..ops.len())
+                .filter(|&i| match &ops[i] {
+                    (chan, None) => chan.can_recv(),
+                    (chan, Some(_)) => chan.can_send() || chan.is_closed(),
+                })
+                .collect();
+            if !ready.is_empty() {
+                let mut scheduler = self.shared.scheduler.borrow_mut();
+                break ready[scheduler.pick(ready.len())];
+            }
+            if let Some(default) = default {
+                return self.eval_block(default, tail).await;
+            }
+            let mut channels = Vec::with_capacity(ops.len());
+            for (chan, value) in &ops {
+                if value.is_none() {
+                    chan.set_waiting(true);
+                    self.wake(chan, Waiting::Send);
+                    channels.push((chan.id(), Waiting::Recv));
+                } else {
+                    channels.push((chan.id(), Waiting::Send));
+                }
+            }
+            self.block("waiting in a select", channels, span).await;
+            for (chan, value) in &ops {
+                if value.is_none() {
+                    chan.set_waiting(false);
+                }
+            }
+        };
+        let case = &cases[chosen];
+        let (chan, value) = ops.swap_remove(chosen);
+        match (&case.op, value) {
+            (SelectOp::Recv(var, _), None) => {
+                let received = chan.recv();
+                if received.is_some() {
+                    self.wake(&chan, Waiting::Send);
+                }
+                self.yield_now().await;
+                if let Some(var) = var {
+                    self.write_var(var, Litteral::Option(received.map(Box::new)))?;
+                }
+                self.eval_block(&case.body, tail).await
+            }
+            (_, value) => match self.send(&chan, value.unwrap_or_else(unit), case.span).await {
+                Ok(()) => self.eval_block(&case.body, tail).await,
+                Err(e) => Err(e.at(case.span).into()),
+            },
+        }
+    }
+
+    // Evaluate the function and arguments of a call being deferred
+    async fn eval_pending_call(&mut self, call: &Expr, span: Span) -> EvalResult<PendingCall> {
+        let (callee, args) = match &call.kind {
+            Kind::Call(name, args, _) => (Callee::Named(name.clone()), args),
+            Kind::CallValue(target, args, _) => match self.eval_expr(target).await? {
+                Litteral::Function(closure) => (Callee::Closure(closure), args),
+                wrong_type => {
+                    return fail(format!("Only functions can be called, but got {:?}", wrong_type))
+                }
+            },
+            _ => return fail("Only function calls can be deferred"),
+        };
+        Ok(PendingCall {
+            callee,
+            args: self.eval_args(args).await?,
+            span,
+        })
+    }
+
+    // Run the calls deferred by the function we're leaving, most recent first.
+    //
+    // These run even if the function is unwinding because of an error. An error
+    // in a deferred call replaces the result, but the remaining calls still run.
+    // A deferred call can also stop an error with recover, in which case the
+    // function returns the zero value of its return type.
+    async fn run_deferred(
+        &mut self,
+        closure: &Closure,
+        mut res: EvalResult<Litteral>,
+    ) -> EvalResult<Litteral> {
+        let deferred = self.deferred.pop().unwrap_or_default();
+        // Deferred calls can themselves call functions deferring calls
+        let outer = self.panicking.take();
+        for call in deferred.into_iter().rev() {
+            // Programs stop right away once they run out of resources, even
+            // in the middle of running deferred calls
+            if let Err(Unwind::Error(e)) = &res {
+                if e.exhausted.is_some() {
+                    break;
+                }
+            }
+            let panicked = match res {
+                Err(Unwind::Error(error)) => {
+                    let depth = self.frames.len() + 1;
+                    self.panicking = Some(Panic { depth, error });
+                    res = Ok(unit());
+                    true
+                }
+                _ => false,
+            };
+            let called = match &call.callee {
+                Callee::Closure(closure) => {
+                    Box::pin(self.call_closure(closure, &call.args, Some(call.span))).await
+                }
+                Callee::Named(name) => {
+                    Box::pin(self.call_function(name, &call.args, Some(call.span))).await
+                }
+            };
+            match self.panicking.take() {
+                Some(panic) => res = Err(Unwind::Error(panic.error)),
+                None if panicked => {
+                    let function = closure.to_string();
+                    let types = &self.shared.types;
+                    res = recovered(types, &closure.ret, &function).map_err(Unwind::from);
+                }
+                None => {}
+            }
+            if let Err(e) = called {
+                res = Err(e.at(call.span));
+            }
+        }
+        self.panicking = outer;
+        res
+    }
+}
+
+// Run a task to completion, starting with a call to its function
+async fn run_task<C: Context>(
+    shared: Rc<Shared<C>>,
+    task: usize,
+    call: PendingCall,
+) -> EvalResult<Litteral> {
+    let mut interpreter = Interpreter::new(shared, task);
+    // Only main has no call site, since tasks started with go start from a call
+    let call_site = match task {
+        0 => None,
+        _ => Some(call.span),
+    };
+    match &call.callee {
+        Callee::Closure(closure) => interpreter.call_closure(closure, &call.args, call_site).await,
+        Callee::Named(name) => interpreter.call_function(name, &call.args, call_site).await,
     }
 }
 
-/// Interpret a program given some context for the interpreter to use.
-pub fn interpret<C: Context>(ctx: C, ast: &AST) -> InterpreterResult<Litteral> {
-    let mut interpreter = Interpreter::new(ctx);
-    interpreter.interpret(ast)
+/// Interpret a program, using a seed to decide how its tasks are scheduled,
+/// and failing once it goes over the limits of a meter.
+///
+/// The program ends once main returns, even if other tasks are still running.
+/// Calls recurse on the stack of the thread running this, which needs to be
+/// big enough for as many calls as the limits allow. The meter tells what the
+/// program used afterwards, and the heap holds the values it put there.
+pub fn interpret_limited<C: Context>(
+    ctx: C,
+    program: &Program,
+    seed: u64,
+    meter: &mut Meter,
+    heap: &mut Heap,
+) -> InterpreterResult<Litteral> {
+    let newtypes = program.types.iter().filter(|t| t.newtype);
+    let mut functions = HashMap::new();
+    for f in &program.functions {
+        if functions.insert(f.name.clone(), Rc::new(named_closure(f))).is_some() {
+            return fail(format!("Redefinition of function {}", f.name));
+        }
+    }
+    let shared = Rc::new(Shared {
+        ctx: RefCell::new(ctx),
+        functions,
+        consts: program.consts.clone(),
+        newtypes: newtypes.map(|t| t.name.clone()).collect(),
+        types: program.types.iter().map(|t| (t.name.clone(), t.clone())).collect(),
+        scheduler: RefCell::new(Scheduler::new(seed)),
+        meter: RefCell::new(*meter),
+        heap: RefCell::new(std::mem::take(heap)),
+    });
+    let res = run_tasks(&shared);
+    *meter = *shared.meter.borrow();
+    *heap = shared.heap.replace(Heap::new());
+    res
 }
+
+// Run the tasks of a program, starting with main, until it's done
+fn run_tasks<C: Context>(shared: &Rc<Shared<C>>) -> InterpreterResult<Litteral> {
+    let main = PendingCall {
+        callee: Callee::Named(String::from("main")),
+        args: Vec::new(),
+        span: Span::default(),
+    };
+    shared.scheduler.borrow_mut().start(main);
+    let mut tasks: Vec<Option<LocalFuture<EvalResult<Litteral>>>> = Vec::new();
+    let mut cx = TaskContext::from_waker(Waker::noop());
+    loop {
+        let started = shared.scheduler.borrow_mut().take_started();
+        for (id, call) in started {
+            debug_assert_eq!(id, tasks.len());
+            tasks.push(Some(Box::pin(run_task(Rc::clone(shared), id, call))));
+        }
+        let next = shared.scheduler.borrow_mut().next();
+        let id = match next {
+            Some(id) => id,
+            None => return Err(shared.scheduler.borrow().deadlock()),
+        };
+        let task = tasks[id].as_mut().expect("Finished tasks are never scheduled");
+        if let Poll::Ready(res) = task.as_mut().poll(&mut cx) {
+            tasks[id] = None;
+            shared.scheduler.borrow_mut().finish(id);
+            match res {
+                Ok(l) if id == 0 => return Ok(l),
+                Err(Unwind::Return(l)) if id == 0 => return Ok(*l),
+                Err(Unwind::Error(e)) => return Err(e),
+                _ => {}
+            }
+        }
+    }
+}
+
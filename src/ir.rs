@@ -0,0 +1,648 @@
+use crate::ast::{BoolOp, Litteral, Locals, Op, Pattern, Span, TypeDecl, TypeName, Var};
+use crate::typer::Type;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Represents an expression of a type checked program, along with its type.
+///
+/// This mirrors the expressions of the AST, so passes running after the type
+/// checker never need to work out types again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub kind: Kind,
+    /// The type of the value this expression produces, as the type checker found it
+    pub typ: Type,
+}
+
+impl Expr {
+    pub fn new(kind: Kind, typ: Type) -> Self {
+        Expr { kind, typ }
+    }
+}
+
+/// Represents what an expression does, like in `ast::Expr`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    /// Call a function by name, which may be a builtin or a newtype
+    Call(String, Vec<Expr>, Span),
+    /// Call the function an expression evaluates to
+    CallValue(Box<Expr>, Vec<Expr>, Span),
+    /// An anonymous function, with the variables it captures
+    Lambda(Vec<(String, TypeName)>, Option<TypeName>, Vec<Expr>, Locals),
+    Litt(Litteral),
+    Declare(Var, Box<Expr>),
+    DeclareImmutable(Var, Box<Expr>),
+    DeclarePattern(Pattern, Box<Expr>, Span),
+    Assign(Var, Box<Expr>),
+    AssignOp(Op, Var, Box<Expr>, Span),
+    Block(Vec<Expr>),
+    BinOp(Op, Box<Expr>, Box<Expr>, Span),
+    ConditionalOp(BoolOp, Box<Expr>, Box<Expr>),
+    IfElse(Box<Expr>, Vec<Expr>, Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>, Span),
+    MakeTuple(Vec<Expr>),
+    MakeSlice(Vec<Expr>),
+    MakeMap(Vec<(Expr, Expr)>),
+    Index(Box<Expr>, Box<Expr>, Span),
+    Subslice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>, Span),
+    AssignIndex(Box<Expr>, Box<Expr>, Box<Expr>, Span),
+    Field(Box<Expr>, usize, Span),
+    Try(Box<Expr>, Span),
+    Defer(Box<Expr>, Span),
+    Go(Box<Expr>, Span),
+    Send(Box<Expr>, Box<Expr>, Span),
+    Recv(Box<Expr>, Span),
+    Select(Vec<SelectCase>, Option<Vec<Expr>>, Span),
+    Ref(Box<Expr>),
+    Deref(Box<Expr>, Span),
+    AssignDeref(Box<Expr>, Box<Expr>, Span),
+    Name(Var),
+}
+
+/// Represents one of the cases of a select
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectCase {
+    pub op: SelectOp,
+    pub body: Vec<Expr>,
+    pub span: Span,
+}
+
+/// Represents the channel operation a select case waits for
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectOp {
+    Recv(Option<Var>, Expr),
+    Send(Expr, Expr),
+}
+
+/// The expressions directly inside of another, in the order they run
+pub fn children(kind: &Kind) -> Vec<&Expr> {
+    match kind {
+        Kind::Litt(_) | Kind::Name(_) => Vec::new(),
+        Kind::Call(_, exprs, _)
+        | Kind::Lambda(_, _, exprs, _)
+        | Kind::Block(exprs)
+        | Kind::MakeTuple(exprs)
+        | Kind::MakeSlice(exprs) => exprs.iter().collect(),
+        Kind::CallValue(target, args, _) => std::iter::once(&**target).chain(args).collect(),
+        Kind::Declare(_, e)
+        | Kind::DeclareImmutable(_, e)
+        | Kind::DeclarePattern(_, e, _)
+        | Kind::Assign(_, e)
+        | Kind::AssignOp(_, _, e, _)
+        | Kind::Not(e)
+        | Kind::Neg(e, _)
+        | Kind::Field(e, _, _)
+        | Kind::Try(e, _)
+        | Kind::Defer(e, _)
+        | Kind::Go(e, _)
+        | Kind::Recv(e, _)
+        | Kind::Ref(e)
+        | Kind::Deref(e, _) => vec![e],
+        Kind::BinOp(_, left, right, _)
+        | Kind::ConditionalOp(_, left, right)
+        | Kind::Index(left, right, _)
+        | Kind::Send(left, right, _)
+        | Kind::AssignDeref(left, right, _) => vec![left, right],
+        Kind::IfElse(cond, if_part, else_part) => {
+            std::iter::once(&**cond).chain(if_part).chain(else_part).collect()
+        }
+        Kind::MakeMap(entries) => entries.iter().flat_map(|(k, v)| vec![k, v]).collect(),
+        Kind::Subslice(target, from, to, _) => {
+            std::iter::once(&**target).chain(from.as_deref()).chain(to.as_deref()).collect()
+        }
+        Kind::AssignIndex(target, index, e, _) => vec![target, index, e],
+        Kind::Select(cases, default, _) => {
+            let mut exprs = Vec::new();
+            for case in cases {
+                match &case.op {
+                    SelectOp::Recv(_, chan) => exprs.push(chan),
+                    SelectOp::Send(chan, value) => exprs.extend(vec![chan, value]),
+                }
+                exprs.extend(&case.body);
+            }
+            exprs.extend(default.iter().flatten());
+            exprs
+        }
+    }
+}
+
+/// Where an expression is in the source, if it's one of the kinds that can fail
+pub fn span(kind: &Kind) -> Option<Span> {
+    match kind {
+        Kind::Call(_, _, span)
+        | Kind::CallValue(_, _, span)
+        | Kind::DeclarePattern(_, _, span)
+        | Kind::AssignOp(_, _, _, span)
+        | Kind::BinOp(_, _, _, span)
+        | Kind::Neg(_, span)
+        | Kind::Index(_, _, span)
+        | Kind::Subslice(_, _, _, span)
+        | Kind::AssignIndex(_, _, _, span)
+        | Kind::Field(_, _, span)
+        | Kind::Try(_, span)
+        | Kind::Defer(_, span)
+        | Kind::Go(_, span)
+        | Kind::Send(_, _, span)
+        | Kind::Recv(_, span)
+        | Kind::Select(_, _, span)
+        | Kind::Deref(_, span)
+        | Kind::AssignDeref(_, _, span) => Some(*span),
+        Kind::Lambda(..)
+        | Kind::Litt(_)
+        | Kind::Declare(..)
+        | Kind::DeclareImmutable(..)
+        | Kind::Assign(..)
+        | Kind::Block(_)
+        | Kind::ConditionalOp(..)
+        | Kind::IfElse(..)
+        | Kind::Not(_)
+        | Kind::MakeTuple(_)
+        | Kind::MakeSlice(_)
+        | Kind::MakeMap(_)
+        | Kind::Ref(_)
+        | Kind::Name(_) => None,
+    }
+}
+
+/// Rebuild an expression, changing the expressions directly inside of it
+pub fn map_children<F: FnMut(Expr) -> Expr>(kind: Kind, mut f: F) -> Kind {
+    let mut boxed = |e: Box<Expr>| Box::new(f(*e));
+    match kind {
+        Kind::Call(name, args, span) => Kind::Call(name, map_exprs(args, f), span),
+        Kind::CallValue(target, args, span) => {
+            let target = boxed(target);
+            Kind::CallValue(target, map_exprs(args, f), span)
+        }
+        Kind::Lambda(args, ret, body, locals) => {
+            Kind::Lambda(args, ret, map_exprs(body, f), locals)
+        }
+        Kind::Declare(var, e) => Kind::Declare(var, boxed(e)),
+        Kind::DeclareImmutable(var, e) => Kind::DeclareImmutable(var, boxed(e)),
+        Kind::DeclarePattern(pattern, e, span) => Kind::DeclarePattern(pattern, boxed(e), span),
+        Kind::Assign(var, e) => Kind::Assign(var, boxed(e)),
+        Kind::AssignOp(op, var, e, span) => Kind::AssignOp(op, var, boxed(e), span),
+        Kind::Block(exprs) => Kind::Block(map_exprs(exprs, f)),
+        Kind::BinOp(op, left, right, span) => Kind::BinOp(op, boxed(left), boxed(right), span),
+        Kind::ConditionalOp(op, left, right) => Kind::ConditionalOp(op, boxed(left), boxed(right)),
+        Kind::IfElse(cond, if_part, else_part) => {
+            let cond = boxed(cond);
+            let if_part = map_exprs(if_part, &mut f);
+            Kind::IfElse(cond, if_part, map_exprs(else_part, f))
+        }
+        Kind::Not(e) => Kind::Not(boxed(e)),
+        Kind::Neg(e, span) => Kind::Neg(boxed(e), span),
+        Kind::MakeTuple(exprs) => Kind::MakeTuple(map_exprs(exprs, f)),
+        Kind::MakeSlice(exprs) => Kind::MakeSlice(map_exprs(exprs, f)),
+        Kind::MakeMap(entries) => {
+            Kind::MakeMap(entries.into_iter().map(|(k, v)| (f(k), f(v))).collect())
+        }
+        Kind::Index(target, index, span) => Kind::Index(boxed(target), boxed(index), span),
+        Kind::Subslice(target, from, to, span) => {
+            let target = boxed(target);
+            let from = from.map(&mut boxed);
+            Kind::Subslice(target, from, to.map(boxed), span)
+        }
+        Kind::AssignIndex(target, index, e, span) => {
+            Kind::AssignIndex(boxed(target), boxed(index), boxed(e), span)
+        }
+        Kind::Field(e, field, span) => Kind::Field(boxed(e), field, span),
+        Kind::Try(e, span) => Kind::Try(boxed(e), span),
+        Kind::Defer(call, span) => Kind::Defer(boxed(call), span),
+        Kind::Go(call, span) => Kind::Go(boxed(call), span),
+        Kind::Send(chan, value, span) => Kind::Send(boxed(chan), boxed(value), span),
+        Kind::Recv(chan, span) => Kind::Recv(boxed(chan), span),
+        Kind::Ref(e) => Kind::Ref(boxed(e)),
+        Kind::Deref(pointer, span) => Kind::Deref(boxed(pointer), span),
+        Kind::AssignDeref(pointer, e, span) => Kind::AssignDeref(boxed(pointer), boxed(e), span),
+        Kind::Select(cases, default, span) => {
+            let cases = cases
+                .into_iter()
+                .map(|case| SelectCase {
+                    op: match case.op {
+                        SelectOp::Recv(var, chan) => SelectOp::Recv(var, f(chan)),
+                        SelectOp::Send(chan, value) => SelectOp::Send(f(chan), f(value)),
+                    },
+                    body: map_exprs(case.body, &mut f),
+                    span: case.span,
+                })
+                .collect();
+            Kind::Select(cases, default.map(|body| map_exprs(body, f)), span)
+        }
+        kind @ Kind::Litt(_) | kind @ Kind::Name(_) => kind,
+    }
+}
+
+/// Rebuild a list of expressions, changing each of them
+pub fn map_exprs<F: FnMut(Expr) -> Expr>(exprs: Vec<Expr>, f: F) -> Vec<Expr> {
+    exprs.into_iter().map(f).collect()
+}
+
+/// Represents a type checked top level function
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<(String, TypeName)>,
+    pub ret: Option<TypeName>,
+    pub body: Vec<Expr>,
+    pub locals: Locals,
+}
+
+/// Represents a whole type checked program, which is what the type checker produces
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub types: Vec<TypeDecl>,
+    /// The values of the constants, which the type checker evaluates
+    pub consts: HashMap<String, Litteral>,
+    pub functions: Vec<Function>,
+}
+
+// How an operator is written in the source
+fn op_symbol(op: Op) -> &'static str {
+    match op {
+        Op::Equal => "==",
+        Op::NotEqual => "!=",
+        Op::Leq => "<=",
+        Op::Less => "<",
+        Op::Geq => ">=",
+        Op::Greater => ">",
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Mod => "%",
+        Op::BitAnd => "&",
+        Op::BitOr => "|",
+        Op::BitXor => "^",
+        Op::AndNot => "&^",
+        Op::Shl => "<<",
+        Op::Shr => ">>",
+    }
+}
+
+// How a type is written in the source, like `Map[String, I64]`
+fn type_name(typ: &TypeName) -> String {
+    let list = |types: &[TypeName]| types.iter().map(type_name).collect::<Vec<_>>().join(", ");
+    match typ {
+        TypeName::Name(name) => name.clone(),
+        TypeName::Tuple(types) => format!("({})", list(types)),
+        TypeName::Slice(elem) => format!("[{}]", type_name(elem)),
+        TypeName::Apply(name, args) => format!("{}[{}]", name, list(args)),
+        TypeName::Func(args, None) => format!("func({})", list(args)),
+        TypeName::Func(args, Some(ret)) => format!("func({}) {}", list(args), type_name(ret)),
+        TypeName::Pointer(elem) => format!("*{}", type_name(elem)),
+    }
+}
+
+// Write a litteral like it would appear in the source, with strings quoted
+fn write_litt(f: &mut fmt::Formatter<'_>, litt: &Litteral) -> fmt::Result {
+    match litt {
+        Litteral::Str(s) => write!(f, "{:?}", s),
+        Litteral::Rune(c) => write!(f, "{:?}", c),
+        Litteral::Tuple(litts) => {
+            write!(f, "(")?;
+            for (i, l) in litts.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_litt(f, l)?;
+            }
+            if litts.len() == 1 {
+                write!(f, ",")?;
+            }
+            write!(f, ")")
+        }
+        Litteral::Option(Some(l)) => {
+            write!(f, "Some(")?;
+            write_litt(f, l)?;
+            write!(f, ")")
+        }
+        Litteral::Result(Ok(l)) | Litteral::Result(Err(l)) => {
+            let name = if let Litteral::Result(Ok(_)) = litt { "Ok" } else { "Err" };
+            write!(f, "{}(", name)?;
+            write_litt(f, l)?;
+            write!(f, ")")
+        }
+        Litteral::Newtype(name, l) => {
+            write!(f, "{}(", name)?;
+            write_litt(f, l)?;
+            write!(f, ")")
+        }
+        litt => write!(f, "{}", litt),
+    }
+}
+
+fn write_pattern(f: &mut fmt::Formatter<'_>, pattern: &Pattern) -> fmt::Result {
+    match pattern {
+        Pattern::Name(var) => write!(f, "{}", var.name),
+        Pattern::Ignore => write!(f, "_"),
+        Pattern::Tuple(patterns) => {
+            write!(f, "(")?;
+            for (i, p) in patterns.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_pattern(f, p)?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+// Writes out the IR like source code, with the type of every statement in a comment
+struct Printer<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    indent: usize,
+}
+
+impl Printer<'_, '_> {
+    // Start a new line, at the current indentation
+    fn line(&mut self) -> fmt::Result {
+        writeln!(self.f)?;
+        write!(self.f, "{}", "  ".repeat(self.indent))
+    }
+
+    fn block(&mut self, exprs: &[Expr]) -> fmt::Result {
+        write!(self.f, "{{")?;
+        self.indent += 1;
+        for e in exprs {
+            self.line()?;
+            self.expr(e)?;
+            write!(self.f, "  // {:?}", e.typ)?;
+        }
+        self.indent -= 1;
+        self.line()?;
+        write!(self.f, "}}")
+    }
+
+    fn list(&mut self, exprs: &[Expr]) -> fmt::Result {
+        for (i, e) in exprs.iter().enumerate() {
+            if i > 0 {
+                write!(self.f, ", ")?;
+            }
+            self.expr(e)?;
+        }
+        Ok(())
+    }
+
+    fn args(&mut self, args: &[(String, TypeName)]) -> fmt::Result {
+        let args: Vec<String> =
+            args.iter().map(|(name, typ)| format!("{} {}", name, type_name(typ))).collect();
+        write!(self.f, "({})", args.join(", "))
+    }
+
+    // Write an expression that something is applied to, like the target of a call,
+    // with parentheses unless it stands on its own
+    fn operand(&mut self, e: &Expr) -> fmt::Result {
+        match &e.kind {
+            Kind::Name(_)
+            | Kind::Litt(_)
+            | Kind::Call(_, _, _)
+            | Kind::CallValue(_, _, _)
+            | Kind::BinOp(_, _, _, _)
+            | Kind::ConditionalOp(_, _, _)
+            | Kind::MakeTuple(_)
+            | Kind::MakeSlice(_)
+            | Kind::MakeMap(_)
+            | Kind::Index(_, _, _)
+            | Kind::Field(_, _, _) => self.expr(e),
+            _ => {
+                write!(self.f, "(")?;
+                self.expr(e)?;
+                write!(self.f, ")")
+            }
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) -> fmt::Result {
+        match &e.kind {
+            Kind::Call(name, args, _) => {
+                write!(self.f, "{}(", name)?;
+                self.list(args)?;
+                write!(self.f, ")")
+            }
+            Kind::CallValue(target, args, _) => {
+                self.operand(target)?;
+                write!(self.f, "(")?;
+                self.list(args)?;
+                write!(self.f, ")")
+            }
+            Kind::Lambda(args, ret, body, _) => {
+                write!(self.f, "func")?;
+                self.args(args)?;
+                if let Some(ret) = ret {
+                    write!(self.f, " {}", type_name(ret))?;
+                }
+                write!(self.f, " ")?;
+                self.block(body)
+            }
+            Kind::Litt(litt) => write_litt(self.f, litt),
+            Kind::Declare(var, e) => {
+                write!(self.f, "{} := ", var.name)?;
+                self.expr(e)
+            }
+            Kind::DeclareImmutable(var, e) => {
+                write!(self.f, "{} :: ", var.name)?;
+                self.expr(e)
+            }
+            Kind::DeclarePattern(pattern, e, _) => {
+                write_pattern(self.f, pattern)?;
+                write!(self.f, " := ")?;
+                self.expr(e)
+            }
+            Kind::Assign(var, e) => {
+                write!(self.f, "{} = ", var.name)?;
+                self.expr(e)
+            }
+            Kind::AssignOp(op, var, e, _) => {
+                write!(self.f, "{} {}= ", var.name, op_symbol(*op))?;
+                self.expr(e)
+            }
+            Kind::Block(exprs) => self.block(exprs),
+            Kind::BinOp(op, left, right, _) => {
+                write!(self.f, "(")?;
+                self.expr(left)?;
+                write!(self.f, " {} ", op_symbol(*op))?;
+                self.expr(right)?;
+                write!(self.f, ")")
+            }
+            Kind::ConditionalOp(op, left, right) => {
+                write!(self.f, "(")?;
+                self.expr(left)?;
+                match op {
+                    BoolOp::And => write!(self.f, " && ")?,
+                    BoolOp::Or => write!(self.f, " || ")?,
+                }
+                self.expr(right)?;
+                write!(self.f, ")")
+            }
+            Kind::IfElse(cond, if_part, else_part) => {
+                write!(self.f, "if ")?;
+                self.expr(cond)?;
+                write!(self.f, " ")?;
+                self.block(if_part)?;
+                write!(self.f, " else ")?;
+                self.block(else_part)
+            }
+            Kind::Not(e) => {
+                write!(self.f, "!")?;
+                self.operand(e)
+            }
+            Kind::Neg(e, _) => {
+                write!(self.f, "-")?;
+                self.operand(e)
+            }
+            Kind::MakeTuple(exprs) => {
+                write!(self.f, "(")?;
+                self.list(exprs)?;
+                if exprs.len() == 1 {
+                    write!(self.f, ",")?;
+                }
+                write!(self.f, ")")
+            }
+            Kind::MakeSlice(exprs) => {
+                write!(self.f, "[")?;
+                self.list(exprs)?;
+                write!(self.f, "]")
+            }
+            Kind::MakeMap(entries) if entries.is_empty() => write!(self.f, "{{:}}"),
+            Kind::MakeMap(entries) => {
+                write!(self.f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, ", ")?;
+                    }
+                    self.expr(k)?;
+                    write!(self.f, ": ")?;
+                    self.expr(v)?;
+                }
+                write!(self.f, "}}")
+            }
+            Kind::Index(target, index, _) => {
+                self.operand(target)?;
+                write!(self.f, "[")?;
+                self.expr(index)?;
+                write!(self.f, "]")
+            }
+            Kind::Subslice(target, from, to, _) => {
+                self.operand(target)?;
+                write!(self.f, "[")?;
+                if let Some(from) = from {
+                    self.expr(from)?;
+                }
+                write!(self.f, ":")?;
+                if let Some(to) = to {
+                    self.expr(to)?;
+                }
+                write!(self.f, "]")
+            }
+            Kind::AssignIndex(target, index, e, _) => {
+                self.operand(target)?;
+                write!(self.f, "[")?;
+                self.expr(index)?;
+                write!(self.f, "] = ")?;
+                self.expr(e)
+            }
+            Kind::Field(e, field, _) => {
+                self.operand(e)?;
+                write!(self.f, ".{}", field)
+            }
+            Kind::Try(e, _) => {
+                self.operand(e)?;
+                write!(self.f, "?")
+            }
+            Kind::Defer(call, _) => {
+                write!(self.f, "defer ")?;
+                self.expr(call)
+            }
+            Kind::Go(call, _) => {
+                write!(self.f, "go ")?;
+                self.expr(call)
+            }
+            Kind::Send(chan, value, _) => {
+                self.operand(chan)?;
+                write!(self.f, " <- ")?;
+                self.expr(value)
+            }
+            Kind::Recv(chan, _) => {
+                write!(self.f, "<-")?;
+                self.operand(chan)
+            }
+            Kind::Select(cases, default, _) => {
+                write!(self.f, "select {{")?;
+                self.indent += 1;
+                for case in cases {
+                    self.line()?;
+                    write!(self.f, "case ")?;
+                    match &case.op {
+                        SelectOp::Recv(var, chan) => {
+                            if let Some(var) = var {
+                                write!(self.f, "{} := ", var.name)?;
+                            }
+                            write!(self.f, "<-")?;
+                            self.operand(chan)?;
+                        }
+                        SelectOp::Send(chan, value) => {
+                            self.operand(chan)?;
+                            write!(self.f, " <- ")?;
+                            self.expr(value)?;
+                        }
+                    }
+                    write!(self.f, " ")?;
+                    self.block(&case.body)?;
+                }
+                if let Some(default) = default {
+                    self.line()?;
+                    write!(self.f, "default ")?;
+                    self.block(default)?;
+                }
+                self.indent -= 1;
+                self.line()?;
+                write!(self.f, "}}")
+            }
+            Kind::Ref(e) => {
+                write!(self.f, "&")?;
+                self.operand(e)
+            }
+            Kind::Deref(e, _) => {
+                write!(self.f, "*")?;
+                self.operand(e)
+            }
+            Kind::AssignDeref(target, e, _) => {
+                write!(self.f, "*")?;
+                self.operand(target)?;
+                write!(self.f, " = ")?;
+                self.expr(e)
+            }
+            Kind::Name(var) => write!(self.f, "{}", var.name),
+        }
+    }
+}
+
+// Programs are written out like source code, which is what `--dump-ir` shows
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut consts: Vec<(&String, &Litteral)> = self.consts.iter().collect();
+        consts.sort_by_key(|(name, _)| *name);
+        for (name, value) in consts {
+            write!(f, "const {} = ", name)?;
+            write_litt(f, value)?;
+            writeln!(f)?;
+        }
+        if !self.consts.is_empty() {
+            writeln!(f)?;
+        }
+        let mut printer = Printer { f, indent: 0 };
+        for func in &self.functions {
+            write!(printer.f, "func {}", func.name)?;
+            printer.args(&func.args)?;
+            if let Some(ret) = &func.ret {
+                write!(printer.f, " {}", type_name(ret))?;
+            }
+            write!(printer.f, " ")?;
+            printer.block(&func.body)?;
+            writeln!(printer.f)?;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::bigint::BigInt;
 use regex::{Regex, RegexSet};
 use std::str::FromStr;
 
@@ -24,6 +25,8 @@ pub enum Token {
     LessEquals,
     /// The < symbol
     Less,
+    /// The <- symbols
+    LeftArrow,
     /// The >= symbol
     GreaterEquals,
     /// The > symbold
@@ -40,7 +43,61 @@ pub enum Token {
     Slash,
     /// The % symbol
     Percent,
+    /// The & symbol
+    Ampersand,
+    /// The | symbol
+    Pipe,
+    /// The ^ symbol
+    Caret,
+    /// The &^ symbols
+    AmpersandCaret,
+    /// The << symbols
+    DoubleLess,
+    /// The >> symbols
+    DoubleGreater,
+    /// The += symbols
+    PlusEquals,
+    /// The -= symbols
+    MinusEquals,
+    /// The *= symbols
+    AsteriskEquals,
+    /// The /= symbols
+    SlashEquals,
+    /// The %= symbols
+    PercentEquals,
+    /// The &= symbols
+    AmpersandEquals,
+    /// The |= symbols
+    PipeEquals,
+    /// The ^= symbols
+    CaretEquals,
+    /// The &^= symbols
+    AmpersandCaretEquals,
+    /// The <<= symbols
+    DoubleLessEquals,
+    /// The >>= symbols
+    DoubleGreaterEquals,
     Func,
+    /// The pub keyword
+    Pub,
+    /// The import keyword
+    Import,
+    /// The const keyword
+    Const,
+    /// The type keyword
+    Type,
+    /// The newtype keyword
+    Newtype,
+    /// The defer keyword
+    Defer,
+    /// The go keyword
+    Go,
+    /// The select keyword
+    Select,
+    /// The case keyword
+    Case,
+    /// The default keyword
+    Default,
     /// The if keyword
     If,
     /// The else keyword
@@ -51,15 +108,33 @@ pub enum Token {
     DoublePipe,
     /// The ! symbol
     Exclamation,
+    OpenBracket,
+    CloseBracket,
+    /// The : symbol
+    Colon,
+    /// The :: symbols
+    DoubleColon,
+    /// The ? symbol
+    Question,
+    /// The . symbol
+    Dot,
     BoolLitteral {
         value: bool,
     },
     StringLitteral {
         value: String,
     },
+    /// A single character, like 'a'
+    RuneLitteral {
+        value: char,
+    },
     IntLitteral {
         value: i64,
     },
+    /// An integer litteral too large to fit in 64 bits
+    BigIntLitteral {
+        value: BigInt,
+    },
     Name {
         value: String,
     },
@@ -71,13 +146,31 @@ pub enum Token {
 
 /// Handle escape sequences when processing a litteral string.
 pub fn process_string_litteral(input: &str) -> String {
+    process_escapes(input, '"')
+}
+
+/// Handle escape sequences when processing a litteral rune.
+///
+/// This returns `None` unless the litteral is exactly one character.
+pub fn process_rune_litteral(input: &str) -> Option<char> {
+    let processed = process_escapes(input, '\'');
+    let mut chars = processed.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// Strings and runes share the same escapes, except that each can escape its own quote
+fn process_escapes(input: &str, quote: char) -> String {
     let mut acc = String::new();
     let mut escaping = false;
     for c in input.chars() {
         let was_escaping = escaping;
         match (escaping, c) {
+            (true, c) if c == quote => acc.push(c),
             // This will only be the first and last
-            (_, '"') => {}
+            (false, c) if c == quote => {}
             (true, 'n') => acc.push_str("\n"),
             (true, '\\') => acc.push('\\'),
             (true, 'r') => acc.push('\r'),
@@ -98,46 +191,89 @@ pub fn process_string_litteral(input: &str) -> String {
 
 /// Represents the type of error that can happen while lexing.
 ///
-/// Right now, this is empty.
+/// The parser also uses this for the errors it raises itself.
 #[derive(Debug, Clone, PartialEq)]
 pub struct LexError {
     message: String,
+    // Where in the source this error happened, if we know
+    location: Option<Location>,
+}
+
+impl LexError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        LexError {
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    /// Mark where in the source this error happened
+    pub fn at(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// The message describing what went wrong
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The location in the source code where this error happened, if known
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
 }
 
 /// Represents a location inside some piece of text
 #[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
-pub struct Location(usize);
+pub struct Location(pub usize);
 
 /// This is what our lexer produces
 pub type Span = Result<(Location, Token, Location), LexError>;
 
-const SIMPLE_MATCH_STRINGS: [&str; 27] = [
-    r"^\{", r"^\}", r"^\(", r"^\)", r"^;", r"^:=", r"^==", r"^!=", r"^=", r"^<=", r"^<", r"^>=",
-    r"^>", r"^,", r"^\+", r"^-\D", r"^\*", r"^/", r"^%", r"^true", r"^false", r"^func", r"^if",
-    r"^else", r"^&&", r"^\|\|", r"^!",
+// When several of these match, the first one wins, so longer symbols come first
+const SIMPLE_MATCH_STRINGS: [&str; 61] = [
+    r"^\{", r"^\}", r"^\(", r"^\)", r"^;", r"^::", r"^:=", r"^==", r"^!=", r"^=", r"^<<=", r"^<<",
+    r"^<=", r"^<-", r"^<", r"^>>=", r"^>>", r"^>=", r"^>", r"^,", r"^\+=", r"^\+", r"^-=", r"^-",
+    r"^\*=", r"^\*", r"^/=", r"^/", r"^%=", r"^%", r"^true\b", r"^false\b", r"^func\b", r"^if\b",
+    r"^else\b", r"^&&", r"^&\^=", r"^&\^", r"^&=", r"^&", r"^\|\|", r"^\|=", r"^\|", r"^\^=",
+    r"^\^", r"^!", r"^\[", r"^\]", r"^:", r"^\?", r"^\.", r"^pub\b", r"^import\b", r"^const\b",
+    r"^type\b", r"^newtype\b", r"^defer\b", r"^go\b", r"^select\b", r"^case\b", r"^default\b",
 ];
-const SIMPLE_MATCH_LENGTHS: [usize; 27] = [
-    1, 1, 1, 1, 1, 2, 2, 2, 1, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 4, 5, 4, 2, 4, 2, 2, 1,
+const SIMPLE_MATCH_LENGTHS: [usize; 61] = [
+    1, 1, 1, 1, 1, 2, 2, 2, 2, 1, 3, 2, 2, 2, 1, 3, 2, 2, 1, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 4, 5,
+    4, 2, 4, 2, 3, 2, 2, 1, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 3, 6, 5, 4, 7, 5, 2, 6, 4, 7,
 ];
-const SIMPLE_MATCH_TOKENS: [Token; 27] = [
+const SIMPLE_MATCH_TOKENS: [Token; 61] = [
     Token::OpenBrace,
     Token::CloseBrace,
     Token::OpenParens,
     Token::CloseParens,
     Token::Semicolon,
+    Token::DoubleColon,
     Token::Define,
     Token::DoubleEquals,
     Token::NotEquals,
     Token::Equals,
+    Token::DoubleLessEquals,
+    Token::DoubleLess,
     Token::LessEquals,
+    Token::LeftArrow,
     Token::Less,
+    Token::DoubleGreaterEquals,
+    Token::DoubleGreater,
     Token::GreaterEquals,
     Token::Greater,
     Token::Comma,
+    Token::PlusEquals,
     Token::Plus,
+    Token::MinusEquals,
     Token::Minus,
+    Token::AsteriskEquals,
     Token::Asterisk,
+    Token::SlashEquals,
     Token::Slash,
+    Token::PercentEquals,
     Token::Percent,
     Token::BoolLitteral { value: true },
     Token::BoolLitteral { value: false },
@@ -145,18 +281,45 @@ const SIMPLE_MATCH_TOKENS: [Token; 27] = [
     Token::If,
     Token::Else,
     Token::DoubleAmpersand,
+    Token::AmpersandCaretEquals,
+    Token::AmpersandCaret,
+    Token::AmpersandEquals,
+    Token::Ampersand,
     Token::DoublePipe,
+    Token::PipeEquals,
+    Token::Pipe,
+    Token::CaretEquals,
+    Token::Caret,
     Token::Exclamation,
+    Token::OpenBracket,
+    Token::CloseBracket,
+    Token::Colon,
+    Token::Question,
+    Token::Dot,
+    Token::Pub,
+    Token::Import,
+    Token::Const,
+    Token::Type,
+    Token::Newtype,
+    Token::Defer,
+    Token::Go,
+    Token::Select,
+    Token::Case,
+    Token::Default,
 ];
 
 pub struct Lexer<'d> {
     data: &'d str,
     pos: usize,
+    // This gets added to every location we produce, which lets us lex
+    // multiple files without their locations overlapping
+    offset: usize,
     can_insert_semi: bool,
     simple_matchers: RegexSet,
     // This allows us to skip various whitespace or comments
     skip_matcher: Regex,
     string_litteral_matcher: Regex,
+    rune_litteral_matcher: Regex,
     int_litteral_matcher: Regex,
     name_matcher: Regex,
     type_name_matcher: Regex,
@@ -166,23 +329,33 @@ impl<'d> Lexer<'d> {
     pub fn new(data: &'d str) -> Lexer {
         let simple_matchers = RegexSet::new(&SIMPLE_MATCH_STRINGS).unwrap();
         let skip_matcher = Regex::new(r"^((//[^\n]*)|\s)+").unwrap();
-        let int_litteral_matcher = Regex::new(r"^-?[0-9]+").unwrap();
+        let int_litteral_matcher = Regex::new(r"^[0-9]+").unwrap();
         let string_litteral_matcher = Regex::new(r#"^"([^"]*)""#).unwrap();
-        let name_matcher = Regex::new(r"^[a-z]\w*").unwrap();
+        let rune_litteral_matcher = Regex::new(r"^'((\\.|[^'\\\n])*)'").unwrap();
+        let name_matcher = Regex::new(r"^[a-z_]\w*").unwrap();
         let type_name_matcher = Regex::new(r"^[A-Z]\w*").unwrap();
         Lexer {
             data,
             pos: 0,
+            offset: 0,
             can_insert_semi: false,
             simple_matchers,
             skip_matcher,
             string_litteral_matcher,
+            rune_litteral_matcher,
             int_litteral_matcher,
             name_matcher,
             type_name_matcher,
         }
     }
 
+    /// Create a lexer whose locations start at a given offset, instead of 0
+    pub fn with_offset(data: &'d str, offset: usize) -> Lexer<'d> {
+        let mut lexer = Lexer::new(data);
+        lexer.offset = offset;
+        lexer
+    }
+
     // This is like next, but next wants to modify the state of can_insert_semi
     fn advance(&mut self) -> Option<Span> {
         while let Some(mat) = self.skip_matcher.find(&self.data[self.pos..]) {
@@ -239,19 +412,43 @@ impl<'d> Lexer<'d> {
             let end = Location(self.pos);
             return Some(Ok((start, matched_token, end)));
         }
+        if let Some(caps) = self.rune_litteral_matcher.captures(current_data) {
+            let total_match = caps.get(0).unwrap();
+            let matched_token = match process_rune_litteral(caps.get(1).unwrap().as_str()) {
+                Some(value) => Token::RuneLitteral { value },
+                None => {
+                    let message = format!(
+                        "Rune litteral {} should be exactly one character",
+                        total_match.as_str()
+                    );
+                    let error = LexError::new(message).at(Location(self.pos));
+                    self.pos += total_match.end() - total_match.start();
+                    return Some(Err(error));
+                }
+            };
+            let start = Location(self.pos);
+            self.pos += total_match.end() - total_match.start();
+            let end = Location(self.pos);
+            return Some(Ok((start, matched_token, end)));
+        }
         if let Some(mat) = self.int_litteral_matcher.find(current_data) {
             let matched_string = mat.as_str();
-            let value = i64::from_str(matched_string).unwrap();
-            let matched_token = Token::IntLitteral { value };
+            let matched_token = match i64::from_str(matched_string) {
+                Ok(value) => Token::IntLitteral { value },
+                // The regex guarantees that this is a valid number
+                Err(_) => Token::BigIntLitteral {
+                    value: BigInt::from_str(matched_string).unwrap(),
+                },
+            };
             let start = Location(self.pos);
             self.pos += matched_string.len();
             let end = Location(self.pos);
             return Some(Ok((start, matched_token, end)));
         }
-        let message = format!("Unrecognized characters at position {}", self.pos);
+        let error = LexError::new("Unrecognized characters").at(Location(self.pos));
         // Since nothing matched, we have to skip to the end
         self.pos += current_data.len();
-        Some(Err(LexError { message }))
+        Some(Err(error))
     }
 }
 
@@ -264,13 +461,19 @@ impl<'d> Iterator for Lexer<'d> {
             self.can_insert_semi = match tok {
                 Token::CloseParens => true,
                 Token::CloseBrace => true,
+                Token::CloseBracket => true,
                 Token::IntLitteral { .. } => true,
+                Token::BigIntLitteral { .. } => true,
                 Token::StringLitteral { .. } => true,
+                Token::RuneLitteral { .. } => true,
                 Token::Name { .. } => true,
+                Token::TypeName { .. } => true,
+                Token::Question => true,
                 _ => false,
             };
         };
-        res
+        let offset = self.offset;
+        res.map(|span| span.map(|(l, tok, r)| (Location(l.0 + offset), tok, Location(r.0 + offset))))
     }
 }
 
@@ -289,6 +492,17 @@ mod test {
         assert_eq!(lexer.next(), Some(Ok(span)));
     }
 
+    #[test]
+    fn names_starting_with_keywords_are_names() {
+        let input = "iffy";
+        let mut lexer = Lexer::new(input);
+        let token = Token::Name {
+            value: String::from(input),
+        };
+        let span = (Location(0), token, Location(input.len()));
+        assert_eq!(lexer.next(), Some(Ok(span)));
+    }
+
     #[test]
     fn spaces_are_skipped_when_lexing() {
         let input = "func main";
@@ -321,4 +535,49 @@ mod test {
         ))];
         assert_eq!(result, spans);
     }
+
+    #[test]
+    fn rune_litterals_lex() {
+        let input = r"'猫' '\'' '\n'";
+        let lexer = Lexer::new(input);
+        let result: Vec<Span> = lexer.collect();
+        let spans = vec![
+            Ok((Location(0), Token::RuneLitteral { value: '猫' }, Location(5))),
+            Ok((Location(6), Token::RuneLitteral { value: '\'' }, Location(10))),
+            Ok((Location(11), Token::RuneLitteral { value: '\n' }, Location(15))),
+        ];
+        assert_eq!(result, spans);
+    }
+
+    #[test]
+    fn minus_before_a_number_is_an_operator() {
+        let input = "x-1";
+        let lexer = Lexer::new(input);
+        let result: Vec<Span> = lexer.collect();
+        let spans = vec![
+            Ok((
+                Location(0),
+                Token::Name {
+                    value: String::from("x"),
+                },
+                Location(1),
+            )),
+            Ok((Location(1), Token::Minus, Location(2))),
+            Ok((Location(2), Token::IntLitteral { value: 1 }, Location(3))),
+        ];
+        assert_eq!(result, spans);
+    }
+
+    #[test]
+    fn longer_symbols_are_lexed_first() {
+        let input = "&^&&<<";
+        let lexer = Lexer::new(input);
+        let result: Vec<Span> = lexer.collect();
+        let spans = vec![
+            Ok((Location(0), Token::AmpersandCaret, Location(2))),
+            Ok((Location(2), Token::DoubleAmpersand, Location(4))),
+            Ok((Location(4), Token::DoubleLess, Location(6))),
+        ];
+        assert_eq!(result, spans);
+    }
 }
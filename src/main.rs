@@ -2,17 +2,33 @@ use std::fs::File;
 use std::io;
 use std::io::Read;
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
 #[macro_use]
 extern crate lalrpop_util;
 lalrpop_mod!(pub parse_ast);
 mod ast;
+mod bigint;
+mod builtins;
+mod bytecode;
+mod chan;
+mod closure;
+mod compiler;
+mod heap;
 mod interpreter;
+mod ir;
 mod lexer;
-mod scopes;
+mod map;
+mod modules;
+mod optimizer;
+mod resolver;
+mod scheduler;
+mod slice;
 #[cfg(test)]
 mod test_programs;
 mod typer;
+mod vm;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "iku", about = "The iku programming language")]
@@ -20,21 +36,220 @@ struct Opt {
     /// A file containing a program in iku
     #[structopt(short, long, parse(from_os_str))]
     file: PathBuf,
+    /// The seed deciding how tasks started with go are scheduled
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+    /// Walk the syntax tree to run the program, instead of compiling it for the VM
+    #[structopt(long)]
+    tree_walker: bool,
+    /// Warn about variables that are never used, or that shadow other variables
+    #[structopt(long)]
+    lint: bool,
+    /// Inline small functions, fold constants and remove code that never runs
+    /// before running the program
+    #[structopt(short = "O")]
+    optimize: bool,
+    /// How many expressions a function can have at most to be inlined with -O
+    #[structopt(long, default_value = "16")]
+    inline_size: usize,
+    /// How many calls a program can be inside of at once, 10000 by default
+    #[structopt(long)]
+    max_depth: Option<usize>,
+    /// How many steps a program can take, printing how many it has left once it's done
+    #[structopt(long)]
+    fuel: Option<u64>,
+    /// How many bytes a program can allocate for values
+    #[structopt(long)]
+    memory: Option<usize>,
+    /// How many milliseconds a program can run for
+    #[structopt(long)]
+    timeout: Option<u64>,
+    /// Print how many values the program put on the heap, and how many were freed
+    #[structopt(long)]
+    gc_stats: bool,
+    /// Print the typed IR of the program, and what it becomes after optimizing with -O
+    #[structopt(long)]
+    dump_ir: bool,
 }
 
+/// Loads modules from files, relative to the directory of the entry file.
+///
+/// A module imported as `"path/to/mod"` lives in `path/to/mod.iku`.
+struct FileLoader {
+    root: PathBuf,
+}
+
+impl modules::Loader for FileLoader {
+    fn load(&mut self, path: &str) -> io::Result<String> {
+        let mut prog_file = File::open(self.root.join(path).with_extension("iku"))?;
+        let mut prog = String::new();
+        prog_file.read_to_string(&mut prog)?;
+        Ok(prog)
+    }
+}
+
+// The stack running a program needs for each call the tree-walker is inside
+// of, on top of what type checking and the rest of running it need. Calls take
+// up to around 30KiB in debug builds, and much less in release builds.
+const STACK_PER_CALL: usize = 64 << 10;
+const STACK_BASE: usize = 8 << 20;
+
+// How many of the innermost and of the outermost calls of a long stack trace are shown
+const SHOWN_FRAMES: usize = 10;
+
 fn main() -> io::Result<()> {
     let opt = Opt::from_args();
-    let mut prog_file = File::open(opt.file)?;
+    let mut limits = interpreter::Limits::default();
+    if let Some(max_depth) = opt.max_depth {
+        limits.max_depth = max_depth;
+    }
+    limits.fuel = opt.fuel;
+    limits.memory = opt.memory;
+    limits.time = opt.timeout.map(Duration::from_millis);
+    // Programs run on a thread of their own. The tree-walker recurses for each
+    // call, so its stack has to be big enough for the deepest calls a program
+    // is allowed to make, but the VM keeps its calls on a stack of its own.
+    let stack_size = if opt.tree_walker {
+        limits.max_depth.saturating_mul(STACK_PER_CALL).saturating_add(STACK_BASE)
+    } else {
+        STACK_BASE
+    };
+    let runner = thread::Builder::new().stack_size(stack_size).spawn(move || run(opt, limits));
+    let runner = runner.map_err(|e| {
+        let message = format!("Can't make a stack for {} calls: {}", limits.max_depth, e);
+        io::Error::new(e.kind(), message)
+    })?;
+    runner.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn run(opt: Opt, limits: interpreter::Limits) -> io::Result<()> {
+    let mut prog_file = File::open(&opt.file)?;
     let mut prog = String::new();
     prog_file.read_to_string(&mut prog)?;
-    let lexer = lexer::Lexer::new(&prog);
-    let ast = parse_ast::ASTParser::new().parse(lexer).unwrap();
-    if let Err(e) = typer::check(&ast) {
-        println!("Type Error: {:?}", e);
-        return Ok(());
-    }
-    if let Err(e) = interpreter::interpret(interpreter::RealContext, &ast) {
-        println!("Interpreter Error: {:?}", e);
+    let root = opt.file.parent().map(PathBuf::from).unwrap_or_default();
+    let entry = opt.file.to_string_lossy();
+    let (sources, res) = modules::load(&mut FileLoader { root }, &entry, prog);
+    let sources = Sources {
+        map: sources,
+        entry: &entry,
+    };
+    let mut ast = match res {
+        Ok(ast) => ast,
+        Err(e) => {
+            let kind = match e.kind() {
+                modules::ErrorKind::Parse => "Parse Error",
+                modules::ErrorKind::Import => "Import Error",
+            };
+            report(kind, e.message(), e.span(), &sources);
+            return Ok(());
+        }
+    };
+    let lints = match resolver::resolve(&mut ast) {
+        Ok(lints) => lints,
+        Err(e) => {
+            report("Name Error", e.message(), e.span(), &sources);
+            return Ok(());
+        }
+    };
+    if opt.lint {
+        for lint in &lints {
+            report("Warning", &lint.message, Some(lint.span), &sources);
+        }
+    }
+    let mut program = match typer::check(&ast) {
+        Ok(program) => program,
+        Err(e) => {
+            report("Type Error", e.message(), e.span(), &sources);
+            return Ok(());
+        }
+    };
+    if opt.dump_ir {
+        println!("// The typed IR of the program\n{}", program);
+    }
+    if opt.optimize {
+        program = optimizer::optimize(optimizer::inline(program, opt.inline_size));
+        if opt.dump_ir {
+            println!("// After optimizing\n{}", program);
+        }
+    }
+    let ctx = interpreter::RealContext;
+    let mut meter = interpreter::Meter::new(limits);
+    let mut heap = heap::Heap::new();
+    let res = if opt.tree_walker {
+        interpreter::interpret_limited(ctx, &program, opt.seed, &mut meter, &mut heap)
+    } else {
+        vm::run_limited(ctx, &program, opt.seed, &mut meter, &mut heap)
+    };
+    if let Some(fuel) = meter.fuel() {
+        let (steps, allocated) = (meter.steps(), meter.allocated());
+        eprintln!("Fuel left: {}, after {} steps allocating {} bytes", fuel, steps, allocated);
+    }
+    if opt.gc_stats {
+        let stats = heap.stats();
+        eprintln!(
+            "Heap: {} values allocated, {} freed ({} by the collector in {} collections), {} live",
+            stats.allocated,
+            stats.freed,
+            stats.collected,
+            stats.collections,
+            stats.live()
+        );
+    }
+    if let Err(e) = res {
+        let kind = match e.exhausted() {
+            Some(_) => "Limit Error",
+            None => "Interpreter Error",
+        };
+        report(kind, e.message(), e.span(), &sources);
+        for (note, span) in e.notes() {
+            report("  Note", note, Some(*span), &sources);
+        }
+        // The innermost call comes first, like in Go
+        let trace = e.trace();
+        let elided = trace.len().saturating_sub(2 * SHOWN_FRAMES);
+        for (i, frame) in trace.iter().rev().enumerate() {
+            if elided > 0 && (SHOWN_FRAMES..SHOWN_FRAMES + elided).contains(&i) {
+                if i == SHOWN_FRAMES {
+                    println!("  ... {} more calls", elided);
+                }
+                continue;
+            }
+            match frame.call_site.and_then(|span| sources.locate(span)) {
+                Some((file, line, col)) => {
+                    println!("  in {}, called at {}:{}:{}", frame.function, file, line, col);
+                }
+                None => println!("  in {}", frame.function),
+            }
+        }
     };
     Ok(())
 }
+
+// The sources of a program, along with the path of the file it was run from
+struct Sources<'a> {
+    map: modules::SourceMap,
+    entry: &'a str,
+}
+
+impl<'a> Sources<'a> {
+    // Find the file a span points into, along with the line and column it starts at
+    fn locate(&self, span: ast::Span) -> Option<(String, usize, usize)> {
+        let (path, line, col) = self.map.locate(span)?;
+        let file = if path == self.entry {
+            path.to_string()
+        } else {
+            format!("{}.iku", path)
+        };
+        Some((file, line, col))
+    }
+}
+
+// Print out an error, along with where it happened if we know
+fn report(kind: &str, message: &str, span: Option<ast::Span>, sources: &Sources) {
+    match span.and_then(|span| sources.locate(span)) {
+        Some((file, line, col)) => {
+            println!("{} at {}:{}:{}: {}", kind, file, line, col, message);
+        }
+        None => println!("{}: {}", kind, message),
+    }
+}
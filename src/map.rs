@@ -0,0 +1,170 @@
+use crate::ast::Litteral;
+use crate::heap::Trace;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Default)]
+struct Entries {
+    // Every entry in insertion order. Deleted entries are left as holes,
+    // which get compacted away once there are too many of them.
+    ordered: Vec<Option<(Litteral, Litteral)>>,
+    // Where each key is stored inside of the ordered entries
+    positions: HashMap<Litteral, usize>,
+}
+
+impl Entries {
+    fn compact(&mut self) {
+        self.ordered.retain(Option::is_some);
+        self.positions.clear();
+        for (i, entry) in self.ordered.iter().enumerate() {
+            if let Some((k, _)) = entry {
+                self.positions.insert(k.clone(), i);
+            }
+        }
+    }
+}
+
+/// Represents a mutable map from keys to values.
+///
+/// Like in Go, maps are references: modifying a map is visible through every
+/// copy of it. Iterating over a map visits entries in the order their keys
+/// were first inserted.
+#[derive(Clone, Debug, Default)]
+pub struct Map {
+    entries: Rc<RefCell<Entries>>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: &Litteral) -> Option<Litteral> {
+        let entries = self.entries.borrow();
+        let i = *entries.positions.get(key)?;
+        entries.ordered[i].as_ref().map(|(_, v)| v.clone())
+    }
+
+    pub fn contains(&self, key: &Litteral) -> bool {
+        self.entries.borrow().positions.contains_key(key)
+    }
+
+    /// Set the value for a key, returning the value it replaces, if any.
+    ///
+    /// Replacing the value of a key doesn't change its place in the iteration order.
+    pub fn insert(&self, key: Litteral, value: Litteral) -> Option<Litteral> {
+        let mut entries = self.entries.borrow_mut();
+        if let Some(&i) = entries.positions.get(&key) {
+            let entry = entries.ordered[i].as_mut().unwrap();
+            return Some(std::mem::replace(&mut entry.1, value));
+        }
+        let i = entries.ordered.len();
+        entries.positions.insert(key.clone(), i);
+        entries.ordered.push(Some((key, value)));
+        None
+    }
+
+    /// Remove a key from the map, returning its value, if it was present.
+    pub fn remove(&self, key: &Litteral) -> Option<Litteral> {
+        let mut entries = self.entries.borrow_mut();
+        let i = entries.positions.remove(key)?;
+        let (_, value) = entries.ordered[i].take().unwrap();
+        if entries.ordered.len() > 2 * entries.positions.len() + 8 {
+            entries.compact();
+        }
+        Some(value)
+    }
+
+    /// Copy out the entries in this map, in insertion order
+    pub fn entries(&self) -> Vec<(Litteral, Litteral)> {
+        self.entries
+            .borrow()
+            .ordered
+            .iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Trace for Map {
+    fn shared(&self) -> (usize, usize) {
+        (Rc::as_ptr(&self.entries) as usize, Rc::strong_count(&self.entries))
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(&Litteral)) {
+        for (k, v) in self.entries.borrow().ordered.iter().flatten() {
+            visit(k);
+            visit(v);
+        }
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        self.len() == other.len()
+            && self
+                .entries()
+                .iter()
+                .all(|(k, v)| other.get(k).as_ref() == Some(v))
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (k, v)) in self.entries().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", k, v)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn s(value: &str) -> Litteral {
+        Litteral::Str(value.into())
+    }
+
+    #[test]
+    fn iteration_follows_insertion_order() {
+        let m = Map::new();
+        for (i, k) in ["c", "a", "b"].iter().enumerate() {
+            m.insert(s(k), Litteral::I64(i as i64));
+        }
+        m.insert(s("a"), Litteral::I64(10));
+        m.remove(&s("c"));
+        m.insert(s("c"), Litteral::I64(20));
+        let keys: Vec<Litteral> = m.entries().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![s("a"), s("b"), s("c")]);
+        assert_eq!(m.get(&s("a")), Some(Litteral::I64(10)));
+    }
+
+    #[test]
+    fn tuple_keys_compare_by_value() {
+        let m = Map::new();
+        let key = Litteral::Tuple(vec![Litteral::I64(1), Litteral::Bool(true)]);
+        m.insert(key.clone(), s("x"));
+        assert!(m.contains(&Litteral::Tuple(vec![
+            Litteral::I64(1),
+            Litteral::Bool(true)
+        ])));
+        assert_eq!(m.remove(&key), Some(s("x")));
+        assert!(m.is_empty());
+    }
+}
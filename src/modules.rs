@@ -0,0 +1,511 @@
+use crate::ast::*;
+use crate::lexer::{LexError, Lexer, Location, Token};
+use crate::parse_ast::ASTParser;
+use lalrpop_util::ParseError;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// Represents the ability to read the source code of modules.
+///
+/// This is made into a trait for the same reason as the interpreter's
+/// `Context`, so that we can load programs without touching the file system.
+pub trait Loader {
+    /// Read the source of a module, given its path relative to the root of the project
+    fn load(&mut self, path: &str) -> io::Result<String>;
+}
+
+/// The kinds of errors that can happen while loading a program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The entry file of the program doesn't parse
+    Parse,
+    /// A module the program imports couldn't be loaded, parsed, or linked with the others
+    Import,
+}
+
+/// Represents an error that happened while loading the modules of a program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    // Where in the source this error happened, if we know
+    span: Option<Span>,
+}
+
+impl Error {
+    /// What kind of error this is
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The message describing what went wrong
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The location in the source code responsible for this error, if known
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error {
+            kind: ErrorKind::Import,
+            message,
+            span: None,
+        }
+    }
+}
+
+/// The type of result we use when loading modules
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn fail<T, S: Into<String>>(message: S, span: Span) -> Result<T> {
+    Err(Error {
+        kind: ErrorKind::Import,
+        message: message.into(),
+        span: Some(span),
+    })
+}
+
+// Describe what went wrong when parsing a module, and where
+fn parse_error(path: &str, e: ParseError<Location, Token, LexError>, kind: ErrorKind) -> Error {
+    let expected = |expected: Vec<String>| expected.join(", ");
+    let (message, span) = match e {
+        ParseError::InvalidToken { location } => {
+            ("Invalid token".into(), Some(Span::new(location, location)))
+        }
+        ParseError::UnrecognizedEOF { location, expected: e } => (
+            format!("Unexpected end of file, expected one of {}", expected(e)),
+            Some(Span::new(location, location)),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (l, token, r),
+            expected: e,
+        } => (
+            format!("Unexpected {:?}, expected one of {}", token, expected(e)),
+            Some(Span::new(l, r)),
+        ),
+        ParseError::ExtraToken { token: (l, token, r) } => {
+            (format!("Unexpected {:?}", token), Some(Span::new(l, r)))
+        }
+        ParseError::User { error } => (
+            error.message().into(),
+            error.location().map(|l| Span::new(l, l)),
+        ),
+    };
+    Error {
+        kind,
+        message: format!("Failed to parse module {}: {}", path, message),
+        span,
+    }
+}
+
+// The source of a single file in a program
+#[derive(Debug)]
+struct SourceFile {
+    path: String,
+    // Where the locations in this file start
+    start: usize,
+    source: String,
+}
+
+/// Keeps track of the source code of every file in a program.
+///
+/// Each file is lexed starting at a different offset, so that a span can be
+/// traced back to the file it comes from.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    // Add a new file, returning the offset its locations should start at
+    fn add(&mut self, path: &str, source: String) -> usize {
+        // We leave a gap between files, so that the end of one file
+        // never looks like the start of the next
+        let start = self
+            .files
+            .last()
+            .map_or(0, |f| f.start + f.source.len() + 1);
+        self.files.push(SourceFile {
+            path: path.into(),
+            start,
+            source,
+        });
+        start
+    }
+
+    /// Find the file a span points into, along with the line and column it starts at
+    pub fn locate(&self, span: Span) -> Option<(&str, usize, usize)> {
+        let file = self
+            .files
+            .iter()
+            .rev()
+            .find(|f| f.start <= span.start && span.start <= f.start + f.source.len())?;
+        let local = Span {
+            start: span.start - file.start,
+            end: span.end - file.start,
+        };
+        let (line, col) = local.line_col(&file.source);
+        Some((&file.path, line, col))
+    }
+}
+
+// A module, before it's been linked with the others
+struct Module {
+    path: String,
+    ast: AST,
+}
+
+struct ModuleLoader<'a, L> {
+    loader: &'a mut L,
+    sources: SourceMap,
+    // The modules we've finished loading, with their dependencies first
+    loaded: Vec<Module>,
+    // The modules we're in the middle of loading, which lets us detect cycles
+    loading: Vec<String>,
+}
+
+impl<'a, L: Loader> ModuleLoader<'a, L> {
+    fn load(&mut self, path: &str, span: Option<Span>) -> Result<()> {
+        if self.loaded.iter().any(|m| m.path == path) {
+            return Ok(());
+        }
+        if let Some(i) = self.loading.iter().position(|p| p == path) {
+            let mut cycle = self.loading[i..].to_vec();
+            cycle.push(path.into());
+            return Err(Error {
+                kind: ErrorKind::Import,
+                message: format!("Import cycle between modules: {}", cycle.join(" -> ")),
+                span,
+            });
+        }
+        let source = self.loader.load(path).map_err(|e| Error {
+            kind: ErrorKind::Import,
+            message: format!("Failed to load module {}: {}", path, e),
+            span,
+        })?;
+        self.parse(path, source, ErrorKind::Import)
+    }
+
+    // Parse a module and load every module it imports, with errors of the
+    // given kind if the module itself doesn't parse
+    fn parse(&mut self, path: &str, source: String, kind: ErrorKind) -> Result<()> {
+        let offset = self.sources.add(path, source.clone());
+        let ast = ASTParser::new()
+            .parse(Lexer::with_offset(&source, offset))
+            .map_err(|e| parse_error(path, e, kind))?;
+        self.loading.push(path.into());
+        for import in &ast.imports {
+            self.load(&import.path, Some(import.span))?;
+        }
+        self.loading.pop();
+        self.loaded.push(Module {
+            path: path.into(),
+            ast,
+        });
+        Ok(())
+    }
+}
+
+// The name a function or constant of some module has once every module is
+// linked together.
+//
+// Items in the entry module keep their names, which can't clash with the
+// other items, since those all have a `.` in them.
+fn linked_name(module: &str, entry: &str, function: &str) -> String {
+    if module == entry {
+        function.into()
+    } else {
+        format!("{}.{}", module, function)
+    }
+}
+
+// Rewrites the names used in a module to the names of the items they refer to
+struct Linker<'a> {
+    // The path of the module we're linking
+    module: &'a str,
+    entry: &'a str,
+    // For every module, which functions, constants and types it has, and whether they're public
+    items: &'a HashMap<&'a str, HashMap<&'a str, bool>>,
+    // Which module each import alias refers to
+    imports: HashMap<&'a str, &'a str>,
+    // The local variables in scope, which shadow items with the same name
+    locals: Vec<HashSet<String>>,
+}
+
+impl<'a> Linker<'a> {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
+
+    fn declare(&mut self, name: &str) {
+        self.locals.last_mut().unwrap().insert(name.into());
+    }
+
+    fn declare_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Name(var) => self.declare(&var.name),
+            Pattern::Ignore => {}
+            Pattern::Tuple(patterns) => patterns.iter().for_each(|p| self.declare_pattern(p)),
+        }
+    }
+
+    // Find the linked name of whatever item a name refers to, if any
+    fn resolve(&self, name: &str) -> std::result::Result<Option<String>, String> {
+        if let Some(i) = name.find('.') {
+            let (alias, item) = (&name[..i], &name[i + 1..]);
+            let module = match self.imports.get(alias) {
+                Some(module) => *module,
+                None => return Err(format!("Module {} hasn't been imported", alias)),
+            };
+            return match self.items[module].get(item) {
+                Some(true) => Ok(Some(linked_name(module, self.entry, item))),
+                Some(false) => Err(format!("{} in module {} isn't public", item, module)),
+                None => Err(format!("Module {} has nothing named {}", module, item)),
+            };
+        }
+        if self.is_local(name) || !self.items[self.module].contains_key(name) {
+            return Ok(None);
+        }
+        Ok(Some(linked_name(self.module, self.entry, name)))
+    }
+
+    // Link a name, blaming the span it comes from if it can't be
+    fn link_name(&self, name: &mut String, span: Option<Span>) -> Result<()> {
+        match self.resolve(name) {
+            Ok(Some(linked)) => *name = linked,
+            Ok(None) => {}
+            Err(message) => {
+                let kind = ErrorKind::Import;
+                return Err(Error { kind, message, span });
+            }
+        }
+        Ok(())
+    }
+
+    fn link_type(&self, typ: &mut TypeName, span: Option<Span>) -> Result<()> {
+        match typ {
+            TypeName::Name(name) => self.link_name(name, span),
+            TypeName::Tuple(types) => types.iter_mut().try_for_each(|t| self.link_type(t, span)),
+            TypeName::Slice(t) | TypeName::Pointer(t) => self.link_type(t, span),
+            TypeName::Apply(_, args) => args.iter_mut().try_for_each(|t| self.link_type(t, span)),
+            TypeName::Func(args, ret) => {
+                for t in args.iter_mut().chain(ret.iter_mut().map(|t| &mut **t)) {
+                    self.link_type(t, span)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Type names don't have spans of their own, and neither do function
+    // signatures, so errors in them can't point anywhere.
+    fn link_args(&self, args: &mut [(String, TypeName)], ret: Option<&mut TypeName>) -> Result<()> {
+        for t in args.iter_mut().map(|(_, t)| t).chain(ret) {
+            self.link_type(t, None)?;
+        }
+        Ok(())
+    }
+
+    fn link_block(&mut self, exprs: &mut [Expr]) -> Result<()> {
+        self.locals.push(HashSet::new());
+        let res = exprs.iter_mut().try_for_each(|e| self.link_expr(e));
+        self.locals.pop();
+        res
+    }
+
+    fn link_function(&mut self, args: &[(String, TypeName)], body: &mut [Expr]) -> Result<()> {
+        self.locals.push(args.iter().map(|(name, _)| name.clone()).collect());
+        let res = self.link_block(body);
+        self.locals.pop();
+        res
+    }
+
+    fn link_expr(&mut self, expr: &mut Expr) -> Result<()> {
+        match expr {
+            Expr::Call(name, args, span) => {
+                for a in args.iter_mut() {
+                    self.link_expr(a)?;
+                }
+                self.link_name(name, Some(*span))
+            }
+            Expr::Name(var) => self.link_name(&mut var.name, Some(var.span)),
+            Expr::CallValue(target, args, _) => {
+                self.link_expr(target)?;
+                args.iter_mut().try_for_each(|a| self.link_expr(a))
+            }
+            Expr::Lambda(args, ret, body, _) => {
+                self.link_args(args, ret.as_mut())?;
+                self.link_function(args, body)
+            }
+            Expr::Litt(_) => Ok(()),
+            Expr::Declare(var, e) | Expr::DeclareImmutable(var, e) => {
+                self.link_expr(e)?;
+                self.declare(&var.name);
+                Ok(())
+            }
+            Expr::DeclarePattern(pattern, e, _) => {
+                self.link_expr(e)?;
+                self.declare_pattern(pattern);
+                Ok(())
+            }
+            Expr::Assign(_, e)
+            | Expr::AssignOp(_, _, e, _)
+            | Expr::Not(e)
+            | Expr::Neg(e, _)
+            | Expr::Field(e, _, _)
+            | Expr::Try(e, _)
+            | Expr::Defer(e, _)
+            | Expr::Go(e, _)
+            | Expr::Recv(e, _)
+            | Expr::Ref(e, _)
+            | Expr::Deref(e, _) => self.link_expr(e),
+            Expr::Block(exprs) => self.link_block(exprs),
+            Expr::BinOp(_, left, right, _)
+            | Expr::ConditionalOp(_, left, right)
+            | Expr::Index(left, right, _)
+            | Expr::Send(left, right, _)
+            | Expr::AssignDeref(left, right, _) => {
+                self.link_expr(left)?;
+                self.link_expr(right)
+            }
+            Expr::IfElse(cond, if_part, else_part) => {
+                self.link_expr(cond)?;
+                self.link_block(if_part)?;
+                self.link_block(else_part)
+            }
+            Expr::MakeTuple(exprs) | Expr::MakeSlice(exprs) => {
+                exprs.iter_mut().try_for_each(|e| self.link_expr(e))
+            }
+            Expr::MakeMap(entries) => entries.iter_mut().try_for_each(|(k, v)| {
+                self.link_expr(k)?;
+                self.link_expr(v)
+            }),
+            Expr::Subslice(target, from, to, _) => {
+                self.link_expr(target)?;
+                for e in from.iter_mut().chain(to.iter_mut()) {
+                    self.link_expr(e)?;
+                }
+                Ok(())
+            }
+            Expr::AssignIndex(target, index, e, _) => {
+                self.link_expr(target)?;
+                self.link_expr(index)?;
+                self.link_expr(e)
+            }
+            Expr::Select(cases, default, _) => {
+                for case in cases.iter_mut() {
+                    let received = match &mut case.op {
+                        SelectOp::Recv(var, chan) => {
+                            self.link_expr(chan)?;
+                            var.as_ref().map(|var| var.name.clone())
+                        }
+                        SelectOp::Send(chan, value) => {
+                            self.link_expr(chan)?;
+                            self.link_expr(value)?;
+                            None
+                        }
+                    };
+                    // What a case receives is only visible in the body of that case
+                    self.locals.push(received.into_iter().collect());
+                    let res = case.body.iter_mut().try_for_each(|e| self.link_expr(e));
+                    self.locals.pop();
+                    res?;
+                }
+                default.iter_mut().try_for_each(|body| self.link_block(body))
+            }
+        }
+    }
+}
+
+// Link every module together into a single AST
+fn link(modules: Vec<Module>, entry: &str) -> Result<AST> {
+    let mut items: HashMap<&str, HashMap<&str, bool>> = HashMap::new();
+    for m in &modules {
+        let functions = m.ast.functions.iter().map(|f| (f.name.as_str(), f.public));
+        let consts = m.ast.consts.iter().map(|c| (c.name.as_str(), c.public));
+        let types = m.ast.types.iter().map(|t| (t.name.as_str(), t.public));
+        items.insert(&m.path, functions.chain(consts).chain(types).collect());
+    }
+    let mut linked = AST::default();
+    for m in &modules {
+        let mut imports = HashMap::new();
+        for i in &m.ast.imports {
+            if let Some(other) = imports.insert(i.alias(), i.path.as_str()) {
+                let message = format!(
+                    "In module {}: {} is already imported as {}",
+                    m.path,
+                    other,
+                    i.alias()
+                );
+                return fail(message, i.span);
+            }
+        }
+        let mut linker = Linker {
+            module: &m.path,
+            entry,
+            items: &items,
+            imports,
+            locals: Vec::new(),
+        };
+        let in_module = |e: Error| Error {
+            kind: e.kind,
+            message: format!("In module {}: {}", m.path, e.message),
+            span: e.span,
+        };
+        for t in &m.ast.types {
+            let mut t = t.clone();
+            linker.link_type(&mut t.typ, Some(t.span)).map_err(in_module)?;
+            t.name = linked_name(&m.path, entry, &t.name);
+            linked.types.push(t);
+        }
+        for f in &m.ast.functions {
+            let mut f = f.clone();
+            linker
+                .link_args(&mut f.args, f.ret.as_mut())
+                .and_then(|_| linker.link_function(&f.args, &mut f.body))
+                .map_err(in_module)?;
+            f.name = linked_name(&m.path, entry, &f.name);
+            linked.functions.push(f);
+        }
+        for c in &m.ast.consts {
+            let mut c = c.clone();
+            if let Some(typ) = c.typ.as_mut() {
+                linker.link_type(typ, Some(c.span)).map_err(in_module)?;
+            }
+            linker
+                .link_function(&[], std::slice::from_mut(&mut c.value))
+                .map_err(in_module)?;
+            c.name = linked_name(&m.path, entry, &c.name);
+            linked.consts.push(c);
+        }
+    }
+    Ok(linked)
+}
+
+/// Load a program, starting from the source of the module containing its main function.
+///
+/// Errors in the entry module are located in a file named `entry`.
+/// This loads every module it imports, directly or not, and links them
+/// together into a single AST. The functions and types of other modules are
+/// named after the module they come from, like `mathx.gcd`.
+///
+/// The sources of the modules are returned even when loading fails, so that
+/// errors can be reported.
+pub fn load<L: Loader>(loader: &mut L, entry: &str, source: String) -> (SourceMap, Result<AST>) {
+    let mut modules = ModuleLoader {
+        loader,
+        sources: SourceMap::default(),
+        loaded: Vec::new(),
+        loading: Vec::new(),
+    };
+    let res = match modules.parse(entry, source, ErrorKind::Parse) {
+        Ok(()) => link(modules.loaded, entry),
+        Err(e) => Err(e),
+    };
+    (modules.sources, res)
+}
@@ -0,0 +1,457 @@
+use crate::ast::{Binding, BoolOp, Litteral, Op, Pattern, Span, Var};
+use crate::builtins;
+use crate::interpreter::{apply_op, negate, unit};
+use crate::ir::{
+    children, map_children, map_exprs, Expr, Function, Kind, Program, SelectCase, SelectOp,
+};
+use crate::typer::Type;
+use std::collections::{HashMap, HashSet};
+
+// Whether evaluating an expression can't do anything besides producing a value,
+// so it can be dropped when that value isn't used.
+//
+// Arithmetic isn't pure, since it can fail by overflowing or dividing by zero.
+fn is_pure(e: &Expr) -> bool {
+    match &e.kind {
+        Kind::Litt(_) | Kind::Name(_) | Kind::Lambda(_, _, _, _) => true,
+        Kind::Not(e) => is_pure(e),
+        Kind::ConditionalOp(_, left, right)
+        | Kind::BinOp(Op::Equal, left, right, _)
+        | Kind::BinOp(Op::NotEqual, left, right, _) => is_pure(left) && is_pure(right),
+        Kind::MakeTuple(exprs) | Kind::MakeSlice(exprs) | Kind::Block(exprs) => {
+            exprs.iter().all(is_pure)
+        }
+        Kind::MakeMap(entries) => entries.iter().all(|(k, v)| is_pure(k) && is_pure(v)),
+        _ => false,
+    }
+}
+
+// A boolean known ahead of time
+fn known_bool(e: &Expr) -> Option<bool> {
+    match e.kind {
+        Kind::Litt(Litteral::Bool(b)) => Some(b),
+        _ => None,
+    }
+}
+
+/// Simplifies a type checked program, without changing what it does
+struct Optimizer<'a> {
+    // The values of the constants in the program, which names can refer to
+    consts: &'a HashMap<String, Litteral>,
+}
+
+impl Optimizer<'_> {
+    fn boxed(&self, e: Expr) -> Box<Expr> {
+        Box::new(self.expr(e))
+    }
+
+    fn exprs(&self, exprs: Vec<Expr>) -> Vec<Expr> {
+        exprs.into_iter().map(|e| self.expr(e)).collect()
+    }
+
+    // Only the last expression of a block gives its value, so the others are
+    // dropped if they don't do anything else
+    fn block(&self, exprs: Vec<Expr>) -> Vec<Expr> {
+        let mut exprs = self.exprs(exprs);
+        let last = exprs.pop();
+        exprs.retain(|e| !is_pure(e));
+        exprs.extend(last);
+        exprs
+    }
+
+    fn expr(&self, e: Expr) -> Expr {
+        let typ = e.typ;
+        let kind = match e.kind {
+            // Local variables shadow constants, so only globals are replaced
+            Kind::Name(var) => match self.consts.get(&var.name) {
+                Some(value) if var.binding == Binding::Global => Kind::Litt(value.clone()),
+                _ => Kind::Name(var),
+            },
+            Kind::BinOp(op, left, right, span) => {
+                let (left, right) = (self.expr(*left), self.expr(*right));
+                if let (Kind::Litt(l), Kind::Litt(r)) = (&left.kind, &right.kind) {
+                    // Operations that fail, like overflowing, are left to fail
+                    // when the program runs
+                    if let Ok(value) = apply_op(op, l.clone(), r.clone()) {
+                        return Expr::new(Kind::Litt(value), typ);
+                    }
+                }
+                Kind::BinOp(op, Box::new(left), Box::new(right), span)
+            }
+            Kind::Neg(operand, span) => {
+                let operand = self.expr(*operand);
+                if let Kind::Litt(l) = &operand.kind {
+                    if let Ok(value) = negate(l.clone()) {
+                        return Expr::new(Kind::Litt(value), typ);
+                    }
+                }
+                Kind::Neg(Box::new(operand), span)
+            }
+            Kind::Not(operand) => {
+                let operand = self.expr(*operand);
+                match operand.kind {
+                    Kind::Litt(Litteral::Bool(b)) => Kind::Litt(Litteral::Bool(!b)),
+                    Kind::Not(inner) => return *inner,
+                    kind => Kind::Not(Box::new(Expr::new(kind, operand.typ))),
+                }
+            }
+            Kind::ConditionalOp(op, left, right) => {
+                let (left, right) = (self.expr(*left), self.expr(*right));
+                let short = op == BoolOp::Or;
+                match (known_bool(&left), known_bool(&right)) {
+                    // The right side never runs
+                    (Some(l), _) if l == short => return left,
+                    (Some(_), _) => return right,
+                    // The right side runs, but doesn't change the result
+                    (None, Some(r)) if r != short => return left,
+                    _ => Kind::ConditionalOp(op, Box::new(left), Box::new(right)),
+                }
+            }
+            Kind::IfElse(cond, if_part, else_part) => {
+                let cond = self.expr(*cond);
+                let (if_part, else_part) = (self.block(if_part), self.block(else_part));
+                match known_bool(&cond) {
+                    Some(true) => Kind::Block(if_part),
+                    Some(false) => Kind::Block(else_part),
+                    None => Kind::IfElse(Box::new(cond), if_part, else_part),
+                }
+            }
+            // Tuples are values, unlike slices and maps, so they can be shared
+            Kind::MakeTuple(exprs) => {
+                let exprs = self.exprs(exprs);
+                let litts: Option<Vec<Litteral>> = exprs
+                    .iter()
+                    .map(|e| match &e.kind {
+                        Kind::Litt(l) => Some(l.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                match litts {
+                    Some(litts) => Kind::Litt(Litteral::Tuple(litts)),
+                    None => Kind::MakeTuple(exprs),
+                }
+            }
+            Kind::Block(exprs) => Kind::Block(self.block(exprs)),
+            Kind::Lambda(args, ret, body, locals) => {
+                Kind::Lambda(args, ret, self.block(body), locals)
+            }
+            Kind::Call(name, args, span) => Kind::Call(name, self.exprs(args), span),
+            Kind::CallValue(target, args, span) => {
+                Kind::CallValue(self.boxed(*target), self.exprs(args), span)
+            }
+            Kind::Declare(var, e) => Kind::Declare(var, self.boxed(*e)),
+            Kind::DeclareImmutable(var, e) => Kind::DeclareImmutable(var, self.boxed(*e)),
+            Kind::DeclarePattern(pattern, e, span) => {
+                Kind::DeclarePattern(pattern, self.boxed(*e), span)
+            }
+            Kind::Assign(var, e) => Kind::Assign(var, self.boxed(*e)),
+            Kind::AssignOp(op, var, e, span) => Kind::AssignOp(op, var, self.boxed(*e), span),
+            Kind::MakeSlice(exprs) => Kind::MakeSlice(self.exprs(exprs)),
+            Kind::MakeMap(entries) => Kind::MakeMap(
+                entries.into_iter().map(|(k, v)| (self.expr(k), self.expr(v))).collect(),
+            ),
+            Kind::Index(target, index, span) => {
+                Kind::Index(self.boxed(*target), self.boxed(*index), span)
+            }
+            Kind::Subslice(target, from, to, span) => Kind::Subslice(
+                self.boxed(*target),
+                from.map(|e| self.boxed(*e)),
+                to.map(|e| self.boxed(*e)),
+                span,
+            ),
+            Kind::AssignIndex(target, index, e, span) => {
+                Kind::AssignIndex(self.boxed(*target), self.boxed(*index), self.boxed(*e), span)
+            }
+            Kind::Field(e, field, span) => Kind::Field(self.boxed(*e), field, span),
+            Kind::Try(e, span) => Kind::Try(self.boxed(*e), span),
+            Kind::Defer(call, span) => Kind::Defer(self.boxed(*call), span),
+            Kind::Go(call, span) => Kind::Go(self.boxed(*call), span),
+            Kind::Send(chan, value, span) => {
+                Kind::Send(self.boxed(*chan), self.boxed(*value), span)
+            }
+            Kind::Recv(chan, span) => Kind::Recv(self.boxed(*chan), span),
+            Kind::Ref(e) => Kind::Ref(self.boxed(*e)),
+            Kind::Deref(pointer, span) => Kind::Deref(self.boxed(*pointer), span),
+            Kind::AssignDeref(pointer, e, span) => {
+                Kind::AssignDeref(self.boxed(*pointer), self.boxed(*e), span)
+            }
+            Kind::Select(cases, default, span) => {
+                let cases = cases
+                    .into_iter()
+                    .map(|case| SelectCase {
+                        op: match case.op {
+                            SelectOp::Recv(var, chan) => SelectOp::Recv(var, self.expr(chan)),
+                            SelectOp::Send(chan, value) => {
+                                SelectOp::Send(self.expr(chan), self.expr(value))
+                            }
+                        },
+                        body: self.block(case.body),
+                        span: case.span,
+                    })
+                    .collect();
+                Kind::Select(cases, default.map(|body| self.block(body)), span)
+            }
+            kind @ Kind::Litt(_) => kind,
+        };
+        Expr::new(kind, typ)
+    }
+}
+
+/// Fold constants, and remove code that never runs or doesn't do anything.
+///
+/// Operators are folded with `apply_op`, like the interpreter applies them, and
+/// the ones that would fail are left for the program to fail on when it runs.
+pub fn optimize(program: Program) -> Program {
+    let Program {
+        types,
+        consts,
+        functions,
+    } = program;
+    let optimizer = Optimizer { consts: &consts };
+    let functions = functions
+        .into_iter()
+        .map(|f| Function {
+            body: optimizer.block(f.body),
+            ..f
+        })
+        .collect();
+    Program {
+        types,
+        consts,
+        functions,
+    }
+}
+
+// How many expressions there are in a function, which is how big inlining it makes its callers
+fn size(e: &Expr) -> usize {
+    1 + children(&e.kind).into_iter().map(size).sum::<usize>()
+}
+
+// The top level functions an expression refers to, whether by calling them or as values
+fn refers_to<'a>(e: &'a Expr, names: &mut HashSet<&'a str>) {
+    match &e.kind {
+        Kind::Call(name, _, _) => {
+            names.insert(name);
+        }
+        Kind::Name(var) if var.binding == Binding::Global => {
+            names.insert(&var.name);
+        }
+        _ => {}
+    }
+    for e in children(&e.kind) {
+        refers_to(e, names);
+    }
+}
+
+// Whether part of a function depends on running in a frame of its own: `?`
+// returns from it, `defer` waits for it to return, and `recover` only works
+// in the deferred call itself. Anonymous functions have frames of their own.
+fn needs_frame(e: &Expr) -> bool {
+    match &e.kind {
+        Kind::Try(_, _) | Kind::Defer(_, _) => true,
+        Kind::Call(name, _, _) if name == "recover" => true,
+        Kind::Lambda(_, _, _, _) => false,
+        kind => children(kind).into_iter().any(needs_frame),
+    }
+}
+
+// Move the local variables of an inlined function past those of its caller
+fn shift_var(mut var: Var, offset: usize) -> Var {
+    if let Binding::Local(slot) = var.binding {
+        var.binding = Binding::Local(slot + offset);
+    }
+    var
+}
+
+fn shift_pattern(pattern: Pattern, offset: usize) -> Pattern {
+    match pattern {
+        Pattern::Name(var) => Pattern::Name(shift_var(var, offset)),
+        Pattern::Tuple(patterns) => {
+            Pattern::Tuple(patterns.into_iter().map(|p| shift_pattern(p, offset)).collect())
+        }
+        Pattern::Ignore => Pattern::Ignore,
+    }
+}
+
+fn shift(e: Expr, offset: usize) -> Expr {
+    let kind = match e.kind {
+        Kind::Name(var) => Kind::Name(shift_var(var, offset)),
+        Kind::Declare(var, e) => Kind::Declare(shift_var(var, offset), Box::new(shift(*e, offset))),
+        Kind::DeclareImmutable(var, e) => {
+            Kind::DeclareImmutable(shift_var(var, offset), Box::new(shift(*e, offset)))
+        }
+        Kind::DeclarePattern(pattern, e, span) => {
+            let e = Box::new(shift(*e, offset));
+            Kind::DeclarePattern(shift_pattern(pattern, offset), e, span)
+        }
+        Kind::Assign(var, e) => Kind::Assign(shift_var(var, offset), Box::new(shift(*e, offset))),
+        Kind::AssignOp(op, var, e, span) => {
+            Kind::AssignOp(op, shift_var(var, offset), Box::new(shift(*e, offset)), span)
+        }
+        // The body of an anonymous function has slots of its own, but it
+        // captures variables from the slots of the function creating it
+        Kind::Lambda(args, ret, body, mut locals) => {
+            let captures = locals.captures.into_iter().map(|var| shift_var(var, offset));
+            locals.captures = captures.collect();
+            Kind::Lambda(args, ret, body, locals)
+        }
+        Kind::Select(cases, default, span) => {
+            let cases = cases
+                .into_iter()
+                .map(|case| SelectCase {
+                    op: match case.op {
+                        SelectOp::Recv(var, chan) => SelectOp::Recv(
+                            var.map(|var| shift_var(var, offset)),
+                            shift(chan, offset),
+                        ),
+                        op => op,
+                    },
+                    ..case
+                })
+                .collect();
+            map_children(Kind::Select(cases, default, span), |e| shift(e, offset))
+        }
+        kind => map_children(kind, |e| shift(e, offset)),
+    };
+    Expr::new(kind, e.typ)
+}
+
+/// Replaces calls to small functions with their body
+struct Inliner<'a> {
+    // The functions whose calls are replaced, by name
+    inlined: HashMap<&'a str, &'a Function>,
+    // How many slots the function being inlined into needs so far
+    slots: usize,
+}
+
+impl Inliner<'_> {
+    fn expr(&mut self, e: Expr) -> Expr {
+        let typ = e.typ;
+        let kind = match e.kind {
+            Kind::Call(name, args, _) if self.inlined.contains_key(name.as_str()) => {
+                let args = map_exprs(args, |e| self.expr(e));
+                return Expr::new(Kind::Block(self.inline(&name, args)), typ);
+            }
+            // The calls started by go and defer still need to be calls, but
+            // their arguments don't
+            Kind::Defer(call, span) => Kind::Defer(Box::new(self.pending_call(*call)), span),
+            Kind::Go(call, span) => Kind::Go(Box::new(self.pending_call(*call)), span),
+            Kind::Lambda(args, ret, body, mut locals) => {
+                let outer = std::mem::replace(&mut self.slots, locals.slots);
+                let body = map_exprs(body, |e| self.expr(e));
+                locals.slots = std::mem::replace(&mut self.slots, outer);
+                Kind::Lambda(args, ret, body, locals)
+            }
+            kind => map_children(kind, |e| self.expr(e)),
+        };
+        Expr::new(kind, typ)
+    }
+
+    fn pending_call(&mut self, call: Expr) -> Expr {
+        let kind = match call.kind {
+            Kind::Call(name, args, span) => {
+                Kind::Call(name, map_exprs(args, |e| self.expr(e)), span)
+            }
+            kind => map_children(kind, |e| self.expr(e)),
+        };
+        Expr::new(kind, call.typ)
+    }
+
+    // The arguments become local variables of the caller, declared before the
+    // body runs, like they would be by a call
+    fn inline(&mut self, name: &str, args: Vec<Expr>) -> Vec<Expr> {
+        let f = self.inlined[name];
+        let offset = self.slots;
+        self.slots += f.locals.slots;
+        let mut block: Vec<Expr> = args
+            .into_iter()
+            .zip(&f.args)
+            .enumerate()
+            .map(|(slot, (arg, (name, _)))| {
+                let var = Var {
+                    name: name.clone(),
+                    binding: Binding::Local(offset + slot),
+                    span: Span::default(),
+                };
+                let typ = arg.typ.clone();
+                Expr::new(Kind::Declare(var, Box::new(arg)), typ)
+            })
+            .collect();
+        // Declarations give a value, unlike empty functions
+        if f.body.is_empty() {
+            block.push(Expr::new(Kind::Litt(unit()), Type::Tuple(Vec::new())));
+        }
+        for e in &f.body {
+            // Functions called by the inlined one can be inlined too, since
+            // none of them calls it back
+            let e = shift(e.clone(), offset);
+            block.push(self.expr(e));
+        }
+        block
+    }
+}
+
+/// Replace calls to small functions with their body, saving the cost of calling them.
+///
+/// Only functions with at most `max_size` expressions are inlined, and never
+/// recursive ones, or those needing a frame of their own, like to defer calls.
+/// Inlined functions don't show up in the stack traces of errors.
+pub fn inline(program: Program, max_size: usize) -> Program {
+    let mut refers: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for f in &program.functions {
+        let names = refers.entry(&f.name).or_default();
+        for e in &f.body {
+            refers_to(e, names);
+        }
+    }
+    // Whether a function can end up calling itself, through the functions it refers to
+    let recursive = |f: &Function| {
+        let mut seen = HashSet::new();
+        let mut todo: Vec<&str> = refers[f.name.as_str()].iter().copied().collect();
+        while let Some(name) = todo.pop() {
+            if name == f.name {
+                return true;
+            }
+            if seen.insert(name) {
+                todo.extend(refers.get(name).into_iter().flatten());
+            }
+        }
+        false
+    };
+    let newtypes: HashSet<&str> =
+        program.types.iter().filter(|t| t.newtype).map(|t| t.name.as_str()).collect();
+    // Builtins and newtypes come first when calling a name, like in the interpreter
+    let shadowed = |name: &str| {
+        name == "print" || builtins::is_builtin(name) || newtypes.contains(name)
+    };
+    let inlined = program
+        .functions
+        .iter()
+        .filter(|f| {
+            let size: usize = f.body.iter().map(size).sum();
+            size <= max_size && !shadowed(&f.name) && !f.body.iter().any(needs_frame)
+        })
+        .filter(|f| !recursive(f))
+        .map(|f| (f.name.as_str(), f))
+        .collect();
+    let mut inliner = Inliner { inlined, slots: 0 };
+    let functions = program
+        .functions
+        .iter()
+        .map(|f| {
+            inliner.slots = f.locals.slots;
+            let body = f.body.iter().map(|e| inliner.expr(e.clone())).collect();
+            let mut locals = f.locals.clone();
+            locals.slots = inliner.slots;
+            Function {
+                body,
+                locals,
+                ..f.clone()
+            }
+        })
+        .collect();
+    Program {
+        functions,
+        ..program
+    }
+}
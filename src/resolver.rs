@@ -0,0 +1,364 @@
+use crate::ast::*;
+use crate::builtins;
+use crate::typer::{Error, Result};
+use std::collections::HashSet;
+
+/// Represents a warning about code that's allowed, but is probably a mistake
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+    pub message: String,
+    pub span: Span,
+}
+
+fn fail_at<T>(span: Span, message: String) -> Result<T> {
+    Err(Error::from(message).at(span))
+}
+
+// A variable declared in a function
+struct Declared {
+    name: String,
+    slot: usize,
+    // Variables declared with `::` can't be assigned to
+    mutable: bool,
+    // Whether the value of this variable is ever read
+    used: bool,
+    span: Span,
+}
+
+// The variables of a function being resolved
+struct Frame {
+    // The variables declared in each nested block, innermost last
+    scopes: Vec<Vec<Declared>>,
+    // The next slot that's free to use
+    next_slot: usize,
+    locals: Locals,
+    // Whether each captured variable can be assigned to
+    mutable_captures: Vec<bool>,
+}
+
+impl Frame {
+    fn new(args: &[(String, TypeName)]) -> Self {
+        let args: Vec<Declared> = args
+            .iter()
+            .enumerate()
+            .map(|(slot, (name, _))| Declared {
+                name: name.clone(),
+                slot,
+                mutable: true,
+                used: true,
+                span: Span::default(),
+            })
+            .collect();
+        Frame {
+            next_slot: args.len(),
+            locals: Locals {
+                slots: args.len(),
+                captures: Vec::new(),
+            },
+            scopes: vec![args],
+            mutable_captures: Vec::new(),
+        }
+    }
+}
+
+/// Finds what every name refers to, ahead of type checking and running a program
+struct Resolver {
+    // Constants and top level functions, which names refer to unless a variable shadows them
+    globals: HashSet<String>,
+    // The functions being resolved, with anonymous functions nested in the last one
+    frames: Vec<Frame>,
+    lints: Vec<Lint>,
+}
+
+impl Resolver {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("Resolving names outside of a function")
+    }
+
+    fn lint(&mut self, span: Span, message: String) {
+        self.lints.push(Lint { message, span });
+    }
+
+    fn enter(&mut self) {
+        self.frame().scopes.push(Vec::new());
+    }
+
+    // Leaving a block frees the slots of its variables for later ones
+    fn exit(&mut self) {
+        let frame = self.frame();
+        let scope = frame.scopes.pop().expect("Exiting a scope that was never entered");
+        frame.next_slot -= scope.len();
+        for var in scope {
+            if !var.used && !var.name.starts_with('_') {
+                self.lint(var.span, format!("The variable {} is never used", var.name));
+            }
+        }
+    }
+
+    // Whether a variable with some name is visible, in this function or the ones around it
+    fn is_visible(&self, name: &str) -> bool {
+        self.frames.iter().any(|frame| {
+            frame.scopes.iter().flatten().any(|var| var.name == name)
+                || frame.locals.captures.iter().any(|var| var.name == name)
+        })
+    }
+
+    fn declare(&mut self, var: &mut Var, mutable: bool) {
+        if self.is_visible(&var.name) && !var.name.starts_with('_') {
+            let message = format!("The variable {} shadows another one", var.name);
+            self.lint(var.span, message);
+        }
+        let frame = self.frame();
+        let slot = frame.next_slot;
+        frame.next_slot += 1;
+        frame.locals.slots = frame.locals.slots.max(frame.next_slot);
+        frame.scopes.last_mut().unwrap().push(Declared {
+            name: var.name.clone(),
+            slot,
+            mutable,
+            used: false,
+            span: var.span,
+        });
+        var.binding = Binding::Local(slot);
+    }
+
+    // Find a variable, along with whether it can be assigned to
+    fn lookup(&mut self, name: &str, reading: bool) -> Option<(Binding, bool)> {
+        self.lookup_in(self.frames.len() - 1, name, reading)
+    }
+
+    // Find a variable visible in a function, capturing it from the functions
+    // around it if needed
+    fn lookup_in(&mut self, depth: usize, name: &str, reading: bool) -> Option<(Binding, bool)> {
+        let frame = &mut self.frames[depth];
+        for scope in frame.scopes.iter_mut().rev() {
+            if let Some(var) = scope.iter_mut().rev().find(|var| var.name == name) {
+                var.used |= reading;
+                return Some((Binding::Local(var.slot), var.mutable));
+            }
+        }
+        if let Some(i) = frame.locals.captures.iter().position(|var| var.name == name) {
+            return Some((Binding::Captured(i), frame.mutable_captures[i]));
+        }
+        if depth == 0 {
+            return None;
+        }
+        // Capturing a variable reads its value when the function is created
+        let (binding, mutable) = self.lookup_in(depth - 1, name, true)?;
+        let frame = &mut self.frames[depth];
+        frame.locals.captures.push(Var {
+            name: name.into(),
+            binding,
+            span: Span::default(),
+        });
+        frame.mutable_captures.push(mutable);
+        Some((Binding::Captured(frame.locals.captures.len() - 1), mutable))
+    }
+
+    fn read(&mut self, var: &mut Var) -> Result<()> {
+        var.binding = match self.lookup(&var.name, true) {
+            Some((binding, _)) => binding,
+            None if self.globals.contains(&var.name) || builtins::constant(&var.name).is_some() => {
+                Binding::Global
+            }
+            None => {
+                return fail_at(var.span, format!("Trying to use undefined variable {}", var.name))
+            }
+        };
+        Ok(())
+    }
+
+    // Resolve a variable being assigned to, with errors pointing at the whole assignment
+    fn assign(&mut self, var: &mut Var, span: Span, reading: bool) -> Result<()> {
+        match self.lookup(&var.name, reading) {
+            Some((binding, true)) => {
+                var.binding = binding;
+                Ok(())
+            }
+            Some((_, false)) => fail_at(
+                span,
+                format!("Can't assign to {}, which was declared with ::", var.name),
+            ),
+            None => fail_at(span, format!("Trying to assign to undefined variable {}", var.name)),
+        }
+    }
+
+    // Resolve the body of a function, returning what it needs for its variables
+    fn function(&mut self, args: &[(String, TypeName)], body: &mut [Expr]) -> Result<Locals> {
+        self.frames.push(Frame::new(args));
+        let res = self.block(body);
+        self.exit();
+        let frame = self.frames.pop().unwrap();
+        res.map(|()| frame.locals)
+    }
+
+    fn block(&mut self, exprs: &mut [Expr]) -> Result<()> {
+        exprs.iter_mut().try_for_each(|e| self.expr(e))
+    }
+
+    fn scoped_block(&mut self, exprs: &mut [Expr]) -> Result<()> {
+        self.enter();
+        let res = self.block(exprs);
+        self.exit();
+        res
+    }
+
+    // Declare the names of a pattern, which can each only appear once in it
+    fn pattern(&mut self, pattern: &mut Pattern, declared: &mut Vec<String>) -> Result<()> {
+        match pattern {
+            Pattern::Name(var) if declared.contains(&var.name) => {
+                let message = format!("The variable {} is declared twice in a pattern", var.name);
+                fail_at(var.span, message)
+            }
+            Pattern::Name(var) => {
+                declared.push(var.name.clone());
+                self.declare(var, true);
+                Ok(())
+            }
+            Pattern::Ignore => Ok(()),
+            Pattern::Tuple(patterns) => {
+                patterns.iter_mut().try_for_each(|p| self.pattern(p, declared))
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> Result<()> {
+        match expr {
+            Expr::Call(name, args, span) => {
+                self.block(args)?;
+                // Variables holding functions shadow other functions
+                if let Some((binding, _)) = self.lookup(name, true) {
+                    let callee = Var {
+                        name: name.clone(),
+                        binding,
+                        span: *span,
+                    };
+                    let args = args.split_off(0);
+                    *expr = Expr::CallValue(Box::new(Expr::Name(callee)), args, *span);
+                }
+                Ok(())
+            }
+            Expr::CallValue(target, args, _) => {
+                self.expr(target)?;
+                self.block(args)
+            }
+            Expr::Lambda(args, _, body, locals) => {
+                *locals = self.function(args, body)?;
+                Ok(())
+            }
+            Expr::Litt(_) => Ok(()),
+            Expr::Name(var) => self.read(var),
+            Expr::Declare(var, e) => {
+                self.expr(e)?;
+                self.declare(var, true);
+                Ok(())
+            }
+            Expr::DeclareImmutable(var, e) => {
+                self.expr(e)?;
+                self.declare(var, false);
+                Ok(())
+            }
+            Expr::DeclarePattern(pattern, e, _) => {
+                self.expr(e)?;
+                self.pattern(pattern, &mut Vec::new())
+            }
+            Expr::Assign(var, e) => {
+                let span = var.span;
+                self.assign(var, span, false)?;
+                self.expr(e)
+            }
+            Expr::AssignOp(_, var, e, span) => {
+                self.assign(var, *span, true)?;
+                self.expr(e)
+            }
+            Expr::Block(exprs) => self.scoped_block(exprs),
+            Expr::Not(e)
+            | Expr::Neg(e, _)
+            | Expr::Field(e, _, _)
+            | Expr::Try(e, _)
+            | Expr::Defer(e, _)
+            | Expr::Go(e, _)
+            | Expr::Recv(e, _)
+            | Expr::Ref(e, _)
+            | Expr::Deref(e, _) => self.expr(e),
+            Expr::BinOp(_, left, right, _)
+            | Expr::ConditionalOp(_, left, right)
+            | Expr::Index(left, right, _)
+            | Expr::Send(left, right, _)
+            | Expr::AssignDeref(left, right, _) => {
+                self.expr(left)?;
+                self.expr(right)
+            }
+            Expr::IfElse(cond, if_part, else_part) => {
+                self.expr(cond)?;
+                self.scoped_block(if_part)?;
+                self.scoped_block(else_part)
+            }
+            Expr::MakeTuple(exprs) | Expr::MakeSlice(exprs) => self.block(exprs),
+            Expr::MakeMap(entries) => entries.iter_mut().try_for_each(|(k, v)| {
+                self.expr(k)?;
+                self.expr(v)
+            }),
+            Expr::Subslice(target, from, to, _) => {
+                self.expr(target)?;
+                for e in from.iter_mut().chain(to.iter_mut()) {
+                    self.expr(e)?;
+                }
+                Ok(())
+            }
+            Expr::AssignIndex(target, index, e, _) => {
+                self.expr(target)?;
+                self.expr(index)?;
+                self.expr(e)
+            }
+            Expr::Select(cases, default, _) => {
+                for case in cases.iter_mut() {
+                    match &mut case.op {
+                        SelectOp::Recv(_, chan) => self.expr(chan)?,
+                        SelectOp::Send(chan, value) => {
+                            self.expr(chan)?;
+                            self.expr(value)?;
+                        }
+                    }
+                    // What a case receives is only visible in the body of that case
+                    self.enter();
+                    if let SelectOp::Recv(Some(var), _) = &mut case.op {
+                        self.declare(var, true);
+                    }
+                    let res = self.block(&mut case.body);
+                    self.exit();
+                    res?;
+                }
+                match default {
+                    Some(body) => self.scoped_block(body),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Find what every name in a program refers to, giving each local variable a slot.
+///
+/// This fails on names that don't refer to anything, and on assignments to
+/// variables declared with `::`. Otherwise, this returns lints about unused
+/// variables, and variables shadowing others.
+pub fn resolve(ast: &mut AST) -> Result<Vec<Lint>> {
+    let globals = ast.consts.iter().map(|c| c.name.clone());
+    let mut resolver = Resolver {
+        globals: globals.chain(ast.functions.iter().map(|f| f.name.clone())).collect(),
+        frames: Vec::new(),
+        lints: Vec::new(),
+    };
+    // Constants can only refer to other constants, which the type checker makes
+    // sure of, so they're resolved like the body of a function without arguments
+    resolver.frames.push(Frame::new(&[]));
+    for c in &mut ast.consts {
+        resolver.expr(&mut c.value)?;
+    }
+    resolver.frames.pop();
+    for f in &mut ast.functions {
+        f.locals = resolver.function(&f.args, &mut f.body)?;
+    }
+    Ok(resolver.lints)
+}
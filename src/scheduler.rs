@@ -0,0 +1,171 @@
+use crate::ast::Span;
+use crate::chan::Chan;
+use crate::interpreter::InterpreterError;
+use std::convert::TryFrom;
+use std::fmt;
+
+// A small pseudo random number generator, so that scheduling only depends on a seed.
+//
+// This is SplitMix64, which is good enough to pick which task runs next.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) % n as u64) as usize
+    }
+}
+
+/// The channel operations a blocked task can be waiting to do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waiting {
+    Send,
+    Recv,
+}
+
+/// What a blocked task is waiting for
+#[derive(Debug)]
+pub struct Blocked {
+    /// What the task is doing, like "receiving from a channel"
+    pub doing: &'static str,
+    /// The channels whose changes might let this task continue
+    pub channels: Vec<(usize, Waiting)>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+enum TaskState {
+    Runnable,
+    Blocked(Blocked),
+    Done,
+}
+
+#[derive(Debug)]
+struct Task {
+    // Describes the task when reporting deadlocks
+    name: String,
+    state: TaskState,
+}
+
+/// Decides which task of a program runs next.
+///
+/// Tasks are scheduled cooperatively, only switching when a task blocks on a
+/// channel, or gives way to the others after starting a task or using a channel.
+/// When several tasks could run, the next one is picked with a seeded generator.
+///
+/// Both the interpreter and the VM use this, with their own kind of call
+/// starting each task, so a seed schedules a program the same way in either.
+#[derive(Debug)]
+pub struct Scheduler<T> {
+    rng: Rng,
+    tasks: Vec<Task>,
+    // Tasks that were started, but haven't been picked up by the executor yet
+    started: Vec<(usize, T)>,
+    // How many channels have been created, used to give each of them an id
+    channels: usize,
+}
+
+impl<T: fmt::Display> Scheduler<T> {
+    pub fn new(seed: u64) -> Self {
+        Scheduler {
+            rng: Rng(seed),
+            tasks: Vec::new(),
+            started: Vec::new(),
+            channels: 0,
+        }
+    }
+
+    /// Start a new task with a call, the first one being main
+    pub fn start(&mut self, call: T) {
+        let id = self.tasks.len();
+        let name = match id {
+            0 => String::from("main"),
+            _ => format!("task {} ({})", id, call),
+        };
+        self.tasks.push(Task {
+            name,
+            state: TaskState::Runnable,
+        });
+        self.started.push((id, call));
+    }
+
+    /// Take the tasks started since the last time, along with their ids
+    pub fn take_started(&mut self) -> Vec<(usize, T)> {
+        std::mem::take(&mut self.started)
+    }
+
+    /// Create a channel for a program, which can't have a negative capacity
+    pub fn make_chan(&mut self, cap: i64) -> Result<Chan, InterpreterError> {
+        let cap = match usize::try_from(cap) {
+            Ok(cap) => cap,
+            Err(_) => {
+                let message = format!("Channels can't have a negative capacity {}", cap);
+                return Err(InterpreterError::from(message));
+            }
+        };
+        self.channels += 1;
+        Ok(Chan::new(self.channels, cap))
+    }
+
+    /// Close a channel, letting the tasks waiting on it find out
+    pub fn close(&mut self, chan: &Chan) -> Result<(), InterpreterError> {
+        if !chan.close() {
+            return Err(InterpreterError::from("Closing a channel that's already closed"));
+        }
+        self.wake(chan, Waiting::Send);
+        self.wake(chan, Waiting::Recv);
+        Ok(())
+    }
+
+    /// Pick a number below n, like one of the cases of a select that can happen
+    pub fn pick(&mut self, n: usize) -> usize {
+        self.rng.below(n)
+    }
+
+    /// Pick the next task to run, if any task can run at all
+    pub fn next(&mut self) -> Option<usize> {
+        let runnable: Vec<usize> = (0..self.tasks.len())
+            .filter(|&id| matches!(self.tasks[id].state, TaskState::Runnable))
+            .collect();
+        match runnable.len() {
+            0 => None,
+            1 => Some(runnable[0]),
+            n => Some(runnable[self.rng.below(n)]),
+        }
+    }
+
+    pub fn block(&mut self, task: usize, blocked: Blocked) {
+        self.tasks[task].state = TaskState::Blocked(blocked);
+    }
+
+    /// Let the tasks waiting to do some operation on a channel check it again
+    pub fn wake(&mut self, chan: &Chan, waiting: Waiting) {
+        for task in &mut self.tasks {
+            if let TaskState::Blocked(blocked) = &task.state {
+                if blocked.channels.contains(&(chan.id(), waiting)) {
+                    task.state = TaskState::Runnable;
+                }
+            }
+        }
+    }
+
+    pub fn finish(&mut self, task: usize) {
+        self.tasks[task].state = TaskState::Done;
+    }
+
+    /// Describe every task that's blocked, once none of them can run anymore
+    pub fn deadlock(&self) -> InterpreterError {
+        let mut error = InterpreterError::from("Deadlock: every task is blocked");
+        for task in &self.tasks {
+            if let TaskState::Blocked(blocked) = &task.state {
+                let note = format!("{} is blocked {}", task.name, blocked.doing);
+                error = error.at(blocked.span).with_note(note, blocked.span);
+            }
+        }
+        error
+    }
+}
@@ -0,0 +1,184 @@
+use crate::ast::Litteral;
+use crate::heap::Trace;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Represents a growable view into a shared array of values.
+///
+/// This follows the semantics of slices in Go: slicing or appending to a slice
+/// with enough capacity shares the same backing array, so modifications are
+/// visible through every slice using that array.
+#[derive(Clone, Debug)]
+pub struct Slice {
+    // The values that have been written to the backing array so far
+    backing: Rc<RefCell<Vec<Litteral>>>,
+    // Where this slice starts in the backing array
+    start: usize,
+    // How many values this slice contains
+    len: usize,
+    // How many values this slice can grow to contain, before needing a new array
+    cap: usize,
+}
+
+impl Slice {
+    /// Create a slice holding exactly a given series of values
+    pub fn new(values: Vec<Litteral>) -> Self {
+        let len = values.len();
+        Slice {
+            backing: Rc::new(RefCell::new(values)),
+            start: 0,
+            len,
+            cap: len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read the value at a certain index, if it's in bounds
+    pub fn get(&self, index: usize) -> Option<Litteral> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.backing.borrow()[self.start + index].clone())
+    }
+
+    /// Write a value at a certain index, returning whether it was in bounds.
+    pub fn set(&self, index: usize, value: Litteral) -> bool {
+        if index >= self.len {
+            return false;
+        }
+        self.backing.borrow_mut()[self.start + index] = value;
+        true
+    }
+
+    /// Create a new slice pointing to the values between two indices.
+    ///
+    /// This returns None if the indices are out of bounds.
+    pub fn slice(&self, from: usize, to: usize) -> Option<Slice> {
+        if from > to || to > self.len {
+            return None;
+        }
+        Some(Slice {
+            backing: self.backing.clone(),
+            start: self.start + from,
+            len: to - from,
+            cap: self.cap - from,
+        })
+    }
+
+    /// How many values the larger array appending to this slice copies them
+    /// into can hold, or None if the value fits in the backing array
+    pub fn grown_cap(&self) -> Option<usize> {
+        if self.len < self.cap {
+            return None;
+        }
+        Some((2 * self.len).max(1))
+    }
+
+    /// Create a new slice with an extra value at the end.
+    ///
+    /// Like in Go, this reuses the backing array if there's enough capacity left,
+    /// and otherwise copies the values over into a larger array.
+    pub fn append(&self, value: Litteral) -> Slice {
+        let cap = match self.grown_cap() {
+            Some(cap) => cap,
+            None => {
+                let mut backing = self.backing.borrow_mut();
+                let end = self.start + self.len;
+                if end < backing.len() {
+                    backing[end] = value;
+                } else {
+                    backing.push(value);
+                }
+                return Slice {
+                    backing: self.backing.clone(),
+                    start: self.start,
+                    len: self.len + 1,
+                    cap: self.cap,
+                };
+            }
+        };
+        let mut values = Vec::with_capacity(cap);
+        values.extend(self.values());
+        values.push(value);
+        Slice {
+            backing: Rc::new(RefCell::new(values)),
+            start: 0,
+            len: self.len + 1,
+            cap,
+        }
+    }
+
+    /// Copy out the values in this slice
+    pub fn values(&self) -> Vec<Litteral> {
+        self.backing.borrow()[self.start..self.start + self.len].to_vec()
+    }
+}
+
+// The whole backing array is traced, since it holds values this slice can grow into
+impl Trace for Slice {
+    fn shared(&self) -> (usize, usize) {
+        (Rc::as_ptr(&self.backing) as usize, Rc::strong_count(&self.backing))
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(&Litteral)) {
+        self.backing.borrow().iter().for_each(visit)
+    }
+}
+
+impl PartialEq for Slice {
+    fn eq(&self, other: &Slice) -> bool {
+        self.values() == other.values()
+    }
+}
+
+impl fmt::Display for Slice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, l) in self.values().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", l)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ints(values: &[i64]) -> Slice {
+        Slice::new(values.iter().map(|i| Litteral::I64(*i)).collect())
+    }
+
+    #[test]
+    fn subslices_share_the_backing_array() {
+        let xs = ints(&[1, 2, 3]);
+        let ys = xs.slice(1, 3).unwrap();
+        ys.set(0, Litteral::I64(20));
+        assert_eq!(xs, ints(&[1, 20, 3]));
+        assert_eq!(xs.slice(2, 4), None);
+    }
+
+    #[test]
+    fn appending_only_copies_when_out_of_capacity() {
+        let xs = ints(&[1]);
+        // No capacity left, so this copies
+        let ys = xs.append(Litteral::I64(2));
+        ys.set(0, Litteral::I64(10));
+        assert_eq!(xs, ints(&[1]));
+        // ys has capacity 2, so after reslicing, appending shares the array
+        let zs = ys.slice(0, 1).unwrap().append(Litteral::I64(3));
+        assert_eq!(ys, ints(&[10, 3]));
+        assert_eq!(zs, ints(&[10, 3]));
+    }
+}
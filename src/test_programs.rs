@@ -1,8 +1,17 @@
 use crate::ast::*;
-use crate::interpreter::{interpret, Context};
+use crate::heap::Heap;
+use crate::interpreter::{self, Context, InterpreterError, InterpreterResult, Limits, Meter};
+use crate::ir;
 use crate::lexer::Lexer;
+use crate::modules::{self, Loader};
+use crate::optimizer;
 use crate::parse_ast::ASTParser;
-use crate::typer::check;
+use crate::resolver;
+use crate::typer::{self, Type};
+use crate::vm;
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
 
 const PROG_1: &'static str = include_str!("../test-programs/1.iku");
 const PROG_2: &'static str = include_str!("../test-programs/2.iku");
@@ -28,6 +37,105 @@ const PROG_21: &'static str = include_str!("../test-programs/21.iku");
 const PROG_22: &'static str = include_str!("../test-programs/22.iku");
 const PROG_23: &'static str = include_str!("../test-programs/23.iku");
 const PROG_24: &'static str = include_str!("../test-programs/24.iku");
+const PROG_25: &'static str = include_str!("../test-programs/25.iku");
+const PROG_26: &'static str = include_str!("../test-programs/26.iku");
+const PROG_27: &'static str = include_str!("../test-programs/27.iku");
+const PROG_28: &'static str = include_str!("../test-programs/28.iku");
+const PROG_29: &'static str = include_str!("../test-programs/29.iku");
+const PROG_30: &'static str = include_str!("../test-programs/30.iku");
+const PROG_31: &'static str = include_str!("../test-programs/31.iku");
+const PROG_32: &'static str = include_str!("../test-programs/32.iku");
+const PROG_33: &'static str = include_str!("../test-programs/33.iku");
+const PROG_34: &'static str = include_str!("../test-programs/34.iku");
+const PROG_35: &'static str = include_str!("../test-programs/35.iku");
+const PROG_36: &'static str = include_str!("../test-programs/36.iku");
+const PROG_37: &'static str = include_str!("../test-programs/37.iku");
+const PROG_38: &'static str = include_str!("../test-programs/38.iku");
+const PROG_39: &'static str = include_str!("../test-programs/39.iku");
+const PROG_40: &'static str = include_str!("../test-programs/40.iku");
+const PROG_41_MAIN: &'static str = include_str!("../test-programs/41/main.iku");
+const PROG_41_MATHX: &'static str = include_str!("../test-programs/41/mathx.iku");
+const PROG_41_GREET: &'static str = include_str!("../test-programs/41/text/greet.iku");
+const PROG_42_MAIN: &'static str = include_str!("../test-programs/42/main.iku");
+const PROG_42_A: &'static str = include_str!("../test-programs/42/a.iku");
+const PROG_42_B: &'static str = include_str!("../test-programs/42/b.iku");
+const PROG_43: &'static str = include_str!("../test-programs/43.iku");
+const PROG_44: &'static str = include_str!("../test-programs/44.iku");
+const PROG_45: &'static str = include_str!("../test-programs/45.iku");
+const PROG_46: &'static str = include_str!("../test-programs/46.iku");
+const PROG_47: &'static str = include_str!("../test-programs/47.iku");
+const PROG_48: &'static str = include_str!("../test-programs/48.iku");
+const PROG_49: &'static str = include_str!("../test-programs/49.iku");
+const PROG_50: &'static str = include_str!("../test-programs/50.iku");
+const PROG_51_MAIN: &'static str = include_str!("../test-programs/51/main.iku");
+const PROG_51_IDS: &'static str = include_str!("../test-programs/51/ids.iku");
+const PROG_52: &'static str = include_str!("../test-programs/52.iku");
+const PROG_53: &'static str = include_str!("../test-programs/53.iku");
+const PROG_54: &'static str = include_str!("../test-programs/54.iku");
+const PROG_55: &'static str = include_str!("../test-programs/55.iku");
+const PROG_56: &'static str = include_str!("../test-programs/56.iku");
+const PROG_57: &'static str = include_str!("../test-programs/57.iku");
+const PROG_58: &'static str = include_str!("../test-programs/58.iku");
+const PROG_59: &'static str = include_str!("../test-programs/59.iku");
+const PROG_60: &'static str = include_str!("../test-programs/60.iku");
+const PROG_61: &'static str = include_str!("../test-programs/61.iku");
+const PROG_62: &'static str = include_str!("../test-programs/62.iku");
+const PROG_63: &'static str = include_str!("../test-programs/63.iku");
+const PROG_64: &'static str = include_str!("../test-programs/64.iku");
+const PROG_65: &'static str = include_str!("../test-programs/65.iku");
+const PROG_66: &'static str = include_str!("../test-programs/66.iku");
+const PROG_67: &'static str = include_str!("../test-programs/67.iku");
+const PROG_68: &'static str = include_str!("../test-programs/68.iku");
+const PROG_69: &'static str = include_str!("../test-programs/69.iku");
+const PROG_70: &'static str = include_str!("../test-programs/70.iku");
+const PROG_71: &'static str = include_str!("../test-programs/71.iku");
+const PROG_72: &'static str = include_str!("../test-programs/72.iku");
+const PROG_73: &'static str = include_str!("../test-programs/73.iku");
+const PROG_74: &'static str = include_str!("../test-programs/74.iku");
+const PROG_75: &'static str = include_str!("../test-programs/75.iku");
+const PROG_76: &'static str = include_str!("../test-programs/76.iku");
+const PROG_77: &'static str = include_str!("../test-programs/77.iku");
+const PROG_78: &'static str = include_str!("../test-programs/78.iku");
+const PROG_79: &'static str = include_str!("../test-programs/79.iku");
+const PROG_80: &'static str = include_str!("../test-programs/80.iku");
+const PROG_81: &'static str = include_str!("../test-programs/81.iku");
+const PROG_82: &'static str = include_str!("../test-programs/82.iku");
+const PROG_83: &'static str = include_str!("../test-programs/83.iku");
+const PROG_84: &'static str = include_str!("../test-programs/84.iku");
+const PROG_85: &'static str = include_str!("../test-programs/85.iku");
+const PROG_86: &'static str = include_str!("../test-programs/86.iku");
+const PROG_87: &'static str = include_str!("../test-programs/87.iku");
+const PROG_88: &'static str = include_str!("../test-programs/88.iku");
+const PROG_89: &'static str = include_str!("../test-programs/89.iku");
+const PROG_90: &'static str = include_str!("../test-programs/90.iku");
+const PROG_91: &'static str = include_str!("../test-programs/91.iku");
+const PROG_92: &'static str = include_str!("../test-programs/92.iku");
+const PROG_93: &'static str = include_str!("../test-programs/93.iku");
+const PROG_94: &'static str = include_str!("../test-programs/94.iku");
+const PROG_95: &'static str = include_str!("../test-programs/95.iku");
+const PROG_96: &'static str = include_str!("../test-programs/96.iku");
+const PROG_97_MAIN: &'static str = include_str!("../test-programs/97/main.iku");
+const PROG_97_B: &'static str = include_str!("../test-programs/97/b.iku");
+const PROG_98_MAIN: &'static str = include_str!("../test-programs/98/main.iku");
+const PROG_98_BROKEN: &'static str = include_str!("../test-programs/98/broken.iku");
+const PROG_99: &'static str = include_str!("../test-programs/99.iku");
+const PROG_100: &'static str = include_str!("../test-programs/100.iku");
+const PROG_101: &'static str = include_str!("../test-programs/101.iku");
+const PROG_102: &'static str = include_str!("../test-programs/102.iku");
+const PROG_103: &'static str = include_str!("../test-programs/103.iku");
+const PROG_104: &'static str = include_str!("../test-programs/104.iku");
+const PROG_105: &'static str = include_str!("../test-programs/105.iku");
+const PROG_106: &'static str = include_str!("../test-programs/106.iku");
+const PROG_107: &'static str = include_str!("../test-programs/107.iku");
+const PROG_108: &'static str = include_str!("../test-programs/108.iku");
+const PROG_109: &'static str = include_str!("../test-programs/109.iku");
+const PROG_110: &'static str = include_str!("../test-programs/110.iku");
+const PROG_111: &'static str = include_str!("../test-programs/111.iku");
+const PROG_112: &'static str = include_str!("../test-programs/112.iku");
+const PROG_113: &'static str = include_str!("../test-programs/113.iku");
+const PROG_114: &'static str = include_str!("../test-programs/114.iku");
+const PROG_115: &'static str = include_str!("../test-programs/115.iku");
+const PROG_116: &'static str = include_str!("../test-programs/116.iku");
 
 #[derive(Debug)]
 struct FakeContext<'a> {
@@ -46,6 +154,94 @@ impl<'a> Context for FakeContext<'a> {
     }
 }
 
+// Resolve the names in a program before type checking it, like running a file does
+fn check(ast: &mut AST) -> typer::Result<()> {
+    resolver::resolve(ast)?;
+    typer::check(ast).map(|_| ())
+}
+
+// Run a program with both the interpreter and the VM, which should behave the same,
+// and once more after optimizing it, which shouldn't change anything either.
+//
+// Both also run with half the memory the program needs, which they count the
+// same way, and with just as much fuel as they need, and one step less, since
+// they both take a step for each expression they evaluate.
+//
+// These run the typed IR of the program, so it must have been checked already.
+fn interpret_seeded<C: Context>(mut ctx: C, ast: &AST, seed: u64) -> InterpreterResult<Litteral> {
+    let program = typer::check(ast).expect("Running a program that doesn't type check");
+    let limits = Limits::default();
+    let (walked, res, meter) = run_seeded(&program, limits, seed);
+    let (ran, vm_res, vm_meter) = run_seeded_vm(&program, limits, seed);
+    assert_eq!(walked, ran, "The VM printed something else");
+    assert_eq!(format!("{:?}", res), format!("{:?}", vm_res), "The VM gave another result");
+    assert_eq!(meter.steps(), vm_meter.steps(), "The VM used up another amount of fuel");
+    let optimized = optimizer::optimize(program.clone());
+    let (folded, folded_res, _) = run_seeded_vm(&optimized, limits, seed);
+    assert_eq!(walked, folded, "The optimized program printed something else");
+    let same_result = format!("{:?}", res) == format!("{:?}", folded_res);
+    assert!(same_result, "The optimized program gave another result");
+    let halved = Limits {
+        memory: Some(meter.allocated() / 2),
+        ..limits
+    };
+    let (walked_halved, res_halved, _) = run_seeded(&program, halved, seed);
+    let (ran_halved, vm_res_halved, _) = run_seeded_vm(&program, halved, seed);
+    assert_eq!(walked_halved, ran_halved, "The VM printed something else with less memory");
+    let same_result = format!("{:?}", res_halved) == format!("{:?}", vm_res_halved);
+    assert!(same_result, "The VM ran out of memory somewhere else");
+    let steps = meter.steps();
+    for run in [run_seeded as Backend, run_seeded_vm] {
+        let fueled = Limits {
+            fuel: Some(steps),
+            ..limits
+        };
+        let (printed, fueled_res, _) = run(&program, fueled, seed);
+        assert_eq!(printed, walked, "Having just enough fuel printed something else");
+        assert_eq!(format!("{:?}", fueled_res), format!("{:?}", res));
+        // Programs that don't evaluate anything can't run out
+        if steps == 0 {
+            continue;
+        }
+        let starved = Limits {
+            fuel: Some(steps - 1),
+            ..limits
+        };
+        let (printed, starved_res, _) = run(&program, starved, seed);
+        assert!(walked.starts_with(&printed), "Running out of fuel printed something else");
+        let exhausted = starved_res.unwrap_err().exhausted();
+        assert_eq!(exhausted, Some(interpreter::Resource::Fuel));
+    }
+    ctx.print(&walked);
+    res
+}
+
+fn interpret<C: Context>(ctx: C, ast: &AST) -> InterpreterResult<Litteral> {
+    interpret_seeded(ctx, ast, 0)
+}
+
+#[derive(Debug)]
+struct FakeLoader {
+    files: HashMap<&'static str, &'static str>,
+}
+
+impl FakeLoader {
+    fn new(files: &[(&'static str, &'static str)]) -> Self {
+        FakeLoader {
+            files: files.iter().cloned().collect(),
+        }
+    }
+}
+
+impl Loader for FakeLoader {
+    fn load(&mut self, path: &str) -> io::Result<String> {
+        match self.files.get(path) {
+            Some(source) => Ok(source.to_string()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        }
+    }
+}
+
 #[test]
 fn test_prog_1() {
     let lexer = Lexer::new(PROG_1);
@@ -53,17 +249,20 @@ fn test_prog_1() {
     let body = vec![Expr::Call(
         String::from("print"),
         vec![Expr::Litt(Litteral::I64(2))],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "2\n");
@@ -76,17 +275,20 @@ fn test_prog_2() {
     let body = vec![Expr::Call(
         String::from("print"),
         vec![Expr::Litt(Litteral::I64(-2))],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "-2\n");
@@ -102,17 +304,20 @@ fn test_prog_3() {
     let body = vec![Expr::Call(
         String::from("print"),
         vec![Expr::Litt(Litteral::Str(litt))],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, &format!("{}\n", PROG_3_LITT));
@@ -123,19 +328,21 @@ fn test_prog_4() {
     let lexer = Lexer::new(PROG_4);
     let res = ASTParser::new().parse(lexer);
     let body = vec![
-        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(1))]),
-        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(2))]),
+        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(1))], Span::default()),
+        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(2))], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "1\n2\n");
@@ -146,19 +353,21 @@ fn test_prog_5() {
     let lexer = Lexer::new(PROG_5);
     let res = ASTParser::new().parse(lexer);
     let body = vec![
-        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(1))]),
-        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(2))]),
+        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(1))], Span::default()),
+        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(2))], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "1\n2\n");
@@ -169,19 +378,21 @@ fn test_prog_6() {
     let lexer = Lexer::new(PROG_6);
     let res = ASTParser::new().parse(lexer);
     let body = vec![
-        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(6))]),
-        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(6))]),
+        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(6))], Span::default()),
+        Expr::Call(String::from("print"), vec![Expr::Litt(Litteral::I64(6))], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "6\n6\n");
@@ -200,20 +411,22 @@ fn test_prog_7() {
                 Box::new(Expr::Litt(Litteral::I64(2))),
             )),
         ),
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
-        Expr::Call("print".into(), vec![Expr::Name("y".into())]),
-        Expr::Call("print".into(), vec![Expr::Name("z".into())]),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
+        Expr::Call("print".into(), vec![Expr::Name("y".into())], Span::default()),
+        Expr::Call("print".into(), vec![Expr::Name("z".into())], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "2\n2\n2\n");
@@ -225,20 +438,22 @@ fn test_prog_8() {
     let res = ASTParser::new().parse(lexer);
     let body = vec![
         Expr::Declare("x".into(), Box::new(Expr::Litt(Litteral::I64(2)))),
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
         Expr::Assign("x".into(), Box::new(Expr::Litt(Litteral::I64(3)))),
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "2\n3\n");
@@ -251,26 +466,30 @@ fn test_prog_9() {
     let body_foo = vec![Expr::Call(
         "print".into(),
         vec![Expr::Litt(Litteral::I64(1))],
+        Span::default(),
     )];
-    let body_main = vec![Expr::Call("foo".into(), vec![])];
-    let ast = AST {
+    let body_main = vec![Expr::Call("foo".into(), vec![], Span::default())];
+    let mut ast = AST {
         functions: vec![
             Function {
                 name: "foo".into(),
                 args: vec![],
                 ret: None,
                 body: body_foo,
+                ..Default::default()
             },
             Function {
                 name: "main".into(),
                 args: vec![],
                 ret: None,
                 body: body_main,
+                ..Default::default()
             },
         ],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "1\n");
@@ -281,14 +500,15 @@ fn test_prog_10() {
     let lexer = Lexer::new(PROG_10);
     let res = ASTParser::new().parse(lexer);
     let body_foo = vec![
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
-        Expr::Call("print".into(), vec![Expr::Name("y".into())]),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
+        Expr::Call("print".into(), vec![Expr::Name("y".into())], Span::default()),
     ];
     let body_main = vec![Expr::Call(
         "foo".into(),
         vec![Expr::Litt(Litteral::I64(1)), Expr::Litt(Litteral::I64(2))],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![
             Function {
                 name: "foo".into(),
@@ -298,17 +518,20 @@ fn test_prog_10() {
                 ],
                 ret: None,
                 body: body_foo,
+                ..Default::default()
             },
             Function {
                 name: "main".into(),
                 args: vec![],
                 ret: None,
                 body: body_main,
+                ..Default::default()
             },
         ],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "1\n2\n");
@@ -319,19 +542,21 @@ fn test_prog_11() {
     let lexer = Lexer::new(PROG_11);
     let res = ASTParser::new().parse(lexer);
     let body = vec![
-        Expr::Call("print".into(), vec![Expr::Litt(Litteral::Bool(true))]),
-        Expr::Call("print".into(), vec![Expr::Litt(Litteral::Bool(false))]),
+        Expr::Call("print".into(), vec![Expr::Litt(Litteral::Bool(true))], Span::default()),
+        Expr::Call("print".into(), vec![Expr::Litt(Litteral::Bool(false))], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "true\nfalse\n");
@@ -350,19 +575,21 @@ fn test_prog_12() {
                 Expr::Name("x".into()),
             ])),
         ),
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
-        Expr::Call("print".into(), vec![Expr::Name("y".into())]),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
+        Expr::Call("print".into(), vec![Expr::Name("y".into())], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "2\n3\n");
@@ -379,7 +606,9 @@ fn test_prog_13() {
                 Op::Equal,
                 Box::new(Expr::Litt(Litteral::I64(1))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
         Expr::Call(
             "print".into(),
@@ -387,19 +616,23 @@ fn test_prog_13() {
                 Op::Equal,
                 Box::new(Expr::Litt(Litteral::I64(1))),
                 Box::new(Expr::Litt(Litteral::I64(2))),
+                Span::default(),
             )],
+            Span::default(),
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "true\nfalse\n");
@@ -416,7 +649,9 @@ fn test_prog_14() {
                 Op::Greater,
                 Box::new(Expr::Litt(Litteral::I64(1))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
         Expr::Call(
             "print".into(),
@@ -424,7 +659,9 @@ fn test_prog_14() {
                 Op::Geq,
                 Box::new(Expr::Litt(Litteral::I64(1))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
         Expr::Call(
             "print".into(),
@@ -432,7 +669,9 @@ fn test_prog_14() {
                 Op::Less,
                 Box::new(Expr::Litt(Litteral::I64(1))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
         Expr::Call(
             "print".into(),
@@ -440,19 +679,23 @@ fn test_prog_14() {
                 Op::Leq,
                 Box::new(Expr::Litt(Litteral::I64(1))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "false\ntrue\nfalse\ntrue\n");
@@ -475,18 +718,20 @@ fn test_prog_15() {
                 )],
             )),
         ),
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "2\n");
@@ -502,6 +747,7 @@ fn test_prog_16() {
             vec![Expr::Call(
                 "print".into(),
                 vec![Expr::Litt(Litteral::I64(1))],
+                Span::default(),
             )],
             vec![],
         ),
@@ -510,20 +756,23 @@ fn test_prog_16() {
             vec![Expr::Call(
                 "print".into(),
                 vec![Expr::Litt(Litteral::I64(2))],
+                Span::default(),
             )],
             vec![],
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "2\n");
@@ -540,7 +789,9 @@ fn test_prog_17() {
                 Op::Add,
                 Box::new(Expr::Litt(Litteral::I64(2))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
         Expr::Call(
             "print".into(),
@@ -548,19 +799,23 @@ fn test_prog_17() {
                 Op::Sub,
                 Box::new(Expr::Litt(Litteral::I64(2))),
                 Box::new(Expr::Litt(Litteral::I64(1))),
+                Span::default(),
             )],
+            Span::default(),
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "3\n1\n");
@@ -581,21 +836,27 @@ fn test_prog_18() {
                     Op::Div,
                     Box::new(Expr::Litt(Litteral::I64(16))),
                     Box::new(Expr::Litt(Litteral::I64(2))),
+                    Span::default(),
                 )),
                 Box::new(Expr::Litt(Litteral::I64(2))),
+                Span::default(),
             )),
+            Span::default(),
         )],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "17\n");
@@ -611,18 +872,22 @@ fn test_prog_19() {
             Op::Mod,
             Box::new(Expr::Litt(Litteral::I64(15))),
             Box::new(Expr::Litt(Litteral::I64(2))),
+            Span::default(),
         )],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "1\n");
@@ -638,18 +903,22 @@ fn test_prog_20() {
             Op::NotEqual,
             Box::new(Expr::Litt(Litteral::I64(1))),
             Box::new(Expr::Litt(Litteral::I64(2))),
+            Span::default(),
         )],
+        Span::default(),
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "true\n");
@@ -665,7 +934,7 @@ fn test_prog_21() {
                 BoolOp::And,
                 Box::new(Expr::Litt(Litteral::Bool(false))),
                 Box::new(Expr::Block(vec![
-                    Expr::Call("print".into(), vec![Expr::Litt(Litteral::I64(0))]),
+                    Expr::Call("print".into(), vec![Expr::Litt(Litteral::I64(0))], Span::default()),
                     Expr::Litt(Litteral::Bool(true)),
                 ])),
             )),
@@ -679,18 +948,21 @@ fn test_prog_21() {
                 Box::new(Expr::Litt(Litteral::Bool(true))),
                 Box::new(Expr::Litt(Litteral::Bool(false))),
             )],
+            Span::default(),
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "false\n");
@@ -705,26 +977,29 @@ fn test_prog_22() {
             BoolOp::Or,
             Box::new(Expr::Litt(Litteral::Bool(true))),
             Box::new(Expr::Block(vec![
-                Expr::Call("print".into(), vec![Expr::Litt(Litteral::I64(0))]),
+                Expr::Call("print".into(), vec![Expr::Litt(Litteral::I64(0))], Span::default()),
                 Expr::Litt(Litteral::Bool(true)),
             ])),
         )),
         vec![Expr::Call(
             "print".into(),
             vec![Expr::Litt(Litteral::I64(1))],
+            Span::default(),
         )],
         vec![],
     )];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "1\n");
@@ -738,22 +1013,26 @@ fn test_prog_23() {
         Expr::Call(
             "print".into(),
             vec![Expr::Not(Box::new(Expr::Litt(Litteral::Bool(true))))],
+            Span::default(),
         ),
         Expr::Call(
             "print".into(),
             vec![Expr::Not(Box::new(Expr::Litt(Litteral::Bool(false))))],
+            Span::default(),
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![Function {
             name: "main".into(),
             args: vec![],
             ret: None,
             body,
+            ..Default::default()
         }],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "false\ntrue\n");
@@ -764,35 +1043,1429 @@ fn test_prog_24() {
     let lexer = Lexer::new(PROG_24);
     let res = ASTParser::new().parse(lexer);
     let body = vec![
-        Expr::Declare("x".into(), Box::new(Expr::Call("foo".into(), vec![]))),
-        Expr::Call("print".into(), vec![Expr::Name("x".into())]),
+        Expr::Declare("x".into(), Box::new(Expr::Call("foo".into(), vec![], Span::default()))),
+        Expr::Call("print".into(), vec![Expr::Name("x".into())], Span::default()),
         Expr::Call(
             "print".into(),
             vec![Expr::MakeTuple(vec![
                 Expr::Litt(Litteral::I64(1)),
                 Expr::Litt(Litteral::I64(2)),
             ])],
+            Span::default(),
         ),
     ];
-    let ast = AST {
+    let mut ast = AST {
         functions: vec![
             Function {
                 name: "foo".into(),
                 args: vec![],
                 ret: None,
                 body: vec![],
+                ..Default::default()
             },
             Function {
                 name: "main".into(),
                 args: vec![],
                 ret: None,
                 body,
+                ..Default::default()
             },
         ],
+        ..Default::default()
     };
     assert_eq!(res.as_ref(), Ok(&ast));
-    assert!(check(&ast).is_ok());
+    assert!(check(&mut ast).is_ok());
     let mut interpreted = String::new();
     assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
     assert_eq!(&interpreted, "()\n(1, 2)\n");
 }
+
+#[test]
+fn test_prog_25() {
+    let lexer = Lexer::new(PROG_25);
+    let res = ASTParser::new().parse(lexer);
+    let body = vec![
+        Expr::Call(
+            "print".into(),
+            vec![Expr::Call(
+                "wrapping_add".into(),
+                vec![
+                    Expr::Litt(Litteral::I64(i64::MAX)),
+                    Expr::Litt(Litteral::I64(1)),
+                ],
+                Span::default(),
+            )],
+            Span::default(),
+        ),
+        Expr::Call(
+            "print".into(),
+            vec![Expr::Call(
+                "saturating_mul".into(),
+                vec![
+                    Expr::Litt(Litteral::I64(i64::MAX)),
+                    Expr::Litt(Litteral::I64(2)),
+                ],
+                Span::default(),
+            )],
+            Span::default(),
+        ),
+    ];
+    let mut ast = AST {
+        functions: vec![Function {
+            name: "main".into(),
+            args: vec![],
+            ret: None,
+            body,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    assert_eq!(res.as_ref(), Ok(&ast));
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(&interpreted, "-9223372036854775808\n9223372036854775807\n");
+}
+
+#[test]
+fn test_prog_26() {
+    let lexer = Lexer::new(PROG_26);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "Division by zero");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_26[span.start..span.end], "a / b");
+    assert_eq!(span.line_col(PROG_26), (2, 3));
+}
+
+#[test]
+fn test_prog_27() {
+    let lexer = Lexer::new(PROG_27);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert!(err.message().starts_with("Integer overflow"));
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_27[span.start..span.end], "x + 1");
+}
+
+#[test]
+fn test_prog_28() {
+    let lexer = Lexer::new(PROG_28);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Division by zero");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_28[span.start..span.end], "1 % (2 - 2)");
+}
+
+#[test]
+fn test_prog_29() {
+    let lexer = Lexer::new(PROG_29);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(&interpreted, "15511210043330985984000000\n52\n1\ntrue\n");
+}
+
+#[test]
+fn test_prog_30() {
+    let lexer = Lexer::new(PROG_30);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "9223372036854775808 doesn't fit in an I64");
+}
+
+#[test]
+fn test_prog_31() {
+    let lexer = Lexer::new(PROG_31);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(&interpreted, "[1, 20, 3]\n24\n[a, b]\n2\n[10, 20, 3]\n3\n");
+}
+
+#[test]
+fn test_prog_32() {
+    let lexer = Lexer::new(PROG_32);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "Index 3 out of bounds for slice of length 3");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_32[span.start..span.end], "xs[3]");
+}
+
+#[test]
+fn test_prog_33() {
+    let lexer = Lexer::new(PROG_33);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = [
+        "Some(25)",
+        "None",
+        "0",
+        "Some(31)",
+        "Some(25)",
+        "false",
+        "2",
+        "[alice, carol]",
+        "{alice: 32, carol: 40}",
+        "Some(one)",
+        "2",
+        "[(x, 1), (y, 2)]",
+    ];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_34() {
+    let lexer = Lexer::new(PROG_34);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Slice(I64) can't be used as the key of a map");
+}
+
+#[test]
+fn test_prog_35() {
+    let lexer = Lexer::new(PROG_35);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = [
+        "Ok(5)",
+        "Err(division by zero)",
+        "false",
+        "division by zero",
+        "Some(42)",
+        "None",
+        "7",
+        "(1, one)",
+    ];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_36() {
+    let lexer = Lexer::new(PROG_36);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "The operator ? returns Option(Unknown), but the function returns I64"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_36[span.start..span.end], "first(xs)?");
+}
+
+#[test]
+fn test_prog_37() {
+    let lexer = Lexer::new(PROG_37);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["12", "6", "11", "-2", "13", "1", "5", "func double"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_38() {
+    let lexer = Lexer::new(PROG_38);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Trying to call a function with types [Str], expected [I64] instead"
+    );
+}
+
+#[test]
+fn test_prog_39() {
+    let lexer = Lexer::new(PROG_39);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["1", "one", "3", "2", "4", "6", "deep"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_40() {
+    let lexer = Lexer::new(PROG_40);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Can't destructure Tuple([I64, I64]) into a tuple of 3 elements"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_40[span.start..span.end], "(a, b, c)");
+}
+
+#[test]
+fn test_prog_41() {
+    let mut loader = FakeLoader::new(&[("mathx", PROG_41_MATHX), ("text/greet", PROG_41_GREET)]);
+    let (_, res) = modules::load(&mut loader, "main", PROG_41_MAIN.into());
+    let mut ast = res.unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["6", "0", "(hello, iku, 6)", "49"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_42() {
+    let mut loader = FakeLoader::new(&[("a", PROG_42_A), ("b", PROG_42_B)]);
+    let (sources, res) = modules::load(&mut loader, "main", PROG_42_MAIN.into());
+    let err = res.unwrap_err();
+    assert_eq!(err.message(), "Import cycle between modules: a -> b -> a");
+    assert_eq!(sources.locate(err.span().unwrap()), Some(("b", 1, 8)));
+}
+
+#[test]
+fn test_prog_43() {
+    let mut loader = FakeLoader::new(&[("mathx", PROG_41_MATHX)]);
+    let (sources, res) = modules::load(&mut loader, "main", PROG_43.into());
+    let err = res.unwrap_err();
+    assert_eq!(
+        err.message(),
+        "In module main: helper in module mathx isn't public"
+    );
+    assert_eq!(sources.locate(err.span().unwrap()), Some(("main", 4, 9)));
+}
+
+#[test]
+fn test_prog_44() {
+    let lexer = Lexer::new(PROG_44);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["10240", "hello", "(5120, true)", "5120", "3"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_45() {
+    let lexer = Lexer::new(PROG_45);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Constants depend on each other in a cycle: A -> B -> C -> A"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_45[span.start..span.end], "A");
+}
+
+#[test]
+fn test_prog_46() {
+    let lexer = Lexer::new(PROG_46);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "The value of constant SIZE isn't a constant expression"
+    );
+}
+
+#[test]
+fn test_prog_47() {
+    let lexer = Lexer::new(PROG_47);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Division by zero");
+}
+
+#[test]
+fn test_prog_48() {
+    let lexer = Lexer::new(PROG_48);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["7", "UserId(6)", "6", "Some(ana)"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_49() {
+    let lexer = Lexer::new(PROG_49);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Trying to call lookup with types [I64], expected [Named(\"UserId\")] instead"
+    );
+}
+
+#[test]
+fn test_prog_50() {
+    let lexer = Lexer::new(PROG_50);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Type aliases refer to each other in a cycle: A -> B -> C -> A"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_50[span.start..span.end], "A");
+}
+
+#[test]
+fn test_prog_51() {
+    let mut loader = FakeLoader::new(&[("ids", PROG_51_IDS)]);
+    let (_, res) = modules::load(&mut loader, "main", PROG_51_MAIN.into());
+    let mut ast = res.unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "8\nids.UserId(8)\n");
+}
+
+#[test]
+fn test_prog_52() {
+    let lexer = Lexer::new(PROG_52);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = [
+        "-3",
+        "-4",
+        "2",
+        "(2, 7, 5, 4)",
+        "17",
+        "-4",
+        "(0, -1)",
+        "-5",
+        "-9223372036854775808",
+    ];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_53() {
+    let lexer = Lexer::new(PROG_53);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Binary op BitAnd requires I64, but found BigInt");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_53[span.start..span.end], "big & big");
+}
+
+#[test]
+fn test_prog_54() {
+    let lexer = Lexer::new(PROG_54);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Integer overflow in negation of -9223372036854775808"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_54[span.start..span.end], "-x");
+}
+
+#[test]
+fn test_prog_55() {
+    let lexer = Lexer::new(PROG_55);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "1\n80\n(10, 100)\n4\n");
+}
+
+#[test]
+fn test_prog_56() {
+    let lexer = Lexer::new(PROG_56);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Can't assign to total, which was declared with ::"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_56[span.start..span.end], "total += 2");
+}
+
+#[test]
+fn test_prog_57() {
+    let lexer = Lexer::new(PROG_57);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Can't assign to name, which was declared with ::"
+    );
+}
+
+#[test]
+fn test_prog_58() {
+    let lexer = Lexer::new(PROG_58);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(
+        interpreted,
+        "hello, wörld!\n(true, false, true)\n(14, 13)\n(104, 195, ö)\n\
+         (hello, wörld!, wörld)\n(4, a-b--c)\nä b c\n(padded, STRASSE, iku)\n\
+         (true, false)\none cat two cat\n(Some(7), None)\nababab\n"
+    );
+}
+
+#[test]
+fn test_prog_59() {
+    let lexer = Lexer::new(PROG_59);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Slice bounds 0:2 aren't on character boundaries of \"wörld\""
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_59[span.start..span.end], "word[0:2]");
+}
+
+#[test]
+fn test_prog_60() {
+    let lexer = Lexer::new(PROG_60);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Strings are immutable and can't be assigned into");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_60[span.start..span.end], "word[0]");
+}
+
+#[test]
+fn test_prog_61() {
+    let lexer = Lexer::new(PROG_61);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "Negative repeat count -1");
+}
+
+#[test]
+fn test_prog_62() {
+    let lexer = Lexer::new(PROG_62);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(
+        interpreted,
+        "(é, true, true)\n(', 10, 233, I)\n([h, é, l, l, o], 5)\n\
+         ([104, 195, 169], 104, 105)\n(255, k, true)\nSome(2)\n"
+    );
+}
+
+#[test]
+fn test_prog_63() {
+    let lexer = Lexer::new(PROG_63);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "300 doesn't fit in a U8");
+}
+
+#[test]
+fn test_prog_64() {
+    let lexer = Lexer::new(PROG_64);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Binary op Add requires I64 or BigInt, but found Rune"
+    );
+}
+
+#[test]
+fn test_prog_65() {
+    let lexer = Lexer::new(PROG_65);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(
+        interpreted,
+        "leaving first\nNone\nleaving first\nSome(7)\nend of main\nlambda\nlambda done\n\
+         (deferred last, ran first, 2)\n(deferred first, ran last, 1)\n"
+    );
+}
+
+#[test]
+fn test_prog_66() {
+    let lexer = Lexer::new(PROG_66);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(interpreted, "cleaning up divide\ncleaning up main\n");
+    assert_eq!(err.message(), "Division by zero");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_66[span.start..span.end], "a / b");
+}
+
+#[test]
+fn test_prog_67() {
+    let lexer = Lexer::new(PROG_67);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Trying to use undefined variable undefined");
+}
+
+#[test]
+fn test_prog_68() {
+    let lexer = Lexer::new(PROG_68);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    for seed in 0..20 {
+        let mut interpreted = String::new();
+        assert!(interpret_seeded(FakeContext::new(&mut interpreted), &ast, seed).is_ok());
+        assert_eq!(interpreted, "(sum of squares, 385)\nNone\n");
+    }
+}
+
+#[test]
+fn test_prog_69() {
+    let lexer = Lexer::new(PROG_69);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut orders = Vec::new();
+    for seed in 0..20 {
+        let mut interpreted = String::new();
+        assert!(interpret_seeded(FakeContext::new(&mut interpreted), &ast, seed).is_ok());
+        let mut again = String::new();
+        assert!(interpret_seeded(FakeContext::new(&mut again), &ast, seed).is_ok());
+        assert_eq!(interpreted, again);
+        let (order, rest) = interpreted.split_at(7);
+        assert_eq!(rest, "nothing to receive\nsent\nSome(5)\n");
+        assert_eq!(order.matches('a').count(), 3);
+        orders.push(String::from(order));
+    }
+    orders.dedup();
+    assert!(orders.len() > 1);
+}
+
+#[test]
+fn test_prog_70() {
+    let lexer = Lexer::new(PROG_70);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(interpreted, "");
+    assert_eq!(err.message(), "Deadlock: every task is blocked");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_70[span.start..span.end], "<-never");
+    let notes: Vec<(&str, &str)> = err
+        .notes()
+        .iter()
+        .map(|(note, span)| (note.as_str(), &PROG_70[span.start..span.end]))
+        .collect();
+    assert_eq!(
+        notes,
+        vec![
+            ("main is blocked receiving from a channel", "<-never"),
+            ("task 1 (func wait) is blocked receiving from a channel", "<-c"),
+        ]
+    );
+}
+
+#[test]
+fn test_prog_71() {
+    let lexer = Lexer::new(PROG_71);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Trying to send Str on a channel of I64");
+}
+
+#[test]
+fn test_prog_72() {
+    let lexer = Lexer::new(PROG_72);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "Sending on a closed channel");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_72[span.start..span.end], "c <- 1");
+}
+
+#[test]
+fn test_prog_73() {
+    let lexer = Lexer::new(PROG_73);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(
+        interpreted,
+        "3\nrecovered: Division by zero\n0\n(recover, None)\n(positive, 1)\n\
+         (recover, Some(negative))\n(, 0)\n"
+    );
+}
+
+// Describe each frame of a stack trace by its function and call site
+fn trace<'a>(source: &'a str, err: &'a InterpreterError) -> Vec<(&'a str, Option<&'a str>)> {
+    err.trace()
+        .iter()
+        .map(|f| {
+            let call_site = f.call_site.map(|span| &source[span.start..span.end]);
+            (f.function.as_str(), call_site)
+        })
+        .collect()
+}
+
+#[test]
+fn test_prog_74() {
+    let lexer = Lexer::new(PROG_74);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "Index 3 out of bounds for slice of length 2");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_74[span.start..span.end], "xs[3]");
+    assert_eq!(
+        trace(PROG_74, &err),
+        vec![
+            ("func main", None),
+            ("func middle", Some("middle([1, 2])")),
+            ("func inner", Some("inner(xs)")),
+        ]
+    );
+}
+
+#[test]
+fn test_prog_75() {
+    let lexer = Lexer::new(PROG_75);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(interpreted, "(indirectly, None)\nmain exits\n");
+    assert_eq!(err.message(), "not recovered");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_75[span.start..span.end], "panic(\"not recovered\")");
+    assert_eq!(
+        trace(PROG_75, &err),
+        vec![("func main", None), ("func fail", Some("fail()"))]
+    );
+}
+
+#[test]
+fn test_prog_76() {
+    let lexer = Lexer::new(PROG_76);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "(1, 2)\n321\nshadowed\n1\n(inner, 1)\n");
+}
+
+#[test]
+fn test_prog_77() {
+    let lexer = Lexer::new(PROG_77);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let lints = resolver::resolve(&mut ast).unwrap();
+    let lints: Vec<(&str, &str)> = lints
+        .iter()
+        .map(|lint| (lint.message.as_str(), &PROG_77[lint.span.start..lint.span.end]))
+        .collect();
+    assert_eq!(
+        lints,
+        vec![
+            ("The variable x shadows another one", "x"),
+            ("The variable unused is never used", "unused"),
+        ]
+    );
+    assert!(typer::check(&ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "3\n3\n");
+}
+
+#[test]
+fn test_prog_78() {
+    let lexer = Lexer::new(PROG_78);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    resolver::resolve(&mut ast).unwrap();
+    let program = typer::check(&ast).unwrap();
+    let types: Vec<&Type> = program.functions[0].body.iter().map(|e| &e.typ).collect();
+    let i64 = || Box::new(Type::I64);
+    assert_eq!(
+        types,
+        vec![
+            &Type::Slice(i64()),
+            &Type::Map(Box::new(Type::Str), i64()),
+            &Type::Option(i64()),
+            &Type::Function(vec![Type::I64], i64()),
+            &Type::Tuple(vec![]),
+        ]
+    );
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "(Some(1), true)\n");
+}
+
+#[test]
+fn test_prog_79() {
+    let lexer = Lexer::new(PROG_79);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let optimized = optimizer::optimize(typer::check(&ast).unwrap());
+    let expected = [
+        "const LIMIT = 10",
+        "",
+        "func main() {",
+        "  x := 21  // I64",
+        "  if (x > 20) {",
+        "    print(\"big\")  // Tuple([])",
+        "  } else {",
+        "    print(\"small\")  // Tuple([])",
+        "  }  // Tuple([])",
+        "  {",
+        "    print((10, \"a\", -10))  // Tuple([])",
+        "  }  // Tuple([])",
+        "  print((x == 21))  // Tuple([])",
+        "}",
+    ];
+    assert_eq!(optimized.to_string(), format!("{}\n", expected.join("\n")));
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "big\n(10, a, -10)\ntrue\n");
+}
+
+#[test]
+fn test_prog_80() {
+    let lexer = Lexer::new(PROG_80);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(interpreted, "5000050000\ntrue\ntrue\n0\n");
+    assert_eq!(err.message(), "Index 1 out of bounds for slice of length 1");
+    // Each tail call replaced the one before it
+    assert_eq!(
+        trace(PROG_80, &err),
+        vec![("func main", None), ("func fail", Some("fail(n - 1)"))]
+    );
+}
+
+#[test]
+fn test_prog_81() {
+    let lexer = Lexer::new(PROG_81);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let inlined = optimizer::inline(program.clone(), 16);
+    let shown = inlined.to_string();
+    assert!(!shown.contains("double(x)") && !shown.contains("pair(x)"));
+    assert!(!shown.contains("add_doubles(x, 7)") && !shown.contains("adder(x)"));
+    // Recursive functions are left alone
+    assert!(shown.contains("fact(5)") && shown.contains("fact((n - 1))"));
+    let mut meter = Meter::new(Limits::default());
+    let mut walked = String::new();
+    let walker = FakeContext::new(&mut walked);
+    let res = interpreter::interpret_limited(walker, &inlined, 0, &mut meter, &mut Heap::new());
+    assert!(res.is_ok());
+    let mut ran = String::new();
+    let ctx = FakeContext::new(&mut ran);
+    assert!(vm::run_limited(ctx, &inlined, 0, &mut meter, &mut Heap::new()).is_ok());
+    assert_eq!(walked, "(5, 10)\n24\n15\n120\n");
+    assert_eq!(walked, ran);
+    let shown = optimizer::inline(program, 3).to_string();
+    assert!(shown.contains("pair(x)") && !shown.contains("double(x)"));
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, walked);
+}
+
+// What a program printed when running under some limits, how it ended, and what it used
+type Limited = (String, InterpreterResult<Litteral>, Meter);
+
+// Running a program with the tree-walker or with the VM, with some seed
+type Backend = fn(&ir::Program, Limits, u64) -> Limited;
+
+fn run_seeded(program: &ir::Program, limits: Limits, seed: u64) -> Limited {
+    let mut meter = Meter::new(limits);
+    let mut printed = String::new();
+    let ctx = FakeContext::new(&mut printed);
+    let res = interpreter::interpret_limited(ctx, program, seed, &mut meter, &mut Heap::new());
+    (printed, res, meter)
+}
+
+fn run_seeded_vm(program: &ir::Program, limits: Limits, seed: u64) -> Limited {
+    let mut meter = Meter::new(limits);
+    let mut printed = String::new();
+    let ctx = FakeContext::new(&mut printed);
+    let res = vm::run_limited(ctx, program, seed, &mut meter, &mut Heap::new());
+    (printed, res, meter)
+}
+
+fn run_limited(program: &ir::Program, limits: Limits) -> Limited {
+    run_seeded(program, limits, 0)
+}
+
+fn run_limited_vm(program: &ir::Program, limits: Limits) -> Limited {
+    run_seeded_vm(program, limits, 0)
+}
+
+#[test]
+fn test_prog_82() {
+    let lexer = Lexer::new(PROG_82);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        max_depth: 20,
+        ..Limits::default()
+    };
+    let (walked, res, _) = run_limited(&program, limits);
+    let (ran, vm_res, _) = run_limited_vm(&program, limits);
+    assert_eq!(walked, ran);
+    assert_eq!(format!("{:?}", res), format!("{:?}", vm_res));
+    // Tail calls don't count towards the limit
+    assert_eq!(walked, "1000\n10\n");
+    let err = res.unwrap_err();
+    assert_eq!(err.message(), "Stack overflow, with more than 20 calls running");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_82[span.start..span.end], "depth(n - 1)");
+    let trace = trace(PROG_82, &err);
+    assert_eq!(trace.len(), 20);
+    assert_eq!(trace[..2], [("func main", None), ("func depth", Some("depth(30)"))]);
+    assert_eq!(trace[19], ("func depth", Some("depth(n - 1)")));
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "1000\n10\n30\n");
+}
+
+#[test]
+fn test_prog_83() {
+    let lexer = Lexer::new(PROG_83);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        fuel: Some(1000),
+        ..Limits::default()
+    };
+    // The deferred call never runs, since running out of fuel can't be recovered from
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "spinning\n");
+        let err = res.unwrap_err();
+        assert_eq!(err.exhausted(), Some(interpreter::Resource::Fuel));
+        assert_eq!(err.message(), "The program ran out of fuel");
+        assert_eq!(meter.fuel(), Some(0));
+        assert_eq!(meter.steps(), 1000);
+    }
+    let limits = Limits {
+        time: Some(Duration::from_millis(20)),
+        ..Limits::default()
+    };
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "spinning\n");
+        assert_eq!(res.unwrap_err().exhausted(), Some(interpreter::Resource::Time));
+        assert!(meter.elapsed() >= Duration::from_millis(20));
+        assert_eq!(meter.fuel(), None);
+    }
+    // Programs finishing in time tell how much fuel they have left
+    let lexer = Lexer::new(PROG_81);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        fuel: Some(10_000),
+        ..Limits::default()
+    };
+    let (_, _, walked) = run_limited(&program, limits);
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "(5, 10)\n24\n15\n120\n");
+        assert!(res.is_ok());
+        assert!(meter.steps() > 0);
+        // Both take a step for each expression they evaluate
+        assert_eq!(meter.steps(), walked.steps());
+        assert_eq!(meter.fuel(), Some(10_000 - meter.steps()));
+    }
+}
+
+#[test]
+fn test_prog_84() {
+    let lexer = Lexer::new(PROG_84);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        memory: Some(100_000),
+        ..Limits::default()
+    };
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "abababab\n");
+        let err = res.unwrap_err();
+        assert_eq!(err.exhausted(), Some(interpreter::Resource::Memory));
+        assert_eq!(err.message(), "The program ran out of memory");
+        // It runs out in the function it tail called, rather than where main called it
+        let span = err.span().unwrap();
+        assert_eq!(&PROG_84[span.start..span.end], "s + s");
+        // The string that would go over the limit is never made
+        assert!(meter.allocated() > 50_000 && meter.allocated() <= 100_000);
+    }
+}
+
+#[test]
+fn test_prog_85() {
+    let lexer = Lexer::new(PROG_85);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["1", "5", "7", "true", "false", "2", "[1, 2, 3]", "10"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_86() {
+    let lexer = Lexer::new(PROG_86);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "6\n10\n9\n0\n21\n");
+    let program = typer::check(&ast).unwrap();
+    for vm in [false, true] {
+        let mut meter = Meter::new(Limits::default());
+        let mut heap = Heap::new();
+        let mut printed = String::new();
+        let ctx = FakeContext::new(&mut printed);
+        let res = match vm {
+            false => interpreter::interpret_limited(ctx, &program, 0, &mut meter, &mut heap),
+            true => vm::run_limited(ctx, &program, 0, &mut meter, &mut heap),
+        };
+        assert!(res.is_ok());
+        assert_eq!(printed, interpreted);
+        // The node pointing to itself outlives the program, until it's collected
+        let stats = heap.stats();
+        assert_eq!((stats.allocated, stats.freed, stats.collected), (10, 9, 9));
+        assert_eq!(stats.collections, 2);
+        assert_eq!(heap.collect(), 1);
+        assert_eq!(heap.stats().live(), 0);
+    }
+}
+
+#[test]
+fn test_prog_87() {
+    let lexer = Lexer::new(PROG_87);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Only pointers can be dereferenced. Found I64");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_87[span.start..span.end], "*n");
+}
+
+#[test]
+fn test_prog_88() {
+    let lexer = Lexer::new(PROG_88);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // Assigning into the empty map decided the type of its keys
+    assert_eq!(err.message(), "Expected an index of type I64. Found Str");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_88[span.start..span.end], "m[\"a\"]");
+}
+
+#[test]
+fn test_prog_89() {
+    let lexer = Lexer::new(PROG_89);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Trying to assign type Str into Map(Str, I64)");
+}
+
+#[test]
+fn test_prog_90() {
+    let lexer = Lexer::new(PROG_90);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // Passing the map to fill decided its type, even inside the anonymous function
+    assert_eq!(
+        err.message(),
+        "insert can't be called with types [Map(Str, I64), Str, Bool]"
+    );
+}
+
+#[test]
+fn test_prog_91() {
+    let lexer = Lexer::new(PROG_91);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // The first send decided what the channel passes
+    assert_eq!(err.message(), "Trying to send Str on a channel of I64");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_91[span.start..span.end], "ch <- \"s\"");
+}
+
+#[test]
+fn test_prog_92() {
+    let lexer = Lexer::new(PROG_92);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // Assigning into the map decided the type of its values
+    assert_eq!(err.message(), "Only pointers can be dereferenced. Found I64");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_92[span.start..span.end], "*unwrap(m[\"a\"])");
+}
+
+#[test]
+fn test_prog_93() {
+    let lexer = Lexer::new(PROG_93);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Can't dereference a value whose type isn't known");
+}
+
+#[test]
+fn test_prog_94() {
+    let lexer = Lexer::new(PROG_94);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        memory: Some(100_000),
+        ..Limits::default()
+    };
+    // Squaring doubles the digits of a big integer, which count towards the limit
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "6561\n");
+        assert_eq!(res.unwrap_err().exhausted(), Some(interpreter::Resource::Memory));
+        assert!(meter.allocated() > 100_000 && meter.allocated() < 300_000);
+    }
+}
+
+#[test]
+fn test_prog_95() {
+    let lexer = Lexer::new(PROG_95);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        memory: Some(100_000),
+        ..Limits::default()
+    };
+    // The string is never made, since it would go over the limit
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "ab-ab-ab-ab-\n");
+        assert_eq!(res.unwrap_err().exhausted(), Some(interpreter::Resource::Memory));
+        assert!(meter.allocated() < 100_000);
+    }
+}
+
+#[test]
+fn test_prog_96() {
+    let lexer = Lexer::new(PROG_96);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    let err = interpret(FakeContext::new(&mut interpreted), &ast).unwrap_err();
+    assert_eq!(err.message(), "Repeating \"ab\" 9223372036854775807 times is too long");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_96[span.start..span.end], "repeat(\"ab\", 9223372036854775807)");
+}
+
+#[test]
+fn test_prog_97() {
+    let mut loader = FakeLoader::new(&[("sub/b", PROG_97_B), ("x/b/b", PROG_97_B)]);
+    let (sources, res) = modules::load(&mut loader, "main", PROG_97_MAIN.into());
+    let err = res.unwrap_err();
+    assert_eq!(err.message(), "In module main: sub/b is already imported as b");
+    assert_eq!(sources.locate(err.span().unwrap()), Some(("main", 2, 8)));
+}
+
+#[test]
+fn test_prog_98() {
+    let mut loader = FakeLoader::new(&[("broken", PROG_98_BROKEN)]);
+    let (sources, res) = modules::load(&mut loader, "main", PROG_98_MAIN.into());
+    let err = res.unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Failed to parse module broken: Only function calls can be deferred"
+    );
+    assert_eq!(err.kind(), modules::ErrorKind::Import);
+    assert_eq!(sources.locate(err.span().unwrap()), Some(("broken", 2, 3)));
+}
+
+#[test]
+fn test_prog_99() {
+    let mut loader = FakeLoader::new(&[]);
+    let (sources, res) = modules::load(&mut loader, "d/my.prog.iku", PROG_99.into());
+    let err = res.unwrap_err();
+    let message = "Failed to parse module d/my.prog.iku: Unexpected CloseParen";
+    assert!(err.message().starts_with(message));
+    assert_eq!(err.kind(), modules::ErrorKind::Parse);
+    assert_eq!(sources.locate(err.span().unwrap()), Some(("d/my.prog.iku", 2, 12)));
+}
+
+#[test]
+fn test_prog_100() {
+    let lexer = Lexer::new(PROG_100);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = ["3", "-3", "-6", "2", "9223372036854775808"];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_101() {
+    let lexer = Lexer::new(PROG_101);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Can't assign to total, which an anonymous function only has a copy of"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_101[span.start..span.end], "total");
+}
+
+#[test]
+fn test_prog_102() {
+    let lexer = Lexer::new(PROG_102);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    let expected = [
+        "(recovered, None)",
+        "325",
+        "(recovered, Some(Division by zero))",
+        "0",
+        "50005000",
+    ];
+    assert_eq!(interpreted, format!("{}\n", expected.join("\n")));
+}
+
+#[test]
+fn test_prog_104() {
+    let lexer = Lexer::new(PROG_104);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let x = || Expr::Name(Var::new("x".into(), Span::default()));
+    let not = |e| Expr::Not(Box::new(e));
+    let printed = |e| Expr::Call("print".into(), vec![e], Span::default());
+    let prints = [printed(not(not(x()))), printed(not(not(not(x()))))];
+    assert_eq!(ast.functions[0].body[1..], prints);
+    assert!(check(&mut ast).is_ok());
+    let optimized = optimizer::optimize(typer::check(&ast).unwrap());
+    let expected = [
+        "func main() {",
+        "  x := true  // Bool",
+        "  print(x)  // Tuple([])",
+        "  print(!x)  // Tuple([])",
+        "}",
+    ];
+    assert_eq!(optimized.to_string(), format!("{}\n", expected.join("\n")));
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "true\nfalse\n");
+}
+
+#[test]
+fn test_prog_105() {
+    let lexer = Lexer::new(PROG_105);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // Both names refer to the same map, so inserting through either decides its types
+    assert_eq!(
+        err.message(),
+        "insert can't be called with types [Map(I64, I64), I64, Str]"
+    );
+}
+
+#[test]
+fn test_prog_106() {
+    let lexer = Lexer::new(PROG_106);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // Sending on c decided what d passes too, since they're the same channel
+    assert_eq!(err.message(), "Trying to send Str on a channel of I64");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_106[span.start..span.end], "d <- \"s\"");
+}
+
+#[test]
+fn test_prog_107() {
+    let lexer = Lexer::new(PROG_107);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    // p and q point to the same value, so assigning through p decided its type
+    assert_eq!(
+        err.message(),
+        "Trying to assign type Option(Str) through Pointer(Option(I64))"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_107[span.start..span.end], "*q");
+}
+
+#[test]
+fn test_prog_108() {
+    let lexer = Lexer::new(PROG_108);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut buf = String::new();
+    let ctx = FakeContext::new(&mut buf);
+    let err = interpret(ctx, &ast).unwrap_err();
+    assert_eq!(err.message(), "Trying to call undefined function main");
+}
+
+#[test]
+fn test_prog_109() {
+    let lexer = Lexer::new(PROG_109);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "42\n42\n");
+}
+
+#[test]
+fn test_prog_110() {
+    let lexer = Lexer::new(PROG_110);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Can't take the address of variable x, since & puts a copy of it on the heap"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_110[span.start..span.end], "&x");
+}
+
+#[test]
+fn test_prog_111() {
+    let lexer = Lexer::new(PROG_111);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let mut interpreted = String::new();
+    assert!(interpret(FakeContext::new(&mut interpreted), &ast).is_ok());
+    assert_eq!(interpreted, "20000\n");
+    let program = typer::check(&ast).unwrap();
+    let limits = Limits {
+        memory: Some(4_000_000),
+        ..Limits::default()
+    };
+    // The arrays hold 1, 2, 4, and so on up to 32768 values
+    for (printed, res, meter) in [run_limited(&program, limits), run_limited_vm(&program, limits)] {
+        assert_eq!(printed, "20000\n");
+        assert!(res.is_ok());
+        let cell = std::mem::size_of::<Litteral>();
+        assert!(meter.allocated() >= 65535 * cell && meter.allocated() < 70000 * cell);
+    }
+}
+
+#[test]
+fn test_prog_112() {
+    let lexer = Lexer::new(PROG_112);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    // Constants are type checked by evaluating them, which fails here
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Op Add only works on numbers, but got Bool(true) and Bool(true)"
+    );
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_112[span.start..span.end], "true + true");
+}
+
+#[test]
+fn test_prog_113() {
+    let lexer = Lexer::new(PROG_113);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Constant LIMIT is already defined");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_113[span.start..span.end], "LIMIT");
+    assert_eq!(span.line_col(PROG_113).0, 2);
+}
+
+#[test]
+fn test_prog_114() {
+    let lexer = Lexer::new(PROG_114);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Constant size is already defined as a function");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_114[span.start..span.end], "size");
+    assert_eq!(span.line_col(PROG_114).0, 1);
+}
+
+#[test]
+fn test_prog_115() {
+    let lexer = Lexer::new(PROG_115);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "Constant None is already defined");
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_115[span.start..span.end], "None");
+    assert_eq!(span.line_col(PROG_115).0, 2);
+}
+
+#[test]
+fn test_prog_116() {
+    let lexer = Lexer::new(PROG_116);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    let err = check(&mut ast).unwrap_err();
+    assert_eq!(err.message(), "The variable a is declared twice in a pattern");
+    // The error is where it's declared the second time
+    let span = err.span().unwrap();
+    assert_eq!(&PROG_116[span.start..span.end], "a");
+    assert_eq!(span.line_col(PROG_116), (2, 11));
+}
+
+// Compare how long the tree-walker and the VM take to run a program making
+// lots of calls. The VM is around 4 times as fast, in debug and release builds,
+// which shows with `cargo test --release bench_fib -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn bench_fib() {
+    let lexer = Lexer::new(PROG_103);
+    let mut ast = ASTParser::new().parse(lexer).unwrap();
+    assert!(check(&mut ast).is_ok());
+    let program = typer::check(&ast).unwrap();
+    let optimized = optimizer::optimize(program.clone());
+    let runs: [(&str, Backend, &ir::Program); 3] = [
+        ("tree-walker", run_seeded, &program),
+        ("vm", run_seeded_vm, &program),
+        ("vm -O", run_seeded_vm, &optimized),
+    ];
+    for (name, run, program) in runs {
+        let start = Instant::now();
+        let (printed, res, _) = run(program, Limits::default(), 0);
+        println!("{}: {:?}", name, start.elapsed());
+        assert!(res.is_ok());
+        assert_eq!(printed, "196418\n");
+    }
+}
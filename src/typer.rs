@@ -1,16 +1,44 @@
 use crate::ast::*;
-use crate::scopes::Scopes;
+use crate::builtins;
+use crate::interpreter::{apply_op, negate};
+use crate::ir::{self, Kind};
 use std::collections::HashMap;
+use std::fmt;
 
 /// Represents the type of error generated by the type checker
 #[derive(Clone, Debug)]
 pub struct Error {
     message: String,
+    // Where in the source this error happened, if we know
+    span: Option<Span>,
+}
+
+impl Error {
+    /// The message describing what went wrong
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The location in the source code responsible for this error, if known
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
+impl Error {
+    /// Attach a span to this error, unless a more precise one is already there
+    pub fn at(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
 }
 
 impl From<String> for Error {
     fn from(string: String) -> Self {
-        Error { message: string }
+        Error {
+            message: string,
+            span: None,
+        }
     }
 }
 
@@ -18,46 +46,343 @@ impl From<String> for Error {
 pub type Result<T> = std::result::Result<T, Error>;
 
 fn fail<T, S: Into<String>>(message: S) -> Result<T> {
+    Err(Error::from(message.into()))
+}
+
+fn fail_at<T, S: Into<String>>(span: Span, message: S) -> Result<T> {
     let message = message.into();
-    Err(Error { message })
+    Err(Error {
+        message,
+        span: Some(span),
+    })
 }
 
+
 /// Represents the basic types that exist
 ///
 /// At the moment, this only includes premium types.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum Type {
     /// Represents the primitive 64 bit signed integer type
     I64,
+    /// Represents the arbitrary precision integer type
+    BigInt,
     /// Represents the primitive string type
     Str,
     /// Represents the primitive boolean type
     Bool,
+    /// Represents a single unicode character
+    Rune,
+    /// Represents a single byte
+    U8,
     /// Represents a tuple type
     Tuple(Vec<Type>),
+    /// Represents a slice of values of a given type
+    Slice(Box<Type>),
+    /// Represents a map from keys of one type to values of another
+    Map(Box<Type>, Box<Type>),
+    /// Represents a value of a given type that may be missing
+    Option(Box<Type>),
+    /// Represents either a successful value of one type, or an error of another
+    Result(Box<Type>, Box<Type>),
+    /// Represents a function taking arguments of some types, and returning another
+    Function(Vec<Type>, Box<Type>),
+    /// Represents a channel passing values of a given type between tasks
+    Chan(Box<Type>),
+    /// Represents a pointer to a value of a given type on the heap
+    Pointer(Box<Type>),
+    /// Represents a newtype, which is only compatible with itself
+    Named(String),
+    /// Represents a type we don't know yet, like the result of `panic`
+    ///
+    /// This can be unified with any other type.
+    Unknown,
+    /// Represents a type we're still inferring, like the elements of `[]`
+    ///
+    /// Every copy of a value shares the types inferred for it, so what using
+    /// one of them tells us holds for all of them.
+    Var(usize),
 }
 
 impl Type {
-    fn from_name(name: &TypeName) -> Result<Type> {
+    /// Combine two types that should be the same, filling in unknown parts.
+    ///
+    /// This returns None if the two types are incompatible.
+    ///
+    /// Types still being inferred are treated like unknown types, which the
+    /// type checker then decides with `Typer::unify`.
+    pub fn unify(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Unknown, t) | (t, Type::Unknown) => Some(t.clone()),
+            (Type::Var(_), t) | (t, Type::Var(_)) => Some(t.clone()),
+            (a, b) => a.zip_inner(b, Type::unify),
+        }
+    }
+
+    // Combine the types directly inside of two types of the same shape,
+    // returning None if their shapes are different
+    fn zip_inner<F>(&self, other: &Type, mut f: F) -> Option<Type>
+    where
+        F: FnMut(&Type, &Type) -> Option<Type>,
+    {
+        match (self, other) {
+            (Type::Slice(a), Type::Slice(b)) => Some(Type::Slice(Box::new(f(a, b)?))),
+            (Type::Option(a), Type::Option(b)) => Some(Type::Option(Box::new(f(a, b)?))),
+            (Type::Chan(a), Type::Chan(b)) => Some(Type::Chan(Box::new(f(a, b)?))),
+            (Type::Pointer(a), Type::Pointer(b)) => Some(Type::Pointer(Box::new(f(a, b)?))),
+            (Type::Map(k1, v1), Type::Map(k2, v2)) => {
+                Some(Type::Map(Box::new(f(k1, k2)?), Box::new(f(v1, v2)?)))
+            }
+            (Type::Result(t1, e1), Type::Result(t2, e2)) => {
+                Some(Type::Result(Box::new(f(t1, t2)?), Box::new(f(e1, e2)?)))
+            }
+            (Type::Function(a, r1), Type::Function(b, r2)) if a.len() == b.len() => {
+                let mut args = Vec::with_capacity(a.len());
+                for (a, b) in a.iter().zip(b.iter()) {
+                    args.push(f(a, b)?);
+                }
+                Some(Type::Function(args, Box::new(f(r1, r2)?)))
+            }
+            (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => {
+                let mut types = Vec::with_capacity(a.len());
+                for (a, b) in a.iter().zip(b.iter()) {
+                    types.push(f(a, b)?);
+                }
+                Some(Type::Tuple(types))
+            }
+            (a, b) if a == b => Some(a.clone()),
+            _ => None,
+        }
+    }
+
+    // Rebuild a type, changing the types directly inside of it
+    fn map_inner<F: FnMut(&Type) -> Type>(&self, mut f: F) -> Type {
+        match self {
+            Type::Tuple(types) => Type::Tuple(types.iter().map(f).collect()),
+            Type::Slice(t) => Type::Slice(Box::new(f(t))),
+            Type::Map(k, v) => Type::Map(Box::new(f(k)), Box::new(f(v))),
+            Type::Option(t) => Type::Option(Box::new(f(t))),
+            Type::Result(t, e) => Type::Result(Box::new(f(t)), Box::new(f(e))),
+            Type::Function(args, ret) => {
+                let args = args.iter().map(&mut f).collect();
+                Type::Function(args, Box::new(f(ret)))
+            }
+            Type::Chan(t) => Type::Chan(Box::new(f(t))),
+            Type::Pointer(t) => Type::Pointer(Box::new(f(t))),
+            t => t.clone(),
+        }
+    }
+
+    // The types directly inside of a type
+    fn inner(&self) -> Vec<&Type> {
+        match self {
+            Type::Tuple(types) => types.iter().collect(),
+            Type::Function(args, ret) => args.iter().chain(std::iter::once(&**ret)).collect(),
+            Type::Map(a, b) | Type::Result(a, b) => vec![a, b],
+            Type::Slice(t) | Type::Option(t) | Type::Chan(t) | Type::Pointer(t) => vec![t],
+            _ => Vec::new(),
+        }
+    }
+
+    // Whether a type contains a given type being inferred
+    fn contains_var(&self, var: usize) -> bool {
+        *self == Type::Var(var) || self.inner().into_iter().any(|t| t.contains_var(var))
+    }
+}
+
+// Types still being inferred are shown like unknown types in error messages,
+// since that's what they are to whoever reads them
+impl fmt::Debug for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::I64 => f.write_str("I64"),
+            Type::BigInt => f.write_str("BigInt"),
+            Type::Str => f.write_str("Str"),
+            Type::Bool => f.write_str("Bool"),
+            Type::Rune => f.write_str("Rune"),
+            Type::U8 => f.write_str("U8"),
+            Type::Tuple(types) => f.debug_tuple("Tuple").field(types).finish(),
+            Type::Slice(t) => f.debug_tuple("Slice").field(t).finish(),
+            Type::Map(k, v) => f.debug_tuple("Map").field(k).field(v).finish(),
+            Type::Option(t) => f.debug_tuple("Option").field(t).finish(),
+            Type::Result(t, e) => f.debug_tuple("Result").field(t).field(e).finish(),
+            Type::Function(args, ret) => f.debug_tuple("Function").field(args).field(ret).finish(),
+            Type::Chan(t) => f.debug_tuple("Chan").field(t).finish(),
+            Type::Pointer(t) => f.debug_tuple("Pointer").field(t).finish(),
+            Type::Named(name) => f.debug_tuple("Named").field(name).finish(),
+            Type::Unknown | Type::Var(_) => f.write_str("Unknown"),
+        }
+    }
+}
+
+// Replace the types still being inferred in a type with unknown types, once
+// nothing can tell us any more about them
+fn forget_vars(typ: &Type) -> Type {
+    match typ {
+        Type::Var(_) => Type::Unknown,
+        t => t.map_inner(forget_vars),
+    }
+}
+
+// The unit type
+fn unit() -> Type {
+    Type::Tuple(vec![])
+}
+
+// The names of builtin types and constructors, which can't be declared again
+const BUILTIN_TYPES: [&str; 14] = [
+    "I64", "BigInt", "String", "Bool", "Rune", "U8", "Map", "Option", "Result", "Chan", "Some",
+    "None", "Ok", "Err",
+];
+
+/// Keeps track of the types declared in a program, with `type` or `newtype`
+struct TypeEnv {
+    decls: HashMap<String, TypeDecl>,
+}
+
+impl TypeEnv {
+    fn from_ast(ast: &AST) -> Result<Self> {
+        let mut decls = HashMap::with_capacity(ast.types.len());
+        for decl in &ast.types {
+            if BUILTIN_TYPES.contains(&decl.name.as_str()) || decls.contains_key(&decl.name) {
+                return fail_at(decl.span, format!("Type {} is already defined", decl.name));
+            }
+            decls.insert(decl.name.clone(), decl.clone());
+        }
+        let env = TypeEnv { decls };
+        // Resolving every declaration up front catches cycles, as well as unknown types
+        for decl in &ast.types {
+            let mut aliases = vec![decl.name.as_str()];
+            let mut typ = env
+                .resolve_in(&decl.typ, &mut aliases)
+                .map_err(|e| e.at(decl.span))?;
+            let mut wrapped = vec![decl.name.as_str()];
+            while let Type::Named(name) = typ {
+                if wrapped.contains(&name.as_str()) {
+                    wrapped.push(&env.decls[&name].name);
+                    return fail_at(
+                        decl.span,
+                        format!(
+                            "Newtypes wrap each other in a cycle: {}",
+                            wrapped.join(" -> ")
+                        ),
+                    );
+                }
+                wrapped.push(&env.decls[&name].name);
+                typ = env.resolve(&env.decls[&name].typ)?;
+            }
+        }
+        Ok(env)
+    }
+
+    /// Find the type a name refers to
+    fn resolve(&self, name: &TypeName) -> Result<Type> {
+        self.resolve_in(name, &mut Vec::new())
+    }
+
+    // This keeps track of the aliases we're in the middle of resolving, to detect cycles
+    fn resolve_in<'a>(&'a self, name: &TypeName, aliases: &mut Vec<&'a str>) -> Result<Type> {
         match name {
             TypeName::Name(t) if t == "I64" => Ok(Type::I64),
+            TypeName::Name(t) if t == "BigInt" => Ok(Type::BigInt),
             TypeName::Name(t) if t == "String" => Ok(Type::Str),
             TypeName::Name(t) if t == "Bool" => Ok(Type::Bool),
+            TypeName::Name(t) if t == "Rune" => Ok(Type::Rune),
+            TypeName::Name(t) if t == "U8" => Ok(Type::U8),
             TypeName::Tuple(ts) => {
                 let mut types = Vec::with_capacity(ts.len());
                 for t in ts {
-                    types.push(Self::from_name(t)?);
+                    types.push(self.resolve_in(t, aliases)?);
                 }
                 Ok(Type::Tuple(types))
             }
-            TypeName::Name(t) => fail(format!("Unknown type {}", t)),
+            TypeName::Slice(t) => Ok(Type::Slice(Box::new(self.resolve_in(t, aliases)?))),
+            TypeName::Pointer(t) => Ok(Type::Pointer(Box::new(self.resolve_in(t, aliases)?))),
+            TypeName::Apply(t, args) => {
+                let mut types = Vec::with_capacity(args.len());
+                for a in args {
+                    types.push(self.resolve_in(a, aliases)?);
+                }
+                match (t.as_str(), types.as_slice()) {
+                    ("Map", [k, v]) => {
+                        if !self.is_hashable(k) {
+                            return fail(format!("{:?} can't be used as the key of a map", k));
+                        }
+                        Ok(Type::Map(Box::new(k.clone()), Box::new(v.clone())))
+                    }
+                    ("Option", [t]) => Ok(Type::Option(Box::new(t.clone()))),
+                    ("Chan", [t]) => Ok(Type::Chan(Box::new(t.clone()))),
+                    ("Result", [t, e]) => Ok(Type::Result(Box::new(t.clone()), Box::new(e.clone()))),
+                    _ => fail(format!("Unknown type {}{:?}", t, types)),
+                }
+            }
+            TypeName::Func(args, ret) => {
+                let mut arg_types = Vec::with_capacity(args.len());
+                for a in args {
+                    arg_types.push(self.resolve_in(a, aliases)?);
+                }
+                let ret = match ret {
+                    Some(ret) => self.resolve_in(ret, aliases)?,
+                    None => unit(),
+                };
+                Ok(Type::Function(arg_types, Box::new(ret)))
+            }
+            TypeName::Name(t) => match self.decls.get(t) {
+                // Newtypes are only referred to by name, so they can contain themselves
+                Some(decl) if decl.newtype => Ok(Type::Named(t.clone())),
+                Some(decl) => {
+                    if let Some(i) = aliases.iter().position(|a| a == t) {
+                        let mut cycle = aliases[i..].to_vec();
+                        cycle.push(&decl.name);
+                        return fail_at(
+                            decl.span,
+                            format!(
+                                "Type aliases refer to each other in a cycle: {}",
+                                cycle.join(" -> ")
+                            ),
+                        );
+                    }
+                    aliases.push(&decl.name);
+                    let res = self.resolve_in(&decl.typ, aliases);
+                    aliases.pop();
+                    res
+                }
+                None => fail(format!("Unknown type {}", t)),
+            },
         }
     }
-}
 
-// The unit type
-fn unit() -> Type {
-    Type::Tuple(vec![])
+    // Find the type wrapped by a newtype, if a name refers to one
+    fn underlying(&self, name: &str) -> Option<Type> {
+        match self.decls.get(name) {
+            // Every declaration was resolved when creating the environment
+            Some(decl) if decl.newtype => self.resolve(&decl.typ).ok(),
+            _ => None,
+        }
+    }
+
+    /// Check whether values of this type can be used as the keys of a map
+    fn is_hashable(&self, typ: &Type) -> bool {
+        self.is_hashable_in(typ, &mut Vec::new())
+    }
+
+    // A newtype can contain itself, so we keep track of the ones we've seen
+    fn is_hashable_in(&self, typ: &Type, seen: &mut Vec<String>) -> bool {
+        match typ {
+            Type::I64 | Type::BigInt | Type::Str | Type::Bool | Type::Rune | Type::U8 => true,
+            Type::Unknown | Type::Var(_) => true,
+            Type::Tuple(types) => types.iter().all(|t| self.is_hashable_in(t, seen)),
+            Type::Named(name) if seen.contains(name) => true,
+            Type::Named(name) => match self.underlying(name) {
+                Some(t) => {
+                    seen.push(name.clone());
+                    self.is_hashable_in(&t, seen)
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 /// Types the interface of a function
@@ -67,14 +392,94 @@ struct FunctionType {
 }
 
 impl FunctionType {
-    fn from_func(func: &Function) -> Result<Self> {
-        let mut args = Vec::with_capacity(func.args.len());
-        for (_, type_name) in &func.args {
-            args.push(Type::from_name(type_name)?)
+    fn from_func(env: &TypeEnv, func: &Function) -> Result<Self> {
+        let arg_names: Vec<TypeName> = func.args.iter().map(|(_, t)| t.clone()).collect();
+        Self::from_parts(env, &arg_names, func.ret.as_ref())
+    }
+
+    fn from_parts(env: &TypeEnv, arg_names: &[TypeName], ret: Option<&TypeName>) -> Result<Self> {
+        let mut args = Vec::with_capacity(arg_names.len());
+        for type_name in arg_names {
+            args.push(env.resolve(type_name)?)
         }
-        let ret = func.ret.as_ref().map(Type::from_name).unwrap_or(Ok(unit()))?;
+        let ret = ret.map(|t| env.resolve(t)).unwrap_or(Ok(unit()))?;
         Ok(FunctionType { args, ret })
     }
+
+    // The type of a value holding a function with this interface
+    fn to_type(&self) -> Type {
+        Type::Function(self.args.clone(), Box::new(self.ret.clone()))
+    }
+
+    // The types of the local variables of a function with this interface, starting
+    // with its arguments, while the others aren't known yet
+    fn frame(&self, locals: &Locals) -> Vec<Type> {
+        let mut types = self.args.clone();
+        types.resize(locals.slots, Type::Unknown);
+        types
+    }
+}
+
+// Make sure that a function is called with arguments of the right types
+fn check_args(func: &str, expected: &[Type], found: &[Type]) -> Result<()> {
+    let compatible = found.len() == expected.len()
+        && found
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| a.unify(b).is_some());
+    if !compatible {
+        return fail(format!(
+            "Trying to call {} with types {:?}, expected {:?} instead",
+            func, found, expected
+        ));
+    }
+    Ok(())
+}
+
+// Anonymous functions capture variables by value, so assigning to one of
+// them would only change the copy, and never the variable it came from
+fn check_assignable(var: &Var) -> Result<()> {
+    match var.binding {
+        Binding::Captured(_) => fail_at(
+            var.span,
+            format!("Can't assign to {}, which an anonymous function only has a copy of", var.name),
+        ),
+        _ => Ok(()),
+    }
+}
+
+// Find the type of a litteral value
+fn type_litt(litt: &Litteral) -> Type {
+    match litt {
+        Litteral::Str(_) => Type::Str,
+        Litteral::I64(_) => Type::I64,
+        Litteral::BigInt(_) => Type::BigInt,
+        Litteral::Bool(_) => Type::Bool,
+        // Channels don't remember the type of their values
+        Litteral::Chan(_) => Type::Chan(Box::new(Type::Unknown)),
+        Litteral::Pointer(_) => Type::Pointer(Box::new(Type::Unknown)),
+        Litteral::Rune(_) => Type::Rune,
+        Litteral::U8(_) => Type::U8,
+        Litteral::Tuple(litts) => Type::Tuple(litts.iter().map(type_litt).collect()),
+        Litteral::Slice(s) => {
+            let elem = s.get(0).map_or(Type::Unknown, |l| type_litt(&l));
+            Type::Slice(Box::new(elem))
+        }
+        Litteral::Map(m) => match m.entries().first() {
+            None => Type::Map(Box::new(Type::Unknown), Box::new(Type::Unknown)),
+            Some((k, v)) => Type::Map(Box::new(type_litt(k)), Box::new(type_litt(v))),
+        },
+        Litteral::Option(o) => {
+            let inner = o.as_ref().map_or(Type::Unknown, |l| type_litt(l));
+            Type::Option(Box::new(inner))
+        }
+        Litteral::Result(Ok(l)) => Type::Result(Box::new(type_litt(l)), Box::new(Type::Unknown)),
+        Litteral::Result(Err(l)) => Type::Result(Box::new(Type::Unknown), Box::new(type_litt(l))),
+        // Functions only become values while interpreting, so they never
+        // appear as litterals in the source
+        Litteral::Function(_) => Type::Unknown,
+        Litteral::Newtype(name, _) => Type::Named(name.clone()),
+    }
 }
 
 /// This is used to reference functions
@@ -84,10 +489,10 @@ struct FunctionTable {
 }
 
 impl FunctionTable {
-    fn from_ast(ast: &AST) -> Result<Self> {
+    fn from_ast(ast: &AST, env: &TypeEnv) -> Result<Self> {
         let mut functions = HashMap::with_capacity(ast.functions.len());
         for f in &ast.functions {
-            let typ = FunctionType::from_func(f)?;
+            let typ = FunctionType::from_func(env, f)?;
             functions.insert(f.name.clone(), typ);
         }
         Ok(FunctionTable { functions })
@@ -98,214 +503,1070 @@ impl FunctionTable {
     }
 }
 
+// This evaluates constants, making sure they don't depend on themselves
+struct ConstEvaluator<'a> {
+    env: &'a TypeEnv,
+    decls: HashMap<&'a str, &'a Const>,
+    values: HashMap<String, Litteral>,
+    // The constants we're in the middle of evaluating
+    evaluating: Vec<&'a str>,
+}
+
+impl<'a> ConstEvaluator<'a> {
+    fn eval_const(&mut self, name: &'a str) -> Result<Litteral> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(value.clone());
+        }
+        let decl = self.decls[name];
+        if let Some(i) = self.evaluating.iter().position(|n| *n == name) {
+            let mut cycle = self.evaluating[i..].to_vec();
+            cycle.push(name);
+            return fail_at(
+                decl.span,
+                format!("Constants depend on each other in a cycle: {}", cycle.join(" -> ")),
+            );
+        }
+        self.evaluating.push(name);
+        let value = self.eval_expr(decl, &decl.value)?;
+        self.evaluating.pop();
+        if let Some(type_name) = &decl.typ {
+            let declared = self.env.resolve(type_name).map_err(|e| e.at(decl.span))?;
+            let actual = type_litt(&value);
+            if declared.unify(&actual).is_none() {
+                return fail_at(
+                    decl.span,
+                    format!(
+                        "Constant {} is declared as {:?}, but has type {:?}",
+                        name, declared, actual
+                    ),
+                );
+            }
+        }
+        self.values.insert(name.into(), value.clone());
+        Ok(value)
+    }
+
+    fn eval_expr(&mut self, decl: &'a Const, expr: &'a Expr) -> Result<Litteral> {
+        match expr {
+            Expr::Litt(l) => Ok(l.clone()),
+            Expr::Name(var) if self.decls.contains_key(var.name.as_str()) => {
+                self.eval_const(&var.name)
+            }
+            Expr::BinOp(op, left, right, span) => {
+                let left = self.eval_expr(decl, left)?;
+                let right = self.eval_expr(decl, right)?;
+                let (left_type, right_type) = (type_litt(&left), type_litt(&right));
+                if left_type.unify(&right_type).is_none() {
+                    return fail_at(
+                        *span,
+                        format!(
+                            "Trying to apply binary options to mismatched types {:?} and {:?}",
+                            left_type, right_type
+                        ),
+                    );
+                }
+                apply_op(*op, left, right).or_else(|e| fail_at(*span, e.message()))
+            }
+            Expr::ConditionalOp(op, left, right) => {
+                match (self.eval_expr(decl, left)?, self.eval_expr(decl, right)?) {
+                    (Litteral::Bool(l), Litteral::Bool(r)) => Ok(Litteral::Bool(match op {
+                        BoolOp::And => l && r,
+                        BoolOp::Or => l || r,
+                    })),
+                    (l, r) => fail_at(
+                        decl.span,
+                        format!("Boolean op {:?} doesn't apply to {:?} and {:?}", op, l, r),
+                    ),
+                }
+            }
+            Expr::Not(e) => match self.eval_expr(decl, e)? {
+                Litteral::Bool(b) => Ok(Litteral::Bool(!b)),
+                l => fail_at(decl.span, format!("The operator ! only applies to Bool. Found {:?}", l)),
+            },
+            Expr::Neg(e, span) => {
+                let operand = self.eval_expr(decl, e)?;
+                negate(operand).or_else(|e| fail_at(*span, e.message()))
+            }
+            Expr::MakeTuple(exprs) => {
+                let mut litterals = Vec::with_capacity(exprs.len());
+                for e in exprs {
+                    litterals.push(self.eval_expr(decl, e)?);
+                }
+                Ok(Litteral::Tuple(litterals))
+            }
+            _ => fail_at(
+                decl.span,
+                format!("The value of constant {} isn't a constant expression", decl.name),
+            ),
+        }
+    }
+}
+
+// Evaluate the constants declared in a program.
+//
+// Constants can only be made of litterals, operators, and other constants.
+// Operators are applied exactly like they are in the interpreter. Their names
+// can't be used by anything else at the top level, including builtins.
+fn eval_consts(env: &TypeEnv, ast: &AST) -> Result<HashMap<String, Litteral>> {
+    let mut decls = HashMap::with_capacity(ast.consts.len());
+    for c in &ast.consts {
+        let name = c.name.as_str();
+        let builtin = name == "print" || builtins::is_builtin(name);
+        if builtin || builtins::constant(name).is_some() || decls.contains_key(name) {
+            return fail_at(c.span, format!("Constant {} is already defined", name));
+        }
+        if ast.functions.iter().any(|f| f.name == name) {
+            return fail_at(c.span, format!("Constant {} is already defined as a function", name));
+        }
+        decls.insert(name, c);
+    }
+    let mut evaluator = ConstEvaluator {
+        env,
+        decls,
+        values: HashMap::with_capacity(ast.consts.len()),
+        evaluating: Vec::new(),
+    };
+    for c in &ast.consts {
+        evaluator.eval_const(&c.name)?;
+    }
+    Ok(evaluator.values)
+}
+
 /// This holds the state we need when type checking
 struct Typer {
-    scopes: Scopes<Type>,
+    // The types of the local variables of the function we're checking, by slot
+    locals: Vec<Type>,
+    // The types of the variables captured by the anonymous function we're checking
+    captured: Vec<Type>,
+    // The declared return type of the function we're currently checking
+    ret: Type,
+    // The values of the constants in the program
+    consts: HashMap<String, Litteral>,
+    // The types declared in the program
+    env: TypeEnv,
+    // What we've found out so far about each type being inferred, which is
+    // Unknown until something tells us
+    inferred: Vec<Type>,
 }
 
 impl Typer {
-    fn new() -> Self {
+    fn new(env: TypeEnv) -> Self {
         Typer {
-            scopes: Scopes::new(),
+            locals: Vec::new(),
+            captured: Vec::new(),
+            ret: unit(),
+            consts: HashMap::new(),
+            env,
+            inferred: Vec::new(),
+        }
+    }
+
+    // The type of a variable, given the tables of top level functions
+    fn var_type(&self, tbl: &FunctionTable, var: &Var) -> Result<Type> {
+        let typ = match var.binding {
+            Binding::Local(slot) => Some(self.locals[slot].clone()),
+            Binding::Captured(i) => Some(self.captured[i].clone()),
+            Binding::Global => self
+                .consts
+                .get(&var.name)
+                .map(type_litt)
+                .or_else(|| builtins::type_constant(&var.name))
+                .or_else(|| tbl.get(&var.name).map(FunctionType::to_type)),
+            Binding::Unresolved => panic!("Type checking {} before resolving it", var.name),
+        };
+        typ.ok_or(Error::from(format!(
+            "Trying to use undefined variable {}",
+            var.name
+        )))
+    }
+
+    // Give a type to a local variable, or one captured by an anonymous function
+    fn set_var(&mut self, var: &Var, typ: Type) {
+        match var.binding {
+            Binding::Local(slot) => self.locals[slot] = typ,
+            Binding::Captured(i) => self.captured[i] = typ,
+            binding => panic!("Assigning to {}, which is {:?}", var.name, binding),
+        }
+    }
+
+    // Make a new type to infer, which nothing is known about yet
+    fn fresh_var(&mut self) -> Type {
+        self.inferred.push(Type::Unknown);
+        Type::Var(self.inferred.len() - 1)
+    }
+
+    // Replace the unknown types inside of the type of a new value with types to
+    // infer, like the elements of `[]`, which every copy of that value shares
+    fn infer(&mut self, typ: &Type) -> Type {
+        match typ {
+            Type::Unknown => self.fresh_var(),
+            t => t.map_inner(|t| self.infer(t)),
+        }
+    }
+
+    // Replace the types being inferred in a type with what we know about them so far
+    fn resolve(&self, typ: &Type) -> Type {
+        match typ {
+            Type::Var(i) if self.inferred[*i] != Type::Unknown => self.resolve(&self.inferred[*i]),
+            t => t.map_inner(|t| self.resolve(t)),
         }
     }
 
-    fn type_litt(&self, litt: &Litteral) -> Type {
-        match litt {
-            Litteral::Str(_) => Type::Str,
-            Litteral::I64(_) => Type::I64,
-            Litteral::Bool(_) => Type::Bool,
-            Litteral::Tuple(litts) => {
-                Type::Tuple(litts.iter().map(|l| self.type_litt(l)).collect())
+    // Combine two types like `Type::unify`, also deciding the types being
+    // inferred in them, so that every copy of a value has to agree with them
+    fn unify(&mut self, a: &Type, b: &Type) -> Option<Type> {
+        let unified = self.unify_inferred(a, b)?;
+        Some(self.resolve(&unified))
+    }
+
+    fn unify_inferred(&mut self, a: &Type, b: &Type) -> Option<Type> {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        match (&a, &b) {
+            (Type::Unknown, t) | (t, Type::Unknown) => Some(t.clone()),
+            (Type::Var(i), Type::Var(j)) if i == j => Some(a.clone()),
+            (Type::Var(i), t) | (t, Type::Var(i)) => {
+                // A type can't contain itself
+                if t.contains_var(*i) {
+                    return None;
+                }
+                self.inferred[*i] = self.infer(t);
+                Some(Type::Var(*i))
             }
+            (a, b) => a.zip_inner(b, |a, b| self.unify_inferred(a, b)),
         }
     }
 
-    fn type_expr(&mut self, tbl: &FunctionTable, expr: &Expr) -> Result<Type> {
+    // Every case of a select must produce the same type, like the branches of an if
+    fn unify_cases(&mut self, a: &Type, b: &Type) -> Result<Type> {
+        self.unify(a, b).ok_or_else(|| {
+            Error::from(format!(
+                "The cases of a select must have the same type. Found {:?} and {:?}",
+                a, b
+            ))
+        })
+    }
+
+    // Using a value can tell us more about its type, like the keys and values
+    // of an empty map once something is assigned into it, which every copy of
+    // that value has to agree with
+    fn refine(&mut self, target: &ir::Expr, typ: Type) {
+        self.unify(&target.typ, &typ);
+    }
+
+    // Refine the arguments of a call with the types the function expects, since
+    // the function may put values in them, like in the values of a map
+    fn refine_args(&mut self, args: &[ir::Expr], expected: &[Type]) {
+        for (arg, typ) in args.iter().zip(expected) {
+            self.refine(arg, typ.clone());
+        }
+    }
+
+    // Try and evaluate an expression made only of litterals, constants, and operators.
+    //
+    // This returns None if the expression isn't constant, or fails to evaluate.
+    fn fold_constant(&self, expr: &Expr) -> Option<Litteral> {
         match expr {
-            Expr::Litt(litt) => Ok(self.type_litt(&litt)),
-            Expr::Declare(name, expr) => {
-                let typ = self.type_expr(tbl, expr)?;
-                self.scopes.create(name, typ.clone());
-                Ok(typ)
-            }
-            Expr::Assign(name, expr) => {
-                let current_type = self.scopes.get(&name).ok_or(Error::from(format!(
-                    "Trying to assign to undefined variable {}",
-                    name
+            Expr::Litt(l) => Some(l.clone()),
+            // Local variables shadow constants
+            Expr::Name(var) if var.binding == Binding::Global => {
+                self.consts.get(&var.name).cloned()
+            }
+            Expr::BinOp(op, left, right, _) => {
+                apply_op(*op, self.fold_constant(left)?, self.fold_constant(right)?).ok()
+            }
+            Expr::Neg(e, _) => negate(self.fold_constant(e)?).ok(),
+            _ => None,
+        }
+    }
+
+    // Type both operands of a binary operation, along with the type of its result
+    fn type_bin_op(
+        &mut self,
+        tbl: &FunctionTable,
+        op: Op,
+        left: &Expr,
+        right: &Expr,
+        span: Span,
+    ) -> Result<(ir::Expr, ir::Expr, Type)> {
+        let typed_left = self.type_expr(tbl, left)?;
+        let typed_right = self.type_expr(tbl, right)?;
+        let (left_type, right_type) = (&typed_left.typ, &typed_right.typ);
+        let left_type = self.unify(left_type, right_type).ok_or(Error::from(format!(
+            "Trying to apply binary options to mismatched types {:?} and {:?}",
+            left_type, right_type
+        )))?;
+        let numeric = match op {
+            Op::Equal | Op::NotEqual => false,
+            Op::Leq
+            | Op::Less
+            | Op::Geq
+            | Op::Greater
+            | Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Mod
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::AndNot
+            | Op::Shl
+            | Op::Shr => true,
+        };
+        let bitwise = matches!(
+            op,
+            Op::BitAnd | Op::BitOr | Op::BitXor | Op::AndNot | Op::Shl | Op::Shr
+        );
+        if let Type::Function(_, _) = left_type {
+            return fail_at(span, "Functions can't be compared");
+        }
+        if bitwise && left_type != Type::I64 {
+            return fail_at(
+                span,
+                format!("Binary op {:?} requires I64, but found {:?}", op, left_type),
+            );
+        }
+        // Strings can be concatenated and ordered, and runes and bytes can be
+        // ordered, but none of them can be used in arithmetic
+        let ordering = matches!(op, Op::Leq | Op::Less | Op::Geq | Op::Greater);
+        let non_numeric = match left_type {
+            Type::Str => ordering || op == Op::Add,
+            Type::Rune | Type::U8 => ordering,
+            _ => false,
+        };
+        if numeric && !non_numeric && left_type != Type::I64 && left_type != Type::BigInt {
+            return fail(format!(
+                "Binary op {:?} requires I64 or BigInt, but found {:?}",
+                op, left_type
+            ));
+        }
+        if let (Op::Div, Some(r)) | (Op::Mod, Some(r)) = (op, self.fold_constant(right)) {
+            if r == Litteral::I64(0) {
+                return fail_at(span, "Division by zero");
+            }
+        }
+        if let (Some(l), Some(r)) = (self.fold_constant(left), self.fold_constant(right)) {
+            if let Err(e) = apply_op(op, l, r) {
+                return fail_at(span, e.message());
+            }
+        }
+        let typ = match op {
+            Op::Equal | Op::NotEqual | Op::Leq | Op::Less | Op::Geq | Op::Greater => Type::Bool,
+            Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Mod
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::AndNot
+            | Op::Shl
+            | Op::Shr => left_type,
+        };
+        Ok((typed_left, typed_right, typ))
+    }
+
+    fn type_exprs(&mut self, tbl: &FunctionTable, exprs: &[Expr]) -> Result<Vec<ir::Expr>> {
+        let mut typed = Vec::with_capacity(exprs.len());
+        for e in exprs {
+            typed.push(self.type_expr(tbl, e)?);
+        }
+        Ok(typed)
+    }
+
+    fn type_expr(&mut self, tbl: &FunctionTable, expr: &Expr) -> Result<ir::Expr> {
+        let (kind, typ) = match expr {
+            Expr::Litt(litt) => (Kind::Litt(litt.clone()), type_litt(litt)),
+            Expr::Declare(var, e) | Expr::DeclareImmutable(var, e) => {
+                let e = self.type_expr(tbl, e)?;
+                let typ = e.typ.clone();
+                self.set_var(var, typ.clone());
+                match expr {
+                    Expr::Declare(_, _) => (Kind::Declare(var.clone(), Box::new(e)), typ),
+                    _ => (Kind::DeclareImmutable(var.clone(), Box::new(e)), typ),
+                }
+            }
+            Expr::DeclarePattern(pattern, e, span) => {
+                let e = self.type_expr(tbl, e)?;
+                self.bind_pattern(pattern, &e.typ, *span)?;
+                let typ = e.typ.clone();
+                (Kind::DeclarePattern(pattern.clone(), Box::new(e), *span), typ)
+            }
+            Expr::Assign(var, e) => {
+                check_assignable(var)?;
+                let current_type = self.var_type(tbl, var)?;
+                let e = self.type_expr(tbl, e)?;
+                let unified = self.unify(&current_type, &e.typ).ok_or(Error::from(format!(
+                    "Trying to assign type {:?} to {}, a variable of type {:?}",
+                    e.typ, var.name, current_type
                 )))?;
-                let current_type = current_type.clone();
-                let assigning = self.type_expr(tbl, expr)?;
-                if assigning != current_type {
-                    return fail(format!(
-                        "Trying to assign type {:?} to {}, a variable of type {:?}",
-                        assigning, name, current_type
-                    ));
+                // Assigning may have told us more about the type of this variable
+                self.set_var(var, unified.clone());
+                (Kind::Assign(var.clone(), Box::new(e)), unified)
+            }
+            Expr::AssignOp(op, var, e, span) => {
+                check_assignable(var)?;
+                let current_type = self.var_type(tbl, var)?;
+                let name_expr = Expr::Name(var.clone());
+                let (_, e, typ) = self.type_bin_op(tbl, *op, &name_expr, e, *span)?;
+                if self.unify(&current_type, &typ).is_none() {
+                    return fail_at(
+                        *span,
+                        format!(
+                            "Trying to assign type {:?} to {}, a variable of type {:?}",
+                            typ, var.name, current_type
+                        ),
+                    );
                 }
-                Ok(assigning)
+                (Kind::AssignOp(*op, var.clone(), Box::new(e), *span), typ)
             }
             Expr::Block(exprs) => {
-                self.scopes.enter(true);
-                let res = self.type_block(tbl, exprs);
-                self.scopes.exit();
-                res
-            }
-            Expr::BinOp(op, left, right) => {
-                let left_type = self.type_expr(tbl, left)?;
-                let right_type = self.type_expr(tbl, right)?;
-                if left_type != right_type {
-                    return fail(format!(
-                        "Trying to apply binary options to mismatched types {:?} and {:?}",
-                        left_type, right_type
-                    ));
-                }
-                let may_expect = match op {
-                    Op::Equal => None,
-                    Op::NotEqual => None,
-                    Op::Leq
-                    | Op::Less
-                    | Op::Geq
-                    | Op::Greater
-                    | Op::Add
-                    | Op::Sub
-                    | Op::Mul
-                    | Op::Div
-                    | Op::Mod => Some(Type::I64),
-                };
-                if let Some(expected) = may_expect {
-                    if left_type != expected {
-                        return fail(format!(
-                            "Binary op {:?} requires type {:?}, but found {:?}",
-                            op, expected, left_type
-                        ));
-                    }
-                }
-                let typ = match op {
-                    Op::Equal | Op::NotEqual | Op::Leq | Op::Less | Op::Geq | Op::Greater => {
-                        Type::Bool
-                    }
-                    Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => Type::I64,
-                };
-                Ok(typ)
+                let (exprs, typ) = self.type_block(tbl, exprs)?;
+                (Kind::Block(exprs), typ)
+            }
+            Expr::BinOp(op, left, right, span) => {
+                let (left, right, typ) = self.type_bin_op(tbl, *op, left, right, *span)?;
+                (Kind::BinOp(*op, Box::new(left), Box::new(right), *span), typ)
             }
             Expr::ConditionalOp(op, left, right) => {
-                let left_type = self.type_expr(tbl, left)?;
-                let right_type = self.type_expr(tbl, right)?;
-                if left_type != Type::Bool {
+                let left = self.type_expr(tbl, left)?;
+                let right = self.type_expr(tbl, right)?;
+                if left.typ != Type::Bool {
                     return fail(format!(
                         "Boolean op {:?} doesn't apply to type {:?}",
-                        op, left_type
+                        op, left.typ
                     ));
                 }
-                if right_type != Type::Bool {
+                if right.typ != Type::Bool {
                     return fail(format!(
                         "Boolean op {:?} doesn't apply to type {:?}",
-                        op, right_type
+                        op, right.typ
                     ));
                 }
-                Ok(Type::Bool)
+                (Kind::ConditionalOp(*op, Box::new(left), Box::new(right)), Type::Bool)
             }
             Expr::IfElse(cond, if_part, else_part) => {
-                let cond_type = self.type_expr(tbl, cond)?;
-                if cond_type != Type::Bool {
+                let cond = self.type_expr(tbl, cond)?;
+                if cond.typ != Type::Bool {
                     return fail(format!(
                         "The condition of an if expression must be a Bool. Found {:?}",
-                        cond_type
+                        cond.typ
                     ));
                 }
-                let if_type = {
-                    self.scopes.enter(true);
-                    let res = self.type_block(tbl, if_part)?;
-                    self.scopes.exit();
-                    res
+                let (if_part, if_type) = self.type_block(tbl, if_part)?;
+                let (else_part, else_type) = self.type_block(tbl, else_part)?;
+                let typ = self.unify(&if_type, &else_type).ok_or(Error::from(format!("The two branches of an if expression must have the same type. Found {:?} and {:?}", if_type, else_type)))?;
+                (Kind::IfElse(Box::new(cond), if_part, else_part), typ)
+            }
+            Expr::Not(e) => {
+                let e = self.type_expr(tbl, e)?;
+                if e.typ != Type::Bool {
+                    return fail(format!(
+                        "The operator ! only applies to Bool. Found {:?}",
+                        e.typ
+                    ));
+                }
+                (Kind::Not(Box::new(e)), Type::Bool)
+            }
+            Expr::Neg(operand, span) => {
+                let e = self.type_expr(tbl, operand)?;
+                if e.typ != Type::I64 && e.typ != Type::BigInt {
+                    return fail_at(
+                        *span,
+                        format!(
+                            "The operator - only applies to I64 or BigInt. Found {:?}",
+                            e.typ
+                        ),
+                    );
+                }
+                if let Some(Err(e)) = self.fold_constant(operand).map(negate) {
+                    return fail_at(*span, e.message());
+                }
+                let typ = e.typ.clone();
+                (Kind::Neg(Box::new(e), *span), typ)
+            }
+            Expr::MakeTuple(exprs) => {
+                let exprs = self.type_exprs(tbl, exprs)?;
+                let types = exprs.iter().map(|e| e.typ.clone()).collect();
+                (Kind::MakeTuple(exprs), Type::Tuple(types))
+            }
+            Expr::MakeSlice(exprs) => {
+                let exprs = self.type_exprs(tbl, exprs)?;
+                let mut elem = Type::Unknown;
+                for e in &exprs {
+                    elem = self.unify(&elem, &e.typ).ok_or(Error::from(format!(
+                        "The elements of a slice must have the same type. Found {:?} and {:?}",
+                        elem, e.typ
+                    )))?;
+                }
+                (Kind::MakeSlice(exprs), self.infer(&Type::Slice(Box::new(elem))))
+            }
+            Expr::MakeMap(entries) => {
+                let mut key = Type::Unknown;
+                let mut value = Type::Unknown;
+                let mut typed = Vec::with_capacity(entries.len());
+                for (k, v) in entries {
+                    let k = self.type_expr(tbl, k)?;
+                    let v = self.type_expr(tbl, v)?;
+                    key = self.unify(&key, &k.typ).ok_or(Error::from(format!(
+                        "The keys of a map must have the same type. Found {:?} and {:?}",
+                        key, k.typ
+                    )))?;
+                    value = self.unify(&value, &v.typ).ok_or(Error::from(format!(
+                        "The values of a map must have the same type. Found {:?} and {:?}",
+                        value, v.typ
+                    )))?;
+                    typed.push((k, v));
+                }
+                if !self.env.is_hashable(&key) {
+                    return fail(format!("{:?} can't be used as the key of a map", key));
+                }
+                let typ = self.infer(&Type::Map(Box::new(key), Box::new(value)));
+                (Kind::MakeMap(typed), typ)
+            }
+            Expr::Index(target, index, span) => {
+                let (target, index, value) = self.type_index(tbl, target, index, *span)?;
+                let typ = match target.typ {
+                    // Keys might be missing from a map
+                    Type::Map(_, _) => Type::Option(Box::new(value)),
+                    _ => value,
                 };
-                let else_type = {
-                    self.scopes.enter(true);
-                    let res = self.type_block(tbl, else_part)?;
-                    self.scopes.exit();
-                    res
+                (Kind::Index(Box::new(target), Box::new(index), *span), typ)
+            }
+            Expr::Subslice(target, from, to, span) => {
+                let (target, sliced) = self.type_slice(tbl, target, *span)?;
+                let from = match from {
+                    Some(from) => Some(Box::new(self.expect_index(tbl, from, *span)?)),
+                    None => None,
+                };
+                let to = match to {
+                    Some(to) => Some(Box::new(self.expect_index(tbl, to, *span)?)),
+                    None => None,
                 };
-                if if_type != else_type {
-                    return fail(format!("The two branches of an if expression must have the same type. Found {:?} and {:?}", if_type, else_type));
+                (Kind::Subslice(Box::new(target), from, to, *span), sliced)
+            }
+            Expr::AssignIndex(target, index, e, span) => {
+                let (target, index, value) = self.type_index(tbl, target, index, *span)?;
+                if target.typ == Type::Str {
+                    return fail_at(*span, "Strings are immutable and can't be assigned into");
+                }
+                let e = self.type_expr(tbl, e)?;
+                let typ = match self.unify(&value, &e.typ) {
+                    Some(typ) => typ,
+                    None => {
+                        return fail_at(
+                            *span,
+                            format!(
+                                "Trying to assign type {:?} into {:?}",
+                                e.typ, target.typ
+                            ),
+                        )
+                    }
+                };
+                // Assigning tells us what the container holds, if it was empty
+                let refined = match &target.typ {
+                    Type::Slice(_) => Some(Type::Slice(Box::new(typ.clone()))),
+                    Type::Map(k, _) => Some(Type::Map(k.clone(), Box::new(typ.clone()))),
+                    _ => None,
+                };
+                if let Some(refined) = refined {
+                    self.refine(&target, refined);
                 }
-                Ok(if_type)
+                let kind = Kind::AssignIndex(Box::new(target), Box::new(index), Box::new(e), *span);
+                (kind, typ)
             }
-            Expr::Not(expr) => {
-                let typ = self.type_expr(tbl, expr)?;
-                if typ != Type::Bool {
+            Expr::Field(e, field, span) => {
+                let e = self.type_expr(tbl, e)?;
+                let typ = match &e.typ {
+                    Type::Tuple(types) => match types.get(*field) {
+                        Some(typ) => typ.clone(),
+                        None => {
+                            return fail_at(
+                                *span,
+                                format!("The tuple {:?} has no field {}", e.typ, field),
+                            )
+                        }
+                    },
+                    // The field of a newtype is the value it wraps, like `id.0`
+                    Type::Named(name) => match (self.env.underlying(name), field) {
+                        (Some(inner), 0) => inner,
+                        _ => {
+                            return fail_at(
+                                *span,
+                                format!("The newtype {} only has the field 0", name),
+                            )
+                        }
+                    },
+                    Type::Unknown => Type::Unknown,
+                    typ => {
+                        return fail_at(*span, format!("Only tuples have fields. Found {:?}", typ))
+                    }
+                };
+                (Kind::Field(Box::new(e), *field, *span), typ)
+            }
+            Expr::Try(e, span) => {
+                let e = self.type_expr(tbl, e)?;
+                // What the function returns early with must be compatible with its return type
+                let (returning, inner) = match e.typ.clone() {
+                    Type::Option(t) => (Type::Option(Box::new(Type::Unknown)), *t),
+                    Type::Result(t, e) => (Type::Result(Box::new(Type::Unknown), e), *t),
+                    typ => {
+                        return fail_at(
+                            *span,
+                            format!("The operator ? only applies to Option or Result. Found {:?}", typ),
+                        )
+                    }
+                };
+                if self.unify(&self.ret.clone(), &returning).is_none() {
+                    return fail_at(
+                        *span,
+                        format!(
+                            "The operator ? returns {:?}, but the function returns {:?}",
+                            returning, self.ret
+                        ),
+                    );
+                }
+                (Kind::Try(Box::new(e), *span), inner)
+            }
+            Expr::Lambda(args, ret, body, locals) => {
+                let arg_names: Vec<TypeName> = args.iter().map(|(_, t)| t.clone()).collect();
+                let func_typ = FunctionType::from_parts(&self.env, &arg_names, ret.as_ref())?;
+                // Like when interpreting, the body only sees the variables captured
+                // when creating the function
+                let mut captured = Vec::with_capacity(locals.captures.len());
+                for var in &locals.captures {
+                    captured.push(self.var_type(tbl, var)?);
+                }
+                let outer_ret = std::mem::replace(&mut self.ret, func_typ.ret.clone());
+                let outer_locals = std::mem::replace(&mut self.locals, func_typ.frame(locals));
+                let outer_captured = std::mem::replace(&mut self.captured, captured);
+                let typed = self.type_block(tbl, body);
+                self.locals = outer_locals;
+                self.captured = outer_captured;
+                self.ret = outer_ret;
+                let (body, actual) = typed?;
+                if self.unify(&func_typ.ret, &actual).is_none() {
                     return fail(format!(
-                        "The operator ! only applies to Bool. Found {:?}",
-                        typ
+                        "In anonymous function, declared return type is {:?}, but found {:?}",
+                        func_typ.ret, actual
                     ));
                 }
-                Ok(Type::Bool)
+                let kind = Kind::Lambda(args.clone(), ret.clone(), body, locals.clone());
+                (kind, func_typ.to_type())
             }
-            Expr::MakeTuple(exprs) => {
-                let mut types = Vec::with_capacity(exprs.len());
-                for e in exprs {
-                    types.push(self.type_expr(tbl, e)?);
+            Expr::Name(var) => {
+                let typ = self.var_type(tbl, var).map_err(|e| e.at(var.span))?;
+                (Kind::Name(var.clone()), typ)
+            }
+            Expr::Call(func, args, span) => {
+                let args = self.type_exprs(tbl, args)?;
+                let arg_types: Vec<Type> = args.iter().map(|a| a.typ.clone()).collect();
+                let mut typ = self.type_call(tbl, func, &arg_types)?;
+                match (func.as_str(), arg_types.as_slice()) {
+                    // Every copy of a new channel has to agree on what it passes
+                    ("chan", _) => typ = self.infer(&typ),
+                    // Inserting into a map is like assigning into it
+                    ("insert", [Type::Map(_, _), key, value]) => {
+                        let map = Type::Map(Box::new(key.clone()), Box::new(value.clone()));
+                        self.refine(&args[0], map);
+                    }
+                    // Appending may write into the backing array of the slice
+                    ("append", [Type::Slice(elem), value]) => {
+                        self.unify(elem, value);
+                    }
+                    ("delete", [Type::Map(k, _), key]) | ("contains", [Type::Map(k, _), key]) => {
+                        self.unify(k, key);
+                    }
+                    ("unwrap_or", [Type::Option(t), default])
+                    | ("unwrap_or", [Type::Result(t, _), default]) => {
+                        self.unify(t, default);
+                    }
+                    ("join", [parts, Type::Str]) => {
+                        self.unify(parts, &Type::Slice(Box::new(Type::Str)));
+                    }
+                    _ => {
+                        let expected = match self.env.underlying(func) {
+                            Some(inner) => Some(vec![inner]),
+                            None => tbl.get(func).map(|f| f.args.clone()),
+                        };
+                        if let Some(expected) = expected {
+                            self.refine_args(&args, &expected);
+                        }
+                    }
                 }
-                Ok(Type::Tuple(types))
+                (Kind::Call(func.clone(), args, *span), typ)
             }
-            Expr::Name(name) => {
-                let typ = self.scopes.get(&name).ok_or(Error::from(format!(
-                    "Trying to use undefined variable {}",
-                    name
-                )))?;
-                Ok(typ.clone())
+            Expr::CallValue(target, args, span) => {
+                let target = self.type_expr(tbl, target)?;
+                let args = self.type_exprs(tbl, args)?;
+                let arg_types: Vec<Type> = args.iter().map(|a| a.typ.clone()).collect();
+                let typ = self.type_call_value(target.typ.clone(), &arg_types)?;
+                if let Type::Function(expected, _) = &target.typ {
+                    self.refine_args(&args, expected);
+                }
+                (Kind::CallValue(Box::new(target), args, *span), typ)
+            }
+            // Whatever the deferred call returns is thrown away
+            Expr::Defer(call, span) => {
+                let call = self.type_expr(tbl, call)?;
+                (Kind::Defer(Box::new(call), *span), unit())
             }
-            Expr::Call(func, args) => {
-                if func == "print" {
-                    return Ok(unit())
+            Expr::Go(call, span) => {
+                let call = self.type_expr(tbl, call)?;
+                (Kind::Go(Box::new(call), *span), unit())
+            }
+            Expr::Send(chan, value, span) => {
+                let (chan, value) = self.type_send(tbl, chan, value, *span)?;
+                (Kind::Send(Box::new(chan), Box::new(value), *span), unit())
+            }
+            // Receiving gives None once the channel is closed
+            Expr::Recv(chan, span) => {
+                let (chan, elem) = self.type_chan(tbl, chan, *span)?;
+                (Kind::Recv(Box::new(chan), *span), Type::Option(Box::new(elem)))
+            }
+            Expr::Select(cases, default, span) => {
+                let mut typ = match (cases.is_empty(), default) {
+                    (true, None) => unit(),
+                    _ => Type::Unknown,
+                };
+                let mut typed = Vec::with_capacity(cases.len());
+                for case in cases {
+                    let op = match &case.op {
+                        SelectOp::Recv(var, chan) => {
+                            let (chan, elem) = self.type_chan(tbl, chan, case.span)?;
+                            if let Some(var) = var {
+                                self.set_var(var, Type::Option(Box::new(elem)));
+                            }
+                            ir::SelectOp::Recv(var.clone(), chan)
+                        }
+                        SelectOp::Send(chan, value) => {
+                            let (chan, value) = self.type_send(tbl, chan, value, case.span)?;
+                            ir::SelectOp::Send(chan, value)
+                        }
+                    };
+                    let (body, case_type) = self.type_block(tbl, &case.body)?;
+                    typ = self.unify_cases(&typ, &case_type)?;
+                    typed.push(ir::SelectCase {
+                        op,
+                        body,
+                        span: case.span,
+                    });
                 }
-                let func_type = tbl.get(func).ok_or(Error::from(format!("Trying to call undefined function {}", func)))?;
-                let mut arg_types = Vec::with_capacity(args.len());
-                for a in args {
-                    arg_types.push(self.type_expr(tbl, a)?);
+                let default = match default {
+                    Some(default) => {
+                        let (default, default_type) = self.type_block(tbl, default)?;
+                        typ = self.unify_cases(&typ, &default_type)?;
+                        Some(default)
+                    }
+                    None => None,
+                };
+                (Kind::Select(typed, default, *span), typ)
+            }
+            Expr::Ref(e, span) => {
+                // The pointer would point to a copy, so changing what it points to
+                // wouldn't change the variable, element or field it came from
+                let what = match &**e {
+                    Expr::Name(var) if var.binding != Binding::Global => {
+                        Some(format!("variable {}", var.name))
+                    }
+                    Expr::Index(..) => Some("an element".to_string()),
+                    Expr::Field(..) => Some("a field".to_string()),
+                    Expr::Deref(..) => Some("what a pointer points to".to_string()),
+                    _ => None,
+                };
+                if let Some(what) = what {
+                    let message = format!(
+                        "Can't take the address of {}, since & puts a copy of it on the heap",
+                        what
+                    );
+                    return fail_at(*span, message);
+                }
+                let e = self.type_expr(tbl, e)?;
+                // Every copy of the pointer has to agree on what it points to, like `&None`
+                let typ = self.infer(&Type::Pointer(Box::new(e.typ.clone())));
+                (Kind::Ref(Box::new(e)), typ)
+            }
+            Expr::Deref(pointer, span) => {
+                let (pointer, elem) = self.type_pointer(tbl, pointer, *span)?;
+                (Kind::Deref(Box::new(pointer), *span), elem)
+            }
+            Expr::AssignDeref(pointer, e, span) => {
+                let (pointer, elem) = self.type_pointer(tbl, pointer, *span)?;
+                let e = self.type_expr(tbl, e)?;
+                let typ = match self.unify(&elem, &e.typ) {
+                    Some(typ) => typ,
+                    None => {
+                        return fail_at(
+                            *span,
+                            format!(
+                                "Trying to assign type {:?} through {:?}",
+                                e.typ, pointer.typ
+                            ),
+                        )
+                    }
+                };
+                self.refine(&pointer, Type::Pointer(Box::new(typ.clone())));
+                (Kind::AssignDeref(Box::new(pointer), Box::new(e), *span), typ)
+            }
+        };
+        Ok(ir::Expr::new(kind, self.resolve(&typ)))
+    }
+
+    // Declare the names in a pattern, given the type of the value it destructures
+    fn bind_pattern(&mut self, pattern: &Pattern, typ: &Type, span: Span) -> Result<()> {
+        match (pattern, typ) {
+            (Pattern::Name(var), typ) => self.set_var(var, typ.clone()),
+            (Pattern::Ignore, _) => {}
+            (Pattern::Tuple(patterns), Type::Tuple(types)) if patterns.len() == types.len() => {
+                for (p, t) in patterns.iter().zip(types.iter()) {
+                    self.bind_pattern(p, t, span)?;
                 }
-                if arg_types != func_type.args {
-                    return fail(format!("Trying to call {} with types {:?}, expected {:?} instead", func, arg_types, func_type.args));
+            }
+            (Pattern::Tuple(patterns), Type::Unknown) => {
+                for p in patterns {
+                    self.bind_pattern(p, &Type::Unknown, span)?;
                 }
-                Ok(func_type.ret.clone())
             }
+            (Pattern::Tuple(patterns), typ) => {
+                return fail_at(
+                    span,
+                    format!(
+                        "Can't destructure {:?} into a tuple of {} elements",
+                        typ,
+                        patterns.len()
+                    ),
+                )
+            }
+        }
+        Ok(())
+    }
+
+    // Type calling a function by name, which may also be a builtin or a newtype
+    fn type_call(&self, tbl: &FunctionTable, func: &str, arg_types: &[Type]) -> Result<Type> {
+        // Calling a newtype wraps a value in it, like `UserId(5)`
+        if let Some(inner) = self.env.underlying(func) {
+            check_args(func, &[inner], arg_types)?;
+            return Ok(Type::Named(func.into()));
+        }
+        if func == "print" {
+            return Ok(unit())
+        }
+        if let Some(res) = builtins::type_call(func, arg_types) {
+            return res;
+        }
+        let func_type = tbl.get(func).ok_or(Error::from(format!("Trying to call undefined function {}", func)))?;
+        check_args(func, &func_type.args, arg_types)?;
+        Ok(func_type.ret.clone())
+    }
+
+    // Type calling a value, which should be a function, returning what it produces
+    fn type_call_value(&mut self, typ: Type, arg_types: &[Type]) -> Result<Type> {
+        match typ {
+            Type::Function(args, ret) => {
+                check_args("a function", &args, arg_types)?;
+                Ok(*ret)
+            }
+            Type::Unknown => Ok(Type::Unknown),
+            typ => fail(format!("Only functions can be called. Found {:?}", typ)),
         }
     }
 
-    fn type_block(&mut self, tbl: &FunctionTable, block: &[Expr]) -> Result<Type> {
-        for i in 1..block.len(){
-            self.type_expr(tbl, &block[i - 1])?;
+    // Type an expression that should be a slice or a string, along with the
+    // type of the result of slicing it
+    fn type_slice(
+        &mut self,
+        tbl: &FunctionTable,
+        expr: &Expr,
+        span: Span,
+    ) -> Result<(ir::Expr, Type)> {
+        let e = self.type_expr(tbl, expr)?;
+        let sliced = match &e.typ {
+            typ @ Type::Slice(_) | typ @ Type::Str => typ.clone(),
+            Type::Unknown => Type::Slice(Box::new(Type::Unknown)),
+            typ => {
+                return fail_at(
+                    span,
+                    format!("Only slices and strings can be sliced. Found {:?}", typ),
+                )
+            }
+        };
+        Ok((e, sliced))
+    }
+
+    // Type indexing into a slice, a string or a map, along with the type of
+    // the values the container holds
+    fn type_index(
+        &mut self,
+        tbl: &FunctionTable,
+        target: &Expr,
+        index: &Expr,
+        span: Span,
+    ) -> Result<(ir::Expr, ir::Expr, Type)> {
+        let target = self.type_expr(tbl, target)?;
+        let (key, value) = match &target.typ {
+            Type::Slice(elem) => (Type::I64, (**elem).clone()),
+            // Indexing a string gives the byte at that offset
+            Type::Str => (Type::I64, Type::U8),
+            Type::Map(k, v) => ((**k).clone(), (**v).clone()),
+            Type::Unknown => (Type::Unknown, Type::Unknown),
+            typ => {
+                return fail_at(
+                    span,
+                    format!("Only slices, strings and maps can be indexed. Found {:?}", typ),
+                )
+            }
+        };
+        let index = self.type_expr(tbl, index)?;
+        let key = match self.unify(&index.typ, &key) {
+            Some(key) => key,
+            None => {
+                return fail_at(
+                    span,
+                    format!("Expected an index of type {:?}. Found {:?}", key, index.typ),
+                )
+            }
+        };
+        // Looking a key up tells us the type of the keys of the map
+        if let Type::Map(_, v) = &target.typ {
+            let refined = Type::Map(Box::new(key), v.clone());
+            self.refine(&target, refined);
         }
-        block.last().map_or(Ok(unit()), |x| self.type_expr(tbl, x))
+        Ok((target, index, value))
     }
 
-    fn check_function(&mut self, tbl: &FunctionTable, f: &Function) -> Result<()> {
-        let func_typ = FunctionType::from_func(f)?;
-        self.scopes.enter(false);
-        for ((name, _), typ) in f.args.iter().zip(func_typ.args.iter()) {
-            self.scopes.create(name, typ.clone());
+    // Make sure that an expression used as an index is an integer
+    fn expect_index(&mut self, tbl: &FunctionTable, expr: &Expr, span: Span) -> Result<ir::Expr> {
+        let e = self.type_expr(tbl, expr)?;
+        if self.unify(&e.typ, &Type::I64).is_none() {
+            return fail_at(span, format!("Indices must have type I64. Found {:?}", e.typ));
         }
-        let actual = self.type_block(tbl, &f.body)?;
-        self.scopes.exit();
-        if func_typ.ret != actual {
-            fail(format!(
+        Ok(e)
+    }
+
+    // Type an expression that should be a channel, along with the type of its values
+    fn type_chan(
+        &mut self,
+        tbl: &FunctionTable,
+        expr: &Expr,
+        span: Span,
+    ) -> Result<(ir::Expr, Type)> {
+        let e = self.type_expr(tbl, expr)?;
+        let elem = match &e.typ {
+            Type::Chan(elem) => (**elem).clone(),
+            Type::Unknown => Type::Unknown,
+            typ => {
+                return fail_at(
+                    span,
+                    format!("Only channels can send and receive. Found {:?}", typ),
+                )
+            }
+        };
+        Ok((e, elem))
+    }
+
+    fn type_pointer(
+        &mut self,
+        tbl: &FunctionTable,
+        expr: &Expr,
+        span: Span,
+    ) -> Result<(ir::Expr, Type)> {
+        let e = self.type_expr(tbl, expr)?;
+        let elem = match &e.typ {
+            Type::Pointer(elem) => (**elem).clone(),
+            // Nothing would check what the value really is when the program runs
+            Type::Unknown | Type::Var(_) => {
+                return fail_at(span, "Can't dereference a value whose type isn't known")
+            }
+            typ => {
+                return fail_at(
+                    span,
+                    format!("Only pointers can be dereferenced. Found {:?}", typ),
+                )
+            }
+        };
+        Ok((e, elem))
+    }
+
+    fn type_send(
+        &mut self,
+        tbl: &FunctionTable,
+        chan: &Expr,
+        value: &Expr,
+        span: Span,
+    ) -> Result<(ir::Expr, ir::Expr)> {
+        let (chan, elem) = self.type_chan(tbl, chan, span)?;
+        let value = self.type_expr(tbl, value)?;
+        let elem = match self.unify(&elem, &value.typ) {
+            Some(elem) => elem,
+            None => {
+                return fail_at(
+                    span,
+                    format!("Trying to send {:?} on a channel of {:?}", value.typ, elem),
+                )
+            }
+        };
+        // Sending tells us what the channel passes, if it was made with `chan(n)`
+        self.refine(&chan, Type::Chan(Box::new(elem)));
+        Ok((chan, value))
+    }
+
+    // Type the expressions of a block, along with the type of the last one
+    fn type_block(&mut self, tbl: &FunctionTable, block: &[Expr]) -> Result<(Vec<ir::Expr>, Type)> {
+        let typed = self.type_exprs(tbl, block)?;
+        let typ = typed.last().map_or(unit(), |e| e.typ.clone());
+        Ok((typed, typ))
+    }
+
+    // Write what we found out about the types being inferred into an expression
+    // and the ones inside of it, once every function has been checked
+    fn settle(&self, e: ir::Expr) -> ir::Expr {
+        let typ = forget_vars(&self.resolve(&e.typ));
+        ir::Expr::new(ir::map_children(e.kind, |e| self.settle(e)), typ)
+    }
+
+    fn check_function(&mut self, tbl: &FunctionTable, f: &Function) -> Result<ir::Function> {
+        let func_typ = FunctionType::from_func(&self.env, f)?;
+        self.ret = func_typ.ret.clone();
+        self.locals = func_typ.frame(&f.locals);
+        let (body, actual) = self.type_block(tbl, &f.body)?;
+        if self.unify(&func_typ.ret, &actual).is_none() {
+            return fail(format!(
                 "In function {}, declared return type is {:?}, but found {:?}",
                 f.name, func_typ.ret, actual
-            ))
-        } else {
-            Ok(())
+            ));
         }
+        Ok(ir::Function {
+            name: f.name.clone(),
+            args: f.args.clone(),
+            ret: f.ret.clone(),
+            body,
+            locals: f.locals.clone(),
+        })
     }
 }
 
 /// Make sure that an AST (i.e. a program) type checks.
 ///
-/// Nothing is returned in the case of a success, but we can then assume
-/// that no incorrect operations happen in the ast
-pub fn check(ast: &AST) -> Result<()> {
-    let tbl = FunctionTable::from_ast(ast)?;
-    let mut typer = Typer::new();
+/// This produces the typed IR of the program, where every expression carries
+/// its type, so later passes can assume that no incorrect operations happen.
+pub fn check(ast: &AST) -> Result<ir::Program> {
+    let env = TypeEnv::from_ast(ast)?;
+    let tbl = FunctionTable::from_ast(ast, &env)?;
+    let consts = eval_consts(&env, ast)?;
+    let mut typer = Typer::new(env);
+    typer.consts = consts;
+    let mut functions = Vec::with_capacity(ast.functions.len());
     for f in &ast.functions {
-        typer.check_function(&tbl, f)?;
+        functions.push(typer.check_function(&tbl, f)?);
     }
-    Ok(())
+    // Expressions typed before their values were used don't know all of their types yet
+    for f in functions.iter_mut() {
+        f.body = ir::map_exprs(std::mem::take(&mut f.body), |e| typer.settle(e));
+    }
+    Ok(ir::Program {
+        types: ast.types.clone(),
+        consts: typer.consts,
+        functions,
+    })
 }
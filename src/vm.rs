@@ -0,0 +1,887 @@
+use crate::ast::*;
+use crate::bytecode::{CallTarget, Capture, Chunk, Instr, Program};
+use crate::chan::Chan;
+use crate::closure::Closure;
+use crate::compiler;
+use crate::heap::Heap;
+use crate::interpreter::*;
+use crate::ir;
+use crate::map::Map;
+use crate::scheduler::{Blocked, Scheduler, Waiting};
+use crate::slice::Slice;
+use std::rc::Rc;
+
+// Where the result of a function goes once it returns
+#[derive(Clone, Copy, Debug)]
+enum Return {
+    // Onto the stack of the function calling it
+    Value,
+    // To the function whose deferred calls are running
+    Deferred,
+    // Nowhere, since the task is done
+    Task,
+}
+
+// A function running in a task
+struct CallFrame {
+    chunk: usize,
+    // The next instruction to run
+    ip: usize,
+    // Where the local variables of this function start on the stack
+    base: usize,
+    // The variables captured by the closure being run, which it can change
+    captured: Vec<Litteral>,
+    deferred: Vec<PendingCall>,
+    // Where this function was called, which is unknown for main
+    call_site: Option<Span>,
+    returns: Return,
+    // Set once the function is done, while its deferred calls run
+    exiting: Option<Box<Exit>>,
+}
+
+// A function that's done, but still running its deferred calls
+struct Exit {
+    res: InterpreterResult<Litteral>,
+    // The error of the functions around this one, while its deferred calls run
+    outer: Option<Panic>,
+    // The span of the deferred call running, and whether it could recover
+    running: Option<(Span, bool)>,
+}
+
+// Each task has its own stack of values, shared by all of its functions
+struct Task {
+    id: usize,
+    stack: Vec<Litteral>,
+    frames: Vec<CallFrame>,
+    // The error a function is failing with, while its deferred calls run
+    panicking: Option<Panic>,
+    // The call this task starts with, until it runs for the first time
+    start: Option<PendingCall>,
+    // The channels this task is waiting to receive from, while blocked
+    waiting: Vec<Chan>,
+}
+
+// What happened once a task stops running
+enum Status {
+    // The task can continue later, once the scheduler picks it again
+    Paused,
+    Done(InterpreterResult<Litteral>),
+}
+
+/// Runs a compiled program.
+///
+/// Calls don't recurse in Rust: every task keeps its own stack of values
+/// and of frames, and stops at the same points as tasks in the interpreter,
+/// so a seed schedules a program the same way in either of them.
+struct Vm<'p, C> {
+    program: &'p Program,
+    ctx: C,
+    scheduler: Scheduler<PendingCall>,
+    meter: &'p mut Meter,
+    heap: &'p mut Heap,
+}
+
+// Take the values on top of the stack
+fn pop_n(stack: &mut Vec<Litteral>, n: usize) -> Vec<Litteral> {
+    stack.split_off(stack.len() - n)
+}
+
+fn pop(stack: &mut Vec<Litteral>) -> Litteral {
+    stack.pop().expect("Popping from an empty stack")
+}
+
+// Push a value the program just created, accounting for what it allocated
+fn push_new(
+    meter: &mut Meter,
+    stack: &mut Vec<Litteral>,
+    value: Litteral,
+) -> InterpreterResult<()> {
+    meter.allocate(&value)?;
+    stack.push(value);
+    Ok(())
+}
+
+// Where the instruction a function is running fails, if it does
+fn span_at(program: &Program, frame: &CallFrame) -> Option<Span> {
+    program.chunks[frame.chunk].spans[frame.ip - 1]
+}
+
+fn as_chan(value: &Litteral) -> InterpreterResult<Chan> {
+    match value {
+        Litteral::Chan(chan) => Ok(chan.clone()),
+        wrong_type => Err(InterpreterError::from(format!(
+            "Only channels can send and receive, but got {:?}",
+            wrong_type
+        ))),
+    }
+}
+
+impl<'p, C: Context> Vm<'p, C> {
+    fn chunk(&self, task: &Task) -> &'p Chunk {
+        let program = self.program;
+        &program.chunks[task.frames.last().expect("No function is running").chunk]
+    }
+
+    // The functions running in a task, outermost first
+    fn trace(&self, task: &Task) -> Vec<Frame> {
+        let frame = |f: &CallFrame| Frame {
+            function: self.program.chunks[f.chunk].name.clone(),
+            call_site: f.call_site,
+        };
+        task.frames.iter().map(frame).collect()
+    }
+
+    // Start running a chunk, with its arguments on top of the stack
+    fn enter(
+        &mut self,
+        task: &mut Task,
+        chunk: usize,
+        captured: Vec<Litteral>,
+        argc: usize,
+        returns: Return,
+        call_site: Option<Span>,
+    ) -> Option<InterpreterResult<Litteral>> {
+        let code = &self.program.chunks[chunk];
+        if argc != code.args.len() {
+            return Some(Err(InterpreterError::from(format!(
+                "Incorrect number of arguments to function {}\n.Expected {}, but got {}",
+                code.name,
+                code.args.len(),
+                argc
+            ))));
+        }
+        if let Err(e) = self.meter.check_depth(task.frames.len()) {
+            return Some(Err(e));
+        }
+        let base = task.stack.len() - argc;
+        task.stack.resize(base + code.slots, unit());
+        task.frames.push(CallFrame {
+            chunk,
+            ip: 0,
+            base,
+            captured,
+            deferred: Vec::new(),
+            call_site,
+            returns,
+            exiting: None,
+        });
+        None
+    }
+
+    // Call a function in tail position, with its arguments on top of the stack.
+    //
+    // The call replaces the function making it, unless that function has
+    // deferred calls to run once the call returns, like in the interpreter.
+    fn tail_call(
+        &mut self,
+        task: &mut Task,
+        chunk: usize,
+        captured: Vec<Litteral>,
+        argc: usize,
+        call_site: Option<Span>,
+    ) -> Option<InterpreterResult<Litteral>> {
+        let frame = task.frames.last().expect("No function is running");
+        if !frame.deferred.is_empty() || argc != self.program.chunks[chunk].args.len() {
+            return self.enter(task, chunk, captured, argc, Return::Value, call_site);
+        }
+        let frame = task.frames.pop().unwrap();
+        let start = task.stack.len() - argc;
+        task.stack.drain(frame.base..start);
+        self.enter(task, chunk, captured, argc, frame.returns, call_site)
+    }
+
+    // Call a function with its arguments on top of the stack.
+    //
+    // Functions of the program start running in a new frame, and give their
+    // result once they return, but builtins give their result right away.
+    fn call(
+        &mut self,
+        task: &mut Task,
+        callee: &Callee,
+        argc: usize,
+        returns: Return,
+        call_site: Option<Span>,
+    ) -> Option<InterpreterResult<Litteral>> {
+        match callee {
+            Callee::Closure(closure) => match closure.code {
+                Some(chunk) => {
+                    let captured = closure.captured.as_ref().clone();
+                    self.enter(task, chunk, captured, argc, returns, call_site)
+                }
+                None => {
+                    task.stack.truncate(task.stack.len() - argc);
+                    let message = format!("The VM can't call {}, which wasn't compiled", closure);
+                    Some(Err(InterpreterError::from(message)))
+                }
+            },
+            Callee::Named(name) => self.call_named(task, name, argc, returns, call_site),
+        }
+    }
+
+    // Call a function by name, like the interpreter does
+    fn call_named(
+        &mut self,
+        task: &mut Task,
+        name: &str,
+        argc: usize,
+        returns: Return,
+        call_site: Option<Span>,
+    ) -> Option<InterpreterResult<Litteral>> {
+        let start = task.stack.len() - argc;
+        let res = match (name, &task.stack[start..]) {
+            ("print", args) => match args.first() {
+                Some(arg) => {
+                    self.ctx.print(&format!("{}\n", arg));
+                    Ok(unit())
+                }
+                None => Err(InterpreterError::from("Not enough arguments to print")),
+            },
+            ("chan", [Litteral::I64(cap)]) => self.scheduler.make_chan(*cap).map(Litteral::Chan),
+            ("close", [Litteral::Chan(chan)]) => self.scheduler.close(chan).map(|_| unit()),
+            ("gc", []) => Ok(Litteral::I64(self.heap.collect() as i64)),
+            ("recover", []) => {
+                let recovered = match task.panicking.take() {
+                    // Running out of resources stops the program, whatever it does
+                    Some(panic)
+                        if panic.depth == task.frames.len() && panic.error.exhausted().is_none() =>
+                    {
+                        Some(panic.error)
+                    }
+                    panicking => {
+                        task.panicking = panicking;
+                        None
+                    }
+                };
+                let message = recovered.map(|e| Box::new(Litteral::Str(e.message().into())));
+                Ok(Litteral::Option(message))
+            }
+            (name, args) => match call_builtin(name, args, &self.program.newtypes, self.meter) {
+                // Builtins account for what their results allocated themselves
+                Some(res) => {
+                    task.stack.truncate(start);
+                    return Some(res);
+                }
+                None => match self.program.functions.get(name) {
+                    Some(&chunk) => {
+                        return self.enter(task, chunk, Vec::new(), argc, returns, call_site)
+                    }
+                    None => Err(format!("Trying to call undefined function {}", name).into()),
+                },
+            },
+        };
+        task.stack.truncate(start);
+        Some(res.and_then(|value| self.meter.allocate(&value).map(|_| value)))
+    }
+
+    // Fail at the instruction that was running in the innermost function
+    fn throw(&mut self, task: &mut Task, error: InterpreterError) -> Option<Status> {
+        let frame = task.frames.last().expect("No function is running");
+        let error = match span_at(self.program, frame) {
+            Some(span) => error.at(span),
+            None => error,
+        };
+        self.leave(task, Err(error))
+    }
+
+    // Leave the innermost function, once its body is done.
+    //
+    // Its deferred calls run first, most recent first, like in the interpreter.
+    // Those calling functions of the program run in frames of their own, and
+    // leaving continues once they return. This returns a status if the task is
+    // done, and None if it can keep running.
+    fn leave(&mut self, task: &mut Task, res: InterpreterResult<Litteral>) -> Option<Status> {
+        // Most functions return a value without deferring any calls, which
+        // goes straight to the function calling them
+        let frame = task.frames.last().expect("No function is running");
+        let returns_value = matches!(frame.returns, Return::Value) && frame.deferred.is_empty();
+        let res = match res {
+            Ok(value) if returns_value => {
+                task.stack.truncate(frame.base);
+                task.frames.pop();
+                task.stack.push(value);
+                return None;
+            }
+            res => res,
+        };
+        let mut leaving = Some(res);
+        loop {
+            let depth = task.frames.len();
+            let frame = task.frames.last_mut().expect("No function is running");
+            if let Some(res) = leaving.take() {
+                task.stack.truncate(frame.base);
+                let outer = task.panicking.take();
+                frame.exiting = Some(Box::new(Exit {
+                    res,
+                    outer,
+                    running: None,
+                }));
+            }
+            let exit = frame.exiting.as_mut().expect("Only exiting functions run deferred calls");
+            // Programs stop right away once they run out of resources
+            if matches!(&exit.res, Err(e) if e.exhausted().is_some()) {
+                frame.deferred.clear();
+            }
+            if let Some(call) = frame.deferred.pop() {
+                let panicked = match std::mem::replace(&mut exit.res, Ok(unit())) {
+                    Err(error) => {
+                        task.panicking = Some(Panic {
+                            depth: depth + 1,
+                            error,
+                        });
+                        true
+                    }
+                    res => {
+                        exit.res = res;
+                        false
+                    }
+                };
+                exit.running = Some((call.span, panicked));
+                let argc = call.args.len();
+                task.stack.extend(call.args);
+                match self.call(task, &call.callee, argc, Return::Deferred, Some(call.span)) {
+                    Some(called) => self.deferred_returned(task, called),
+                    None => return None,
+                }
+                continue;
+            }
+            let trace = match &exit.res {
+                Err(e) if e.trace().is_empty() => self.trace(task),
+                _ => Vec::new(),
+            };
+            let frame = task.frames.pop().unwrap();
+            let exit = frame.exiting.unwrap();
+            task.panicking = exit.outer;
+            let res = exit.res.map_err(|e| e.with_trace(trace));
+            match frame.returns {
+                Return::Task => return Some(Status::Done(res)),
+                Return::Deferred => self.deferred_returned(task, res),
+                Return::Value => match res {
+                    Ok(value) => {
+                        task.stack.push(value);
+                        return None;
+                    }
+                    // The call itself is where errors without a span happened
+                    Err(error) => {
+                        let caller = task.frames.last().unwrap();
+                        let error = match span_at(self.program, caller) {
+                            Some(span) => error.at(span),
+                            None => error,
+                        };
+                        leaving = Some(Err(error));
+                    }
+                },
+            }
+        }
+    }
+
+    // Continue leaving a function once one of its deferred calls is done
+    fn deferred_returned(&mut self, task: &mut Task, called: InterpreterResult<Litteral>) {
+        let frame = task.frames.last_mut().expect("No function is running");
+        let code = &self.program.chunks[frame.chunk];
+        let exit = frame.exiting.as_mut().expect("Only exiting functions run deferred calls");
+        let (span, panicked) = exit.running.take().expect("No deferred call is running");
+        match task.panicking.take() {
+            Some(panic) => exit.res = Err(panic.error),
+            None if panicked => exit.res = recovered(&self.program.types, &code.ret, &code.name),
+            None => {}
+        }
+        if let Err(e) = called {
+            exit.res = Err(e.at(span));
+        }
+    }
+
+    // Wait until one of the channels this task is blocked on changes
+    fn block(
+        &mut self,
+        task: &mut Task,
+        doing: &'static str,
+        channels: Vec<(usize, Waiting)>,
+        span: Span,
+    ) {
+        let blocked = Blocked {
+            doing,
+            channels,
+            span,
+        };
+        self.scheduler.block(task.id, blocked);
+        // The instruction runs again once the task is woken up
+        task.frames.last_mut().unwrap().ip -= 1;
+    }
+
+    // Evaluate the function and arguments of a call being deferred or started
+    fn pending_call(
+        &self,
+        task: &mut Task,
+        target: CallTarget,
+        argc: usize,
+    ) -> InterpreterResult<PendingCall> {
+        let args = pop_n(&mut task.stack, argc);
+        let callee = match target {
+            CallTarget::Value => match pop(&mut task.stack) {
+                Litteral::Function(closure) => Callee::Closure(closure),
+                wrong_type => {
+                    let message = format!("Only functions can be called, but got {:?}", wrong_type);
+                    return Err(InterpreterError::from(message));
+                }
+            },
+            CallTarget::Named(i) => Callee::Named(self.chunk(task).names[i].clone()),
+        };
+        let span = span_at(self.program, task.frames.last().unwrap());
+        Ok(PendingCall {
+            callee,
+            args,
+            span: span.unwrap_or_default(),
+        })
+    }
+
+    // Run a task until it's done, or it has to let other tasks run
+    fn run(&mut self, task: &mut Task) -> Status {
+        // Receivers stop waiting once they run again, and try receiving again
+        for chan in task.waiting.drain(..) {
+            chan.set_waiting(false);
+        }
+        if let Some(call) = task.start.take() {
+            // Only main has no call site, since tasks started with go start from a call
+            let call_site = match task.id {
+                0 => None,
+                _ => Some(call.span),
+            };
+            let argc = call.args.len();
+            task.stack.extend(call.args);
+            if let Some(res) = self.call(task, &call.callee, argc, Return::Task, call_site) {
+                return Status::Done(res);
+            }
+        }
+        loop {
+            let program = self.program;
+            let frame = task.frames.last_mut().expect("No function is running");
+            let chunk = &program.chunks[frame.chunk];
+            let instr = &chunk.code[frame.ip];
+            let steps = chunk.steps[frame.ip];
+            frame.ip += 1;
+            // Starting to evaluate expressions takes steps, using up some fuel
+            if steps > 0 {
+                if let Err(e) = self.meter.take_steps(u64::from(steps)) {
+                    match self.throw(task, e) {
+                        Some(status) => return status,
+                        None => continue,
+                    }
+                }
+            }
+            let base = frame.base;
+            let stack = &mut task.stack;
+            let res: InterpreterResult<()> = match instr {
+                Instr::Const(i) => {
+                    stack.push(chunk.consts[*i].clone());
+                    Ok(())
+                }
+                Instr::Unit => {
+                    stack.push(unit());
+                    Ok(())
+                }
+                Instr::Load(slot) => {
+                    let value = stack[base + slot].clone();
+                    stack.push(value);
+                    Ok(())
+                }
+                Instr::Store(slot) => {
+                    stack[base + slot] = pop(stack);
+                    Ok(())
+                }
+                Instr::LoadCaptured(i) => {
+                    stack.push(frame.captured[*i].clone());
+                    Ok(())
+                }
+                Instr::StoreCaptured(i) => {
+                    frame.captured[*i] = pop(stack);
+                    Ok(())
+                }
+                Instr::Dup => {
+                    let value = stack.last().expect("Duplicating from an empty stack").clone();
+                    stack.push(value);
+                    Ok(())
+                }
+                Instr::Pop => {
+                    pop(stack);
+                    Ok(())
+                }
+                Instr::MakeClosure(i) => {
+                    let code = &program.chunks[*i];
+                    let captured = code.captures.iter().map(|capture| match capture {
+                        Capture::Local(slot) => stack[base + slot].clone(),
+                        Capture::Captured(i) => frame.captured[*i].clone(),
+                    });
+                    let closure = Closure {
+                        name: None,
+                        args: code.args.clone(),
+                        ret: code.ret.clone(),
+                        body: Rc::clone(&code.body),
+                        captured: Rc::new(captured.collect()),
+                        slots: code.slots,
+                        code: Some(*i),
+                    };
+                    push_new(self.meter, stack, Litteral::Function(Rc::new(closure)))
+                }
+                Instr::BinOp(op) => {
+                    let right = pop(stack);
+                    let left = pop(stack);
+                    self.meter
+                        .reserve(op_allocation(*op, &left, &right))
+                        .and_then(|_| apply_op(*op, left, right))
+                        .and_then(|l| push_new(self.meter, stack, l))
+                }
+                Instr::IntOp(op) => {
+                    let right = pop(stack);
+                    let left = pop(stack);
+                    match (left, right) {
+                        (Litteral::I64(l), Litteral::I64(r)) => {
+                            int_op(*op, l, r).map(|l| stack.push(l))
+                        }
+                        (l, r) => unreachable!("Op {:?} on I64 applied to {:?} and {:?}", op, l, r),
+                    }
+                }
+                Instr::Neg => {
+                    let operand = pop(stack);
+                    negate(operand).map(|l| stack.push(l))
+                }
+                Instr::Not => {
+                    let operand = pop(stack);
+                    stack.push(logical_not(operand));
+                    Ok(())
+                }
+                Instr::Ref => {
+                    let pointer = self.heap.alloc(pop(stack));
+                    push_new(self.meter, stack, Litteral::Pointer(pointer))
+                }
+                Instr::Deref => {
+                    let pointer = pop(stack);
+                    stack.push(deref(pointer));
+                    Ok(())
+                }
+                Instr::AssignDeref => {
+                    let value = pop(stack);
+                    let pointer = pop(stack);
+                    stack.push(assign_deref(pointer, value));
+                    Ok(())
+                }
+                Instr::ShortCircuit(op, target) => {
+                    let left = pop(stack);
+                    if let Some(res) = short_circuit(*op, left) {
+                        stack.push(res);
+                        frame.ip = *target;
+                    }
+                    Ok(())
+                }
+                Instr::JumpIfFalse(target) => {
+                    let cond = pop(stack);
+                    if !condition(cond) {
+                        frame.ip = *target;
+                    }
+                    Ok(())
+                }
+                Instr::Jump(target) => {
+                    frame.ip = *target;
+                    Ok(())
+                }
+                Instr::MakeTuple(n) => {
+                    let litterals = pop_n(stack, *n);
+                    push_new(self.meter, stack, Litteral::Tuple(litterals))
+                }
+                Instr::MakeSlice(n) => {
+                    let litterals = pop_n(stack, *n);
+                    push_new(self.meter, stack, Litteral::Slice(Slice::new(litterals)))
+                }
+                Instr::MakeMap(n) => {
+                    let map = Map::new();
+                    let mut entries = pop_n(stack, 2 * n).into_iter();
+                    while let (Some(k), Some(v)) = (entries.next(), entries.next()) {
+                        map.insert(k, v);
+                    }
+                    push_new(self.meter, stack, Litteral::Map(map))
+                }
+                Instr::Index => {
+                    let i = pop(stack);
+                    let target = pop(stack);
+                    index(target, i).map(|l| stack.push(l))
+                }
+                Instr::Subslice(has_from, has_to) => {
+                    let to = if *has_to { Some(pop(stack)) } else { None };
+                    let from = if *has_from { Some(pop(stack)) } else { None };
+                    let target = pop(stack);
+                    subslice(target, from, to).map(|l| stack.push(l))
+                }
+                Instr::AssignIndex => {
+                    let value = pop(stack);
+                    let i = pop(stack);
+                    let target = pop(stack);
+                    assign_index(target, i, value).map(|l| stack.push(l))
+                }
+                Instr::Field(i) => {
+                    let value = pop(stack);
+                    field(value, *i).map(|l| stack.push(l))
+                }
+                Instr::Unpack(n) => {
+                    let value = pop(stack);
+                    unpack(value, *n).map(|litterals| stack.extend(litterals.into_iter().rev()))
+                }
+                Instr::Try => match try_unwrap(pop(stack)) {
+                    Unwrapped::Value(value) => {
+                        stack.push(value);
+                        Ok(())
+                    }
+                    Unwrapped::Return(early) => match self.leave(task, Ok(early)) {
+                        Some(status) => return status,
+                        None => continue,
+                    },
+                },
+                Instr::Call(chunk, argc) => {
+                    let call_site = span_at(program, frame);
+                    match self.enter(task, *chunk, Vec::new(), *argc, Return::Value, call_site) {
+                        Some(res) => res.map(|l| task.stack.push(l)),
+                        None => Ok(()),
+                    }
+                }
+                Instr::TailCall(chunk, argc) => {
+                    let call_site = span_at(program, frame);
+                    match self.tail_call(task, *chunk, Vec::new(), *argc, call_site) {
+                        Some(res) => res.map(|l| task.stack.push(l)),
+                        None => Ok(()),
+                    }
+                }
+                Instr::CallValue(argc) | Instr::TailCallValue(argc) => {
+                    let call_site = span_at(program, frame);
+                    let tail = matches!(instr, Instr::TailCallValue(_));
+                    match stack.remove(stack.len() - argc - 1) {
+                        Litteral::Function(closure) => {
+                            let called = match closure.code {
+                                Some(chunk) if tail => {
+                                    let captured = closure.captured.as_ref().clone();
+                                    self.tail_call(task, chunk, captured, *argc, call_site)
+                                }
+                                _ => {
+                                    let callee = Callee::Closure(closure);
+                                    self.call(task, &callee, *argc, Return::Value, call_site)
+                                }
+                            };
+                            match called {
+                                Some(res) => res.map(|l| task.stack.push(l)),
+                                None => Ok(()),
+                            }
+                        }
+                        wrong_type => Err(InterpreterError::from(format!(
+                            "Only functions can be called, but got {:?}",
+                            wrong_type
+                        ))),
+                    }
+                }
+                Instr::CallNamed(name, argc) => {
+                    let call_site = span_at(program, frame);
+                    let name = &chunk.names[*name];
+                    match self.call_named(task, name, *argc, Return::Value, call_site) {
+                        Some(res) => res.map(|l| task.stack.push(l)),
+                        None => Ok(()),
+                    }
+                }
+                Instr::Defer(target, argc) => {
+                    self.pending_call(task, *target, *argc).map(|call| {
+                        task.frames.last_mut().unwrap().deferred.push(call);
+                        task.stack.push(unit());
+                    })
+                }
+                Instr::Go(target, argc) => match self.pending_call(task, *target, *argc) {
+                    Ok(call) => {
+                        self.scheduler.start(call);
+                        task.stack.push(unit());
+                        return Status::Paused;
+                    }
+                    Err(e) => Err(e),
+                },
+                Instr::Send => match as_chan(&stack[stack.len() - 2]) {
+                    Ok(chan) if chan.is_closed() => Err("Sending on a closed channel".into()),
+                    Ok(chan) if chan.can_send() => {
+                        let value = pop(stack);
+                        pop(stack);
+                        chan.send(value);
+                        self.scheduler.wake(&chan, Waiting::Recv);
+                        task.stack.push(unit());
+                        return Status::Paused;
+                    }
+                    Ok(chan) => {
+                        let span = span_at(program, frame).unwrap_or_default();
+                        let channels = vec![(chan.id(), Waiting::Send)];
+                        self.block(task, "sending on a channel", channels, span);
+                        return Status::Paused;
+                    }
+                    Err(e) => Err(e),
+                },
+                Instr::Recv => match as_chan(&stack[stack.len() - 1]) {
+                    Ok(chan) => {
+                        if let Some(value) = chan.recv() {
+                            pop(stack);
+                            stack.push(Litteral::Option(Some(Box::new(value))));
+                            self.scheduler.wake(&chan, Waiting::Send);
+                            return Status::Paused;
+                        }
+                        if chan.is_closed() {
+                            pop(stack);
+                            stack.push(Litteral::Option(None));
+                            continue;
+                        }
+                        // Waiting receivers make room on the channel, so senders can try again
+                        let span = span_at(program, frame).unwrap_or_default();
+                        chan.set_waiting(true);
+                        self.scheduler.wake(&chan, Waiting::Send);
+                        task.waiting.push(chan.clone());
+                        let channels = vec![(chan.id(), Waiting::Recv)];
+                        self.block(task, "receiving from a channel", channels, span);
+                        return Status::Paused;
+                    }
+                    Err(e) => Err(e),
+                },
+                Instr::Select(i) => match self.select(task, *i) {
+                    Ok(Some(status)) => return status,
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                },
+                Instr::Return => {
+                    let value = pop(stack);
+                    match self.leave(task, Ok(value)) {
+                        Some(status) => return status,
+                        None => continue,
+                    }
+                }
+                Instr::Fail(message) => Err(chunk.names[*message].clone().into()),
+            };
+            if let Err(e) = res {
+                if let Some(status) = self.throw(task, e) {
+                    return status;
+                }
+            }
+        }
+    }
+
+    // Pick one of the cases of a select that can happen right away, like the
+    // interpreter, running the default block or waiting if none can.
+    //
+    // This gives the status of the task if it has to stop running.
+    fn select(&mut self, task: &mut Task, i: usize) -> InterpreterResult<Option<Status>> {
+        let chunk = self.chunk(task);
+        let select = &chunk.selects[i];
+        let len: usize = select.cases.iter().map(|case| if case.recv { 1 } else { 2 }).sum();
+        let mut operands = task.stack[task.stack.len() - len..].iter();
+        let mut ops = Vec::with_capacity(select.cases.len());
+        for case in &select.cases {
+            let chan = as_chan(operands.next().unwrap())?;
+            let value = if case.recv { None } else { operands.next() };
+            ops.push((chan, value));
+        }
+        let ready: Vec<usize> = (0..ops.len())
+            .filter(|&i| match &ops[i] {
+                (chan, None) => chan.can_recv(),
+                (chan, Some(_)) => chan.can_send() || chan.is_closed(),
+            })
+            .collect();
+        if ready.is_empty() {
+            if let Some(default) = select.default {
+                task.stack.truncate(task.stack.len() - len);
+                task.frames.last_mut().unwrap().ip = default;
+                return Ok(None);
+            }
+            let mut channels = Vec::with_capacity(ops.len());
+            for (chan, value) in &ops {
+                if value.is_none() {
+                    chan.set_waiting(true);
+                    self.scheduler.wake(chan, Waiting::Send);
+                    task.waiting.push(chan.clone());
+                    channels.push((chan.id(), Waiting::Recv));
+                } else {
+                    channels.push((chan.id(), Waiting::Send));
+                }
+            }
+            self.block(task, "waiting in a select", channels, select.span);
+            return Ok(Some(Status::Paused));
+        }
+        let chosen = ready[self.scheduler.pick(ready.len())];
+        let case = &select.cases[chosen];
+        let (chan, value) = ops.swap_remove(chosen);
+        let value = value.cloned();
+        task.stack.truncate(task.stack.len() - len);
+        match value {
+            None => {
+                let received = chan.recv();
+                if received.is_some() {
+                    self.scheduler.wake(&chan, Waiting::Send);
+                }
+                task.stack.push(Litteral::Option(received.map(Box::new)));
+            }
+            Some(_) if chan.is_closed() => {
+                return Err(InterpreterError::from("Sending on a closed channel").at(case.span));
+            }
+            Some(value) => {
+                chan.send(value);
+                self.scheduler.wake(&chan, Waiting::Recv);
+            }
+        }
+        task.frames.last_mut().unwrap().ip = case.target;
+        Ok(Some(Status::Paused))
+    }
+}
+
+/// Run a program on the VM, using a seed to decide how its tasks are scheduled,
+/// and failing once it goes over the limits of a meter.
+///
+/// The program is compiled first, and it ends once main returns, like in the interpreter.
+/// Calls don't use the stack of the host here, so any depth is safe. The values the
+/// program puts on the heap go to the one given.
+pub fn run_limited<C: Context>(
+    ctx: C,
+    program: &ir::Program,
+    seed: u64,
+    meter: &mut Meter,
+    heap: &mut Heap,
+) -> InterpreterResult<Litteral> {
+    let program = compiler::compile(program)?;
+    let mut vm = Vm {
+        program: &program,
+        ctx,
+        scheduler: Scheduler::new(seed),
+        meter,
+        heap,
+    };
+    let main = PendingCall {
+        callee: Callee::Named(String::from("main")),
+        args: Vec::new(),
+        span: Span::default(),
+    };
+    vm.scheduler.start(main);
+    let mut tasks: Vec<Option<Task>> = Vec::new();
+    loop {
+        for (id, call) in vm.scheduler.take_started() {
+            debug_assert_eq!(id, tasks.len());
+            tasks.push(Some(Task {
+                id,
+                stack: Vec::new(),
+                frames: Vec::new(),
+                panicking: None,
+                start: Some(call),
+                waiting: Vec::new(),
+            }));
+        }
+        let id = match vm.scheduler.next() {
+            Some(id) => id,
+            None => return Err(vm.scheduler.deadlock()),
+        };
+        let mut task = tasks[id].take().expect("Finished tasks are never scheduled");
+        match vm.run(&mut task) {
+            Status::Paused => tasks[id] = Some(task),
+            Status::Done(res) => {
+                vm.scheduler.finish(id);
+                match res {
+                    Ok(l) if id == 0 => return Ok(l),
+                    Err(e) => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+    }
+}